@@ -1,163 +1,313 @@
-//!
-//! checker.rs
-//! Tools for checking output logs produced by the _T_wo _P_hase _C_ommit
-//! project in run mode. Exports a single public function called check_last_run
-//! that accepts a directory where client, participant, and coordinator log
-//! files are found, and the number of clients, participants. Loads and analyses
-//! log files to check a handful of correctness invariants.
-//!
-extern crate log;
-extern crate stderrlog;
-extern crate clap;
-extern crate ctrlc;
-
-use std::collections::HashMap;
-
-use message;
-use message::MessageType;
-use message::ProtocolMessage;
-use oplog::OpLog;
-
-///
-/// check_participant()
-///
-/// Given a participant name and HashMaps that represents the log files for the
-/// participant and coordinator (already filtered for commit records), check
-/// that the committed and aborted transactions are agreed upon by the two.
-///
-/// <params>
-///     participant: name of participant (label)
-///     ncommit: number of committed transactions from coordinator
-///     nabort: number of aborted transactions from coordinator
-///     ccommitted: map of committed transactions from coordinator
-///     plog: map of participant operations
-///
-fn check_participant(
-    participant: &String,
-    num_commit: usize,
-    num_abort: usize,
-    coord_committed: &HashMap<u32, ProtocolMessage>,
-    participant_log: &HashMap<u32, ProtocolMessage>
-    ) -> bool {
-
-    let mut result = true;
-
-    // Filter the participant log for Global Commits, Local Commits, and Aborted
-    let participant_commit_map: HashMap<u32, message::ProtocolMessage> =
-        participant_log.iter()
-        .filter(|e| (*e.1).mtype == MessageType::CoordinatorCommit)
-        .map(|(k,v)| (k.clone(), v.clone()))
-        .collect();
-    let participant_local_commit_map: HashMap<u32, message::ProtocolMessage> =
-        participant_log.iter()
-        .filter(|e| (*e.1).mtype == MessageType::ParticipantVoteCommit)
-        .map(|(k,v)| (k.clone(), v.clone()))
-        .collect();
-    let participant_abort_map: HashMap<u32, message::ProtocolMessage> =
-        participant_log.iter()
-        .filter(|e| (*e.1).mtype == MessageType::CoordinatorAbort)
-        .map(|(k,v)| (k.clone(), v.clone()))
-        .collect();
-
-    let num_participant_commit = participant_commit_map.len();
-    let num_participant_local_commit = participant_local_commit_map.len();
-    let num_participant_abort = participant_abort_map.len();
-
-    result &= num_participant_commit <= num_commit;
-    result &= num_participant_local_commit >= num_commit;
-    result &= num_participant_abort <= num_abort;
-
-    assert!(num_participant_commit <= num_commit);
-    assert!(num_commit <= num_participant_local_commit);
-    assert!(num_abort >= num_participant_abort);
-
-    for (_, coord_msg) in coord_committed.iter() {
-        let txid = coord_msg.txid.clone();
-        let mut _found_txid = 0;
-        let mut found_local_txid = 0;
-        for (_, participant_msg) in participant_commit_map.iter() {
-            if participant_msg.txid == txid {
-                _found_txid += 1;
-            }
-        }
-
-        for (_, participant_msg) in participant_local_commit_map.iter() {
-            // Handle the case where the participant simply doesn't get the
-            // global commit message from the coordinator. If the coordinator
-            // committed the transaction, the participant has to have voted in
-            // favor. Namely, when _found_txid != found_local_txid.
-            if participant_msg.txid == txid {
-                found_local_txid += 1;
-            }
-        }
-
-        // Exactly one commit of txid per participant
-        result &= found_local_txid == 1;
-        assert!(found_local_txid == 1);
-    }
-    println!("{} OK: Committed: {} == {} (Committed-global), Aborted: {} <= {} (Aborted-global)",
-             participant.clone(),
-             num_participant_commit,
-             num_commit,
-             num_participant_abort,
-             num_abort);
-    result
-}
-
-///
-/// check_last_run()
-///
-/// Accepts a directory where client, participant, and coordinator log files are
-/// found, and the number of clients, participants. Loads and analyses log files
-/// to check a handful of correctness invariants.
-///
-/// <params>
-///     num_clients: Number of clients
-///     num_requests: Number of requests per client
-///     num_participants: Number of participants
-///     log_path: Directory for client, participant, and coordinator logs
-///
-pub fn check_last_run(
-    num_clients: u32,
-    num_requests: u32,
-    num_participants: u32,
-    log_path: &String) {
-
-        info!("Checking 2PC run:  {} requests * {} clients, {} participants",
-              num_requests,
-              num_clients,
-              num_participants);
-
-        let coord_log_path = format!("{}//{}", log_path, "coordinator.log");
-        let coord_log = OpLog::from_file(coord_log_path);
-
-        let lock = coord_log.arc();
-        let coord_map = lock.lock().unwrap();
-
-        // Filter coordinator logs for Commit and Abort
-        let committed: HashMap<u32, message::ProtocolMessage> =
-            coord_map.iter()
-            .filter(|e| (*e.1).mtype == MessageType::CoordinatorCommit)
-            .map(|(k,v)| (k.clone(), v.clone()))
-            .collect();
-        let aborted: HashMap<u32, message::ProtocolMessage> =
-            coord_map.iter()
-            .filter(|e| (*e.1).mtype == MessageType::CoordinatorAbort)
-            .map(|(k,v)| (k.clone(), v.clone()))
-            .collect();
-
-        let num_commit = committed.len();
-        let num_abort = aborted.len();
-
-        // Iterate and check each participant
-        for pid in 0..num_participants {
-            let participant_id_str = format!("participant_{}", pid);
-            let participant_log_path = format!("{}//{}.log", log_path, participant_id_str);
-            let participant_oplog = OpLog::from_file(participant_log_path);
-            let participant_lock = participant_oplog.arc();
-            let participant_log = participant_lock.lock().unwrap();
-            check_participant(&participant_id_str, num_commit, num_abort, &committed, &participant_log);
-        }
-    }
-
-
+//!
+//! checker.rs
+//! Tools for checking output logs produced by the _T_wo _P_hase _C_ommit
+//! project in run mode. Exports a single public function called check_last_run
+//! that accepts a directory where client, participant, and coordinator log
+//! files are found, and the number of clients, participants. Loads and analyses
+//! log files to check a handful of correctness invariants.
+//!
+extern crate log;
+extern crate stderrlog;
+extern crate clap;
+extern crate ctrlc;
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use message;
+use message::MessageType;
+use message::ProtocolMessage;
+use oplog::OpLog;
+use partition::PartitionMap;
+use tpcoptions::CommitProtocol;
+
+///
+/// check_participant()
+///
+/// Given a participant name and HashMaps that represents the log files for the
+/// participant and coordinator (already filtered for commit records), check
+/// that the committed and aborted transactions are agreed upon by the two.
+/// A transaction the participant voted ReadOnly on has no decision record
+/// there; if the coordinator committed it, the vote stands in for its commit
+/// vote. A one-phase transaction
+/// has no vote at all, only the decision the participant took.
+/// For 3PC runs, also check that the participant only pre-committed what it
+/// voted to commit and only committed what it pre-committed, unless it
+/// restarted after voting: the PreCommit may have gone out while it was
+/// down, and then only a commit of the coordinator's may have reached it.
+///
+/// <params>
+///     participant: name of participant (label)
+///     ncommit: number of committed transactions from coordinator
+///     nabort: number of aborted transactions from coordinator
+///     ccommitted: map of committed transactions from coordinator
+///     caborted: map of aborted transactions from coordinator
+///     plog: map of participant operations
+///     one_phase: transactions the coordinator left to a single participant
+///     protocol: commit protocol the run used
+///
+#[allow(clippy::too_many_arguments)]
+fn check_participant(
+    participant: &str,
+    num_commit: usize,
+    num_abort: usize,
+    coord_committed: &HashMap<u32, ProtocolMessage>,
+    coord_aborted: &HashMap<u32, ProtocolMessage>,
+    participant_log: &HashMap<u32, ProtocolMessage>,
+    one_phase: &HashSet<String>,
+    protocol: CommitProtocol
+    ) -> bool {
+
+    let mut result = true;
+
+    // Filter the participant log for Global Commits, Local Commits, and Aborted
+    let participant_commit_map: HashMap<u32, message::ProtocolMessage> =
+        participant_log.iter()
+        .filter(|e| e.1.mtype == MessageType::CoordinatorCommit)
+        .map(|(k,v)| (*k, v.clone()))
+        .collect();
+    let participant_local_commit_map: HashMap<u32, message::ProtocolMessage> =
+        participant_log.iter()
+        .filter(|e| e.1.mtype == MessageType::ParticipantVoteCommit)
+        .map(|(k,v)| (*k, v.clone()))
+        .collect();
+    let participant_read_only_map: HashMap<u32, message::ProtocolMessage> =
+        participant_log.iter()
+        .filter(|e| e.1.mtype == MessageType::ParticipantVoteReadOnly)
+        .map(|(k,v)| (*k, v.clone()))
+        .collect();
+    let participant_read_only_commit_map: HashMap<u32, message::ProtocolMessage> =
+        participant_read_only_map.iter()
+        .filter(|e| coord_committed.values().any(|m| m.txid == e.1.txid))
+        .map(|(k,v)| (*k, v.clone()))
+        .collect();
+    let participant_one_phase_commit_map: HashMap<u32, message::ProtocolMessage> =
+        participant_commit_map.iter()
+        .filter(|e| one_phase.contains(&e.1.txid))
+        .map(|(k,v)| (*k, v.clone()))
+        .collect();
+    let participant_abort_map: HashMap<u32, message::ProtocolMessage> =
+        participant_log.iter()
+        .filter(|e| e.1.mtype == MessageType::CoordinatorAbort)
+        .map(|(k,v)| (*k, v.clone()))
+        .collect();
+
+    let num_participant_commit = participant_commit_map.len();
+    let num_participant_local_commit = participant_local_commit_map.len() + participant_read_only_commit_map.len()
+        + participant_one_phase_commit_map.len();
+    let num_participant_abort = participant_abort_map.len();
+
+    result &= num_participant_commit <= num_commit;
+    result &= num_participant_local_commit >= num_commit;
+    result &= num_participant_abort <= num_abort;
+
+    assert!(num_participant_commit <= num_commit);
+    assert!(num_commit <= num_participant_local_commit);
+    assert!(num_abort >= num_participant_abort);
+
+    for (_, coord_msg) in coord_committed.iter() {
+        let txid = coord_msg.txid.clone();
+        let mut _found_txid = 0;
+        let mut found_local_txid = 0;
+        for (_, participant_msg) in participant_commit_map.iter() {
+            if participant_msg.txid == txid {
+                _found_txid += 1;
+            }
+        }
+
+        for (_, participant_msg) in participant_local_commit_map.iter()
+            .chain(participant_read_only_commit_map.iter())
+            .chain(participant_one_phase_commit_map.iter()) {
+            // Handle the case where the participant simply doesn't get the
+            // global commit message from the coordinator. If the coordinator
+            // committed the transaction, the participant has to have voted in
+            // favor. Namely, when _found_txid != found_local_txid.
+            if participant_msg.txid == txid {
+                found_local_txid += 1;
+            }
+        }
+
+        // Exactly one commit of txid per participant
+        result &= found_local_txid == 1;
+        assert!(found_local_txid == 1);
+    }
+
+    // A ReadOnly voter sits out phase two
+    for (_, participant_msg) in participant_read_only_map.iter() {
+        let decided = participant_commit_map.values().chain(participant_abort_map.values())
+            .any(|m| m.txid == participant_msg.txid);
+        result &= !decided;
+        assert!(!decided, "{} logged a decision for {} after voting ReadOnly", participant, participant_msg.txid);
+    }
+
+    // The participant never reached the opposite outcome of the coordinator
+    for (_, participant_msg) in participant_commit_map.iter() {
+        let aborted = coord_aborted.values().any(|m| m.txid == participant_msg.txid);
+        result &= !aborted;
+        assert!(!aborted, "{} committed {} which the coordinator aborted", participant, participant_msg.txid);
+    }
+    for (_, participant_msg) in participant_abort_map.iter() {
+        let committed = coord_committed.values().any(|m| m.txid == participant_msg.txid);
+        result &= !committed;
+        assert!(!committed, "{} aborted {} which the coordinator committed", participant, participant_msg.txid);
+    }
+
+    if protocol == CommitProtocol::ThreePhase {
+        let participant_precommit_map: HashMap<u32, message::ProtocolMessage> =
+            participant_log.iter()
+            .filter(|e| e.1.mtype == MessageType::CoordinatorPreCommit)
+            .map(|(k,v)| (*k, v.clone()))
+            .collect();
+
+        // Pre-committed only what we voted to commit
+        for (_, participant_msg) in participant_precommit_map.iter() {
+            let voted = participant_local_commit_map.values().any(|m| m.txid == participant_msg.txid);
+            result &= voted;
+            assert!(voted, "{} pre-committed {} without voting to commit", participant, participant_msg.txid);
+        }
+
+        // Restarts of the participant, by log sequence number
+        let recovered: Vec<u32> = participant_log.iter()
+            .filter(|e| e.1.mtype == MessageType::ParticipantRecovered)
+            .map(|(k, _)| *k)
+            .collect();
+        let recovered_in_doubt = |txid: &String, committed_at: u32| {
+            participant_local_commit_map.iter()
+                .filter(|e| e.1.txid == *txid)
+                .any(|(voted_at, _)| recovered.iter().any(|r| voted_at < r && *r < committed_at))
+        };
+
+        // Committed only what we pre-committed (or decided on our own)
+        for (seqno, participant_msg) in participant_commit_map.iter().filter(|e| !one_phase.contains(&e.1.txid)) {
+            let precommitted = participant_precommit_map.values().any(|m| m.txid == participant_msg.txid)
+                || recovered_in_doubt(&participant_msg.txid, *seqno);
+            result &= precommitted;
+            assert!(precommitted, "{} committed {} without a PreCommit", participant, participant_msg.txid);
+        }
+
+        // A transaction we restarted in doubt about may have been pre-committed
+        // while we were down: we can only have learned its commit from the
+        // coordinator (or a peer that did), so the coordinator committed it
+        for (seqno, participant_msg) in participant_commit_map.iter().filter(|e| !one_phase.contains(&e.1.txid)) {
+            let precommitted = participant_precommit_map.values().any(|m| m.txid == participant_msg.txid);
+            if precommitted || !recovered_in_doubt(&participant_msg.txid, *seqno) {
+                continue;
+            }
+            let committed = coord_committed.values().any(|m| m.txid == participant_msg.txid);
+            result &= committed;
+            assert!(committed, "{} committed {} after restarting without a PreCommit, but the coordinator did not commit it",
+                    participant, participant_msg.txid);
+        }
+    }
+    println!("{} OK: Committed: {} <= {} (Committed-global) <= {} (Local-commit, {} ReadOnly), Aborted: {} <= {} (Aborted-global)",
+             participant,
+             num_participant_commit,
+             num_commit,
+             num_participant_local_commit,
+             participant_read_only_commit_map.len(),
+             num_participant_abort,
+             num_abort);
+    result
+}
+
+///
+/// check_last_run()
+///
+/// Accepts a directory where client, participant, and coordinator log files are
+/// found, and the number of clients, participants. Loads and analyses log files
+/// to check a handful of correctness invariants. Each participant is only
+/// checked against the transactions whose proposal touched keys it owns.
+///
+/// <params>
+///     num_clients: Number of clients
+///     num_requests: Number of requests per client
+///     num_participants: Number of participants
+///     log_path: Directory for client, participant, and coordinator logs
+///     protocol: Commit protocol the run used
+///     partition_map: Key-to-participant map the run used
+///
+pub fn check_last_run(
+    num_clients: u32,
+    num_requests: u32,
+    num_participants: u32,
+    log_path: &String,
+    protocol: CommitProtocol,
+    partition_map: &PartitionMap) {
+
+        info!("Checking {} run:  {} requests * {} clients, {} participants",
+              protocol.as_str(),
+              num_requests,
+              num_clients,
+              num_participants);
+
+        let coord_log_path = format!("{}//{}", log_path, "coordinator.log");
+        let coord_log = OpLog::load(coord_log_path);
+
+        let lock = coord_log.arc();
+        let coord_map = lock.lock().unwrap();
+
+        // Filter coordinator logs for Commit and Abort
+        let committed: HashMap<u32, message::ProtocolMessage> =
+            coord_map.iter()
+            .filter(|e| e.1.mtype == MessageType::CoordinatorCommit)
+            .map(|(k,v)| (*k, v.clone()))
+            .collect();
+        let aborted: HashMap<u32, message::ProtocolMessage> =
+            coord_map.iter()
+            .filter(|e| e.1.mtype == MessageType::CoordinatorAbort)
+            .map(|(k,v)| (*k, v.clone()))
+            .collect();
+
+
+        // Transactions left to a single participant to decide
+        let one_phase: HashSet<String> =
+            coord_map.values()
+            .filter(|m| m.mtype == MessageType::CoordinatorOnePhaseCommit)
+            .map(|m| m.txid.clone())
+            .collect();
+
+        // Under 3PC the coordinator only commits what it pre-committed
+        if protocol == CommitProtocol::ThreePhase {
+            for (_, coord_msg) in committed.iter().filter(|e| !one_phase.contains(&e.1.txid)) {
+                let precommitted = coord_map.values()
+                    .any(|m| m.mtype == MessageType::CoordinatorPreCommit && m.txid == coord_msg.txid);
+                assert!(precommitted, "coordinator committed {} without a PreCommit", coord_msg.txid);
+            }
+        }
+
+        // A transaction is only ended once it was decided
+        for (_, coord_msg) in coord_map.iter().filter(|e| e.1.mtype == MessageType::CoordinatorEnd) {
+            let decided = committed.values().chain(aborted.values()).any(|m| m.txid == coord_msg.txid);
+            assert!(decided, "coordinator ended {} without deciding it", coord_msg.txid);
+        }
+
+        // Participants each proposal went to
+        let proposed_to: HashMap<String, Vec<u32>> =
+            coord_map.values()
+            .filter(|m| matches!(m.mtype, MessageType::CoordinatorPropose | MessageType::CoordinatorOnePhaseCommit))
+            .filter(|m| !m.ops.is_empty())
+            .map(|m| (m.txid.clone(), partition_map.split(&m.ops).into_keys().collect()))
+            .collect();
+        let involves = |m: &ProtocolMessage, pid: u32| {
+            proposed_to.get(&m.txid).is_none_or(|pids| pids.contains(&pid))
+        };
+
+        // Iterate and check each participant
+        for pid in 0..num_participants {
+            let committed: HashMap<u32, message::ProtocolMessage> =
+                committed.iter().filter(|e| involves(e.1, pid)).map(|(k,v)| (*k, v.clone())).collect();
+            let aborted: HashMap<u32, message::ProtocolMessage> =
+                aborted.iter().filter(|e| involves(e.1, pid)).map(|(k,v)| (*k, v.clone())).collect();
+            let num_commit = committed.len();
+            let num_abort = aborted.len();
+
+            let participant_id_str = format!("participant_{}", pid);
+            let participant_log_path = format!("{}//{}.log", log_path, participant_id_str);
+            let participant_oplog = OpLog::load(participant_log_path);
+            let participant_lock = participant_oplog.arc();
+            let participant_log = participant_lock.lock().unwrap();
+            check_participant(&participant_id_str, num_commit, num_abort, &committed, &aborted, &participant_log, &one_phase, protocol);
+        }
+    }
+
+
@@ -1,202 +1,324 @@
-//!
-//! client.rs
-//! Implementation of 2PC client
-//!
-extern crate ipc_channel;
-extern crate log;
-extern crate stderrlog;
-
-use std::thread;
-use std::time::Duration;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-
-use client::ipc_channel::ipc::IpcReceiver as Receiver;
-use client::ipc_channel::ipc::TryRecvError;
-use client::ipc_channel::ipc::IpcSender as Sender;
-
-use message;
-use message::MessageType;
-
-// Client state and primitives for communicating with the coordinator
-pub struct Client {
-    pub id_str: String,
-    pub running: Arc<AtomicBool>,
-    pub num_requests: u32,
-    tx: Sender<message::ProtocolMessage>,
-    rx: Receiver<message::ProtocolMessage>,
-    successful_ops: u64,
-    failed_ops: u64,
-    unknown_ops: u64,
-}
-
-///
-/// Client Implementation
-/// Required:
-/// 1. new -- constructor
-/// 2. pub fn report_status -- Reports number of committed/aborted/unknown
-/// 3. pub fn protocol(&mut self, n_requests: i32) -- Implements client side protocol
-///
-impl Client {
-
-    ///
-    /// new()
-    ///
-    /// Constructs and returns a new client, ready to run the 2PC protocol
-    /// with the coordinator.
-    ///
-    /// HINT: You may want to pass some channels or other communication
-    ///       objects that enable coordinator->client and client->coordinator
-    ///       messaging to this constructor.
-    /// HINT: You may want to pass some global flags that indicate whether
-    ///       the protocol is still running to this constructor
-    ///
-    pub fn new(id_str: String,
-               running: Arc<AtomicBool>,
-               tx: Sender<message::ProtocolMessage>,
-               rx: Receiver<message::ProtocolMessage>) -> Client {
-        Client {
-            id_str: id_str,
-            running: running,
-            num_requests: 0,
-            tx: tx,
-            rx: rx,
-            successful_ops: 0,
-            failed_ops: 0,
-            unknown_ops: 0,
-        }
-    }
-
-    ///
-    /// wait_for_exit_signal(&mut self)
-    /// Wait until the running flag is set by the CTRL-C handler
-    ///
-    pub fn wait_for_exit_signal(&mut self) {
-        trace!("{}::Waiting for exit signal", self.id_str.clone());
-
-        loop {
-            match self.rx.try_recv() {
-                Ok(msg) => {
-                    if msg.mtype == MessageType::CoordinatorExit {
-                        trace!("{}::Received exit signal", self.id_str);
-                        break;
-                    }
-                },
-                Err(TryRecvError::Empty) => {
-                    if !self.running.load(Ordering::SeqCst) {
-                        break;
-                    }
-                    thread::sleep(Duration::from_millis(100));
-                },
-                Err(TryRecvError::IpcError(_)) => {
-                    break;
-                }
-            }
-        }
-
-        trace!("{}::Exiting", self.id_str.clone());
-    }
-
-    ///
-    /// send_next_operation(&mut self)
-    /// Send the next operation to the coordinator
-    ///
-    pub fn send_next_operation(&mut self) {
-
-        // Create a new request with a unique TXID.
-        self.num_requests = self.num_requests + 1;
-        let txid = format!("{}_op_{}", self.id_str.clone(), self.num_requests);
-        let pm = message::ProtocolMessage::generate(message::MessageType::ClientRequest,
-                                                    txid.clone(),
-                                                    self.id_str.clone(),
-                                                    self.num_requests);
-        info!("{}::Sending operation #{}", self.id_str.clone(), self.num_requests);
-
-        self.tx.send(pm).unwrap_or(());
-
-        trace!("{}::Sent operation #{}", self.id_str.clone(), self.num_requests);
-    }
-
-    ///
-    /// recv_result()
-    /// Wait for the coordinator to respond with the result for the
-    /// last issued request. Note that we assume the coordinator does
-    /// not fail in this simulation
-    ///
-    pub fn recv_result(&mut self) {
-
-        info!("{}::Receiving Coordinator Result", self.id_str.clone());
-
-        let timeout = Duration::from_millis(2000);
-        let start_time = std::time::Instant::now();
-
-        loop {
-            if start_time.elapsed() > timeout {
-                trace!("{}::Timeout waiting for result", self.id_str);
-                self.unknown_ops += 1;
-                break;
-            }
-
-            if !self.running.load(Ordering::SeqCst) {
-                self.unknown_ops += 1;
-                break;
-            }
-
-            match self.rx.try_recv() {
-                Ok(msg) => {
-                    if msg.mtype == MessageType::ClientResultCommit {
-                        info!("{}::Received COMMIT result", self.id_str);
-                        self.successful_ops += 1;
-                        break;
-                    } else if msg.mtype == MessageType::ClientResultAbort {
-                        info!("{}::Received ABORT result", self.id_str);
-                        self.failed_ops += 1;
-                        break;
-                    } else if msg.mtype == MessageType::CoordinatorExit {
-                        trace!("{}::Received exit signal while waiting for result", self.id_str);
-                        self.unknown_ops += 1;
-                        break;
-                    }
-                },
-                Err(TryRecvError::Empty) => {
-                    thread::sleep(Duration::from_millis(1));
-                },
-                Err(TryRecvError::IpcError(_)) => {
-                    self.unknown_ops += 1;
-                    break;
-                }
-            }
-        }
-    }
-
-    ///
-    /// report_status()
-    /// Report the abort/commit/unknown status (aggregate) of all transaction
-    /// requests made by this client before exiting.
-    ///
-    pub fn report_status(&mut self) {
-        println!("{}:\tC:{}\tA:{}\tU:{}", self.id_str, self.successful_ops, self.failed_ops, self.unknown_ops);
-    }
-
-    ///
-    /// protocol()
-    /// Implements the client side of the 2PC protocol
-    /// HINT: if the simulation ends early, don't keep issuing requests!
-    /// HINT: if you've issued all your requests, wait for some kind of
-    ///       exit signal before returning from the protocol method!
-    ///
-    pub fn protocol(&mut self, n_requests: u32) {
-
-        for _i in 0..n_requests {
-            if !self.running.load(Ordering::SeqCst) {
-                break;
-            }
-
-            self.send_next_operation();
-            self.recv_result();
-        }
-
-        self.wait_for_exit_signal();
-        self.report_status();
-    }
-}
+//!
+//! client.rs
+//! Implementation of 2PC client
+//!
+extern crate log;
+extern crate rand;
+extern crate stderrlog;
+
+use std::time::Duration;
+use std::time::Instant;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use client::rand::prelude::*;
+
+use clock;
+use fixedhash::HashMap;
+use message;
+use message::MessageType;
+use message::Operation;
+use transport::Transport;
+use transport::TryRecvError;
+
+/// How long a client waits for the result of a request
+const RESULT_TIMEOUT: Duration = Duration::from_millis(2000);
+
+// Client state and primitives for communicating with the coordinator
+pub struct Client<T: Transport> {
+    pub id_str: String,
+    pub running: Arc<AtomicBool>,
+    pub num_requests: u32,
+    num_keys: u32,
+    pending: Vec<Operation>,
+    written: HashMap<String, String>,
+    transport: T,
+    sent_at: Option<Instant>,
+    exit_received: bool,    // The coordinator shut down: no more requests
+    successful_ops: u64,
+    failed_ops: u64,
+    unknown_ops: u64,
+}
+
+///
+/// Client Implementation
+/// Required:
+/// 1. new -- constructor
+/// 2. pub fn report_status -- Reports number of committed/aborted/unknown
+/// 3. pub fn protocol(&mut self, n_requests: i32) -- Implements client side protocol
+///
+impl<T: Transport> Client<T> {
+
+    ///
+    /// new()
+    ///
+    /// Constructs and returns a new client, ready to run the 2PC protocol
+    /// with the coordinator.
+    ///
+    /// HINT: You may want to pass some channels or other communication
+    ///       objects that enable coordinator->client and client->coordinator
+    ///       messaging to this constructor.
+    /// HINT: You may want to pass some global flags that indicate whether
+    ///       the protocol is still running to this constructor
+    ///
+    pub fn new(id_str: String,
+               num_keys: u32,
+               running: Arc<AtomicBool>,
+               transport: T) -> Client<T> {
+        Client {
+            id_str,
+            running,
+            num_requests: 0,
+            num_keys,
+            pending: Vec::new(),
+            written: HashMap::default(),
+            transport,
+            sent_at: None,
+            exit_received: false,
+            successful_ops: 0,
+            failed_ops: 0,
+            unknown_ops: 0,
+        }
+    }
+
+    ///
+    /// wait_for_exit_signal(&mut self)
+    /// Wait until the running flag is set by the CTRL-C handler
+    ///
+    pub fn wait_for_exit_signal(&mut self) {
+        trace!("{}::Waiting for exit signal", self.id_str.clone());
+
+        loop {
+            match self.transport.recv_timeout(Duration::from_millis(100)) {
+                Ok(msg) => {
+                    if msg.mtype == MessageType::CoordinatorExit {
+                        trace!("{}::Received exit signal", self.id_str);
+                        break;
+                    }
+                },
+                Err(TryRecvError::Empty) => {
+                    if !self.running.load(Ordering::SeqCst) {
+                        break;
+                    }
+                },
+                Err(TryRecvError::Disconnected) => {
+                    break;
+                }
+            }
+        }
+
+        trace!("{}::Exiting", self.id_str.clone());
+    }
+
+    ///
+    /// next_ops(&self, txid)
+    /// Picks the payload of the next transaction: a read of one random key,
+    /// then a put, delete or compare-and-swap of another. The swap expects
+    /// the value this client last committed for the key, so it fails if
+    /// another client wrote it since. One in four transactions only reads
+    /// two keys instead.
+    ///
+    fn next_ops(&self, txid: &str) -> Vec<Operation> {
+        let (read, write, read_only, kind) = clock::with_rng(|rng| {
+            (rng.gen_range(0, self.num_keys), rng.gen_range(0, self.num_keys), rng.gen_range(0, 4) == 0, rng.gen_range(0, 4))
+        });
+        let read_key = format!("key_{}", read);
+        let write_key = format!("key_{}", write);
+        if read_only {
+            return vec![Operation::Get(read_key), Operation::Get(write_key)];
+        }
+        let write = match kind {
+            0 | 1 => Operation::Put(write_key, txid.to_string()),
+            2 => Operation::Delete(write_key),
+            _ => {
+                let expected = self.written.get(&write_key).cloned();
+                Operation::CompareAndSwap(write_key, expected, txid.to_string())
+            },
+        };
+        vec![Operation::Get(read_key), write]
+    }
+
+    ///
+    /// record_commit(&mut self)
+    /// Remembers the values the last request wrote now that it committed
+    ///
+    fn record_commit(&mut self) {
+        for op in self.pending.drain(..) {
+            match op {
+                Operation::Put(key, value) | Operation::CompareAndSwap(key, _, value) => {
+                    self.written.insert(key, value);
+                },
+                Operation::Delete(key) => {
+                    self.written.remove(&key);
+                },
+                Operation::Get(_) => {},
+            }
+        }
+    }
+
+    ///
+    /// send_next_operation(&mut self)
+    /// Send the next operation to the coordinator
+    ///
+    pub fn send_next_operation(&mut self) {
+
+        // Create a new request with a unique TXID.
+        self.num_requests += 1;
+        let txid = format!("{}_op_{}", self.id_str.clone(), self.num_requests);
+        let mut pm = message::ProtocolMessage::generate(message::MessageType::ClientRequest,
+                                                        txid.clone(),
+                                                        self.id_str.clone(),
+                                                        self.num_requests);
+        pm.ops = self.next_ops(&txid);
+        self.pending = pm.ops.clone();
+        info!("{}::Sending operation #{}", self.id_str.clone(), self.num_requests);
+
+        self.transport.send(pm).unwrap_or(());
+
+        trace!("{}::Sent operation #{}", self.id_str.clone(), self.num_requests);
+    }
+
+    ///
+    /// recv_result()
+    /// Wait for the coordinator to respond with the result for the
+    /// last issued request. Note that we assume the coordinator does
+    /// not fail in this simulation
+    ///
+    pub fn recv_result(&mut self) {
+
+        info!("{}::Receiving Coordinator Result", self.id_str.clone());
+
+        let start_time = clock::now();
+
+        loop {
+            if clock::elapsed(start_time) > RESULT_TIMEOUT {
+                trace!("{}::Timeout waiting for result", self.id_str);
+                self.unknown_ops += 1;
+                break;
+            }
+
+            if !self.running.load(Ordering::SeqCst) {
+                self.unknown_ops += 1;
+                break;
+            }
+
+            match self.transport.recv_timeout(Duration::from_millis(1)) {
+                Ok(msg) => {
+                    if self.handle_result(&msg) {
+                        break;
+                    }
+                },
+                Err(TryRecvError::Empty) => {},
+                Err(TryRecvError::Disconnected) => {
+                    self.unknown_ops += 1;
+                    break;
+                }
+            }
+        }
+    }
+
+    ///
+    /// handle_result(&mut self, msg)
+    /// Accounts for the result of the last issued request if msg carries
+    /// one. Results of earlier requests (duplicates, or ones that came in
+    /// after we gave up on them) are ignored. Returns true if the request
+    /// is finished.
+    ///
+    fn handle_result(&mut self, msg: &message::ProtocolMessage) -> bool {
+        let result = matches!(msg.mtype, MessageType::ClientResultCommit | MessageType::ClientResultAbort
+                                         | MessageType::ClientResultUnknown);
+        if result && msg.txid != format!("{}_op_{}", self.id_str, self.num_requests) {
+            trace!("{}::Ignoring stale result for txid: {}", self.id_str, msg.txid);
+            false
+        } else if msg.mtype == MessageType::ClientResultCommit {
+            info!("{}::Received COMMIT result", self.id_str);
+            self.record_commit();
+            self.successful_ops += 1;
+            true
+        } else if msg.mtype == MessageType::ClientResultAbort {
+            info!("{}::Received ABORT result", self.id_str);
+            self.failed_ops += 1;
+            true
+        } else if msg.mtype == MessageType::ClientResultUnknown {
+            info!("{}::Received UNKNOWN result", self.id_str);
+            self.unknown_ops += 1;
+            true
+        } else if msg.mtype == MessageType::CoordinatorExit {
+            trace!("{}::Received exit signal while waiting for result", self.id_str);
+            self.exit_received = true;
+            self.unknown_ops += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    ///
+    /// step(&mut self, n_requests)
+    /// Never-blocking version of the client protocol, for the simulation:
+    /// issues the next request once the last one is finished, and checks
+    /// for its result otherwise. Returns true once n_requests are finished.
+    ///
+    pub fn step(&mut self, n_requests: u32) -> bool {
+        let sent_at = match self.sent_at {
+            Some(sent_at) => sent_at,
+            None if self.num_requests < n_requests && !self.exit_received => {
+                self.send_next_operation();
+                self.sent_at = Some(clock::now());
+                return false;
+            },
+            None => return true,
+        };
+
+        let finished = match self.transport.try_recv() {
+            Ok(msg) => self.handle_result(&msg),
+            Err(TryRecvError::Empty) if clock::elapsed(sent_at) > RESULT_TIMEOUT => {
+                trace!("{}::Timeout waiting for result", self.id_str);
+                self.unknown_ops += 1;
+                true
+            },
+            Err(TryRecvError::Empty) => false,
+            Err(TryRecvError::Disconnected) => {
+                self.unknown_ops += 1;
+                true
+            },
+        };
+        if finished {
+            self.sent_at = None;
+        }
+        false
+    }
+
+    ///
+    /// report_status()
+    /// Report the abort/commit/unknown status (aggregate) of all transaction
+    /// requests made by this client before exiting.
+    ///
+    pub fn report_status(&mut self) {
+        println!("{}:\tC:{}\tA:{}\tU:{}", self.id_str, self.successful_ops, self.failed_ops, self.unknown_ops);
+    }
+
+    ///
+    /// protocol()
+    /// Implements the client side of the 2PC protocol
+    /// HINT: if the simulation ends early, don't keep issuing requests!
+    /// HINT: if you've issued all your requests, wait for some kind of
+    ///       exit signal before returning from the protocol method!
+    ///
+    pub fn protocol(&mut self, n_requests: u32) {
+
+        for _i in 0..n_requests {
+            if !self.running.load(Ordering::SeqCst) || self.exit_received {
+                break;
+            }
+
+            self.send_next_operation();
+            self.recv_result();
+        }
+
+        if !self.exit_received {
+            self.wait_for_exit_signal();
+        }
+        self.report_status();
+    }
+}
@@ -0,0 +1,111 @@
+//!
+//! clock.rs
+//! Where the roles get the time and their random numbers from. By default
+//! that is the wall clock and the thread's RNG. A simulation switches the
+//! calling thread to a virtual clock it advances itself and an RNG seeded
+//! from its seed, so a run can be replayed exactly.
+//!
+extern crate rand;
+
+use std::cell::RefCell;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use clock::rand::distributions::Distribution;
+use clock::rand::distributions::Standard;
+use clock::rand::prelude::*;
+use clock::rand::rngs::StdRng;
+
+///
+/// Virtual
+/// Virtual time (an offset from when the simulation started) and the
+/// seeded RNG of a simulating thread
+///
+struct Virtual {
+    start: Instant,
+    offset: Duration,
+    rng: StdRng,
+}
+
+thread_local! {
+    static VIRTUAL: RefCell<Option<Virtual>> = const { RefCell::new(None) };
+}
+
+///
+/// simulate(seed)
+/// Switches this thread to virtual time, starting now, and to an RNG seeded
+/// with seed
+///
+pub fn simulate(seed: u64) {
+    VIRTUAL.with(|v| {
+        *v.borrow_mut() = Some(Virtual {
+            start: Instant::now(),
+            offset: Duration::from_millis(0),
+            rng: StdRng::seed_from_u64(seed),
+        });
+    });
+}
+
+///
+/// now()
+/// The current time
+///
+pub fn now() -> Instant {
+    VIRTUAL.with(|v| match &*v.borrow() {
+        Some(virt) => virt.start + virt.offset,
+        None => Instant::now(),
+    })
+}
+
+///
+/// elapsed(since)
+/// Time passed since the instant since
+///
+pub fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+///
+/// advance(by)
+/// Moves virtual time forward; does nothing on the wall clock
+///
+pub fn advance(by: Duration) {
+    VIRTUAL.with(|v| {
+        if let Some(virt) = &mut *v.borrow_mut() {
+            virt.offset += by;
+        }
+    });
+}
+
+///
+/// sleep(duration)
+/// Sleeps on the wall clock, or just lets virtual time pass
+///
+pub fn sleep(duration: Duration) {
+    let simulating = VIRTUAL.with(|v| v.borrow().is_some());
+    if simulating {
+        advance(duration);
+    } else {
+        thread::sleep(duration);
+    }
+}
+
+///
+/// with_rng(f)
+/// Calls f with the RNG of this thread
+///
+pub fn with_rng<R, F: FnOnce(&mut dyn RngCore) -> R>(f: F) -> R {
+    VIRTUAL.with(|v| match &mut *v.borrow_mut() {
+        Some(virt) => f(&mut virt.rng),
+        None => f(&mut thread_rng()),
+    })
+}
+
+///
+/// random()
+/// A random value, like rand::random()
+///
+pub fn random<T>() -> T where Standard: Distribution<T> {
+    with_rng(|rng| rng.gen())
+}
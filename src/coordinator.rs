@@ -1,293 +1,1300 @@
-//!
-//! coordinator.rs
-//! Implementation of 2PC coordinator
-//!
-extern crate log;
-extern crate stderrlog;
-extern crate rand;
-extern crate ipc_channel;
-
-use std::collections::HashMap;
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread;
-use std::time::Duration;
-
-use coordinator::ipc_channel::ipc::IpcSender as Sender;
-use coordinator::ipc_channel::ipc::IpcReceiver as Receiver;
-use coordinator::ipc_channel::ipc::TryRecvError;
-
-use message::MessageType;
-use message::ProtocolMessage;
-use oplog;
-
-/// CoordinatorState
-/// States for 2PC state machine
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum CoordinatorState {
-    Quiescent,
-    ReceivedRequest,
-    ProposalSent,
-    ReceivedVotesAbort,
-    ReceivedVotesCommit,
-    SentGlobalDecision
-}
-
-/// Coordinator
-/// Struct maintaining state for coordinator
-pub struct Coordinator {
-    state: CoordinatorState,
-    running: Arc<AtomicBool>,
-    log: oplog::OpLog,
-    participant_map: HashMap<String, (Sender<ProtocolMessage>, Receiver<ProtocolMessage>)>,
-    client_map: HashMap<String, (Sender<ProtocolMessage>, Receiver<ProtocolMessage>)>,
-    successful_ops: u64,
-    failed_ops: u64,
-    unknown_ops: u64,
-}
-
-///
-/// Coordinator
-/// Implementation of coordinator functionality
-/// Required:
-/// 1. new -- Constructor
-/// 2. protocol -- Implementation of coordinator side of protocol
-/// 3. report_status -- Report of aggregate commit/abort/unknown stats on exit.
-/// 4. participant_join -- What to do when a participant joins
-/// 5. client_join -- What to do when a client joins
-///
-impl Coordinator {
-
-    ///
-    /// new()
-    /// Initialize a new coordinator
-    ///
-    /// <params>
-    ///     log_path: directory for log files --> create a new log there.
-    ///     r: atomic bool --> still running?
-    ///
-    pub fn new(
-        log_path: String,
-        r: &Arc<AtomicBool>) -> Coordinator {
-
-        Coordinator {
-            state: CoordinatorState::Quiescent,
-            log: oplog::OpLog::new(log_path),
-            running: r.clone(),
-            participant_map: HashMap::new(),
-            client_map: HashMap::new(),
-            successful_ops: 0,
-            failed_ops: 0,
-            unknown_ops: 0,
-        }
-    }
-
-    ///
-    /// participant_join()
-    /// Adds a new participant for the coordinator to keep track of
-    ///
-    /// HINT: Keep track of any channels involved!
-    /// HINT: You may need to change the signature of this function
-    ///
-    pub fn participant_join(&mut self, name: &String, 
-                           sender: Sender<ProtocolMessage>, 
-                           receiver: Receiver<ProtocolMessage>) {
-        assert!(self.state == CoordinatorState::Quiescent);
-
-        self.participant_map.insert(name.clone(), (sender, receiver));
-    }
-
-    ///
-    /// client_join()
-    /// Adds a new client for the coordinator to keep track of
-    ///
-    /// HINT: Keep track of any channels involved!
-    /// HINT: You may need to change the signature of this function
-    ///
-    pub fn client_join(&mut self, name: &String, 
-                      sender: Sender<ProtocolMessage>, 
-                      receiver: Receiver<ProtocolMessage>) {
-        assert!(self.state == CoordinatorState::Quiescent);
-
-        self.client_map.insert(name.clone(), (sender, receiver));
-    }
-
-    ///
-    /// report_status()
-    /// Report the abort/commit/unknown status (aggregate) of all transaction
-    /// requests made by this coordinator before exiting.
-    ///
-    pub fn report_status(&mut self) {
-        println!("coordinator:\tC:{}\tA:{}\tU:{}", self.successful_ops, self.failed_ops, self.unknown_ops);
-    }
-
-    ///
-    /// protocol()
-    /// Implements the coordinator side of the 2PC protocol
-    /// HINT: If the simulation ends early, don't keep handling requests!
-    /// HINT: Wait for some kind of exit signal before returning from the protocol!
-    ///
-    pub fn protocol(&mut self) {
-        
-        loop {
-            if !self.running.load(Ordering::SeqCst) {
-                break;
-            }
-
-            // Try to receive a request from any client
-            let mut request_received = false;
-            let mut request: Option<ProtocolMessage> = None;
-            let mut client_name: Option<String> = None;
-
-            for (name, (_tx, rx)) in self.client_map.iter() {
-                match rx.try_recv() {
-                    Ok(msg) => {
-                        if msg.mtype == MessageType::ClientRequest {
-                            trace!("Coordinator received request from {}", name);
-                            request = Some(msg);
-                            client_name = Some(name.clone());
-                            request_received = true;
-                            break;
-                        }
-                    },
-                    Err(TryRecvError::Empty) => continue,
-                    Err(TryRecvError::IpcError(_)) => continue,
-                }
-            }
-
-            if !request_received {
-                thread::sleep(Duration::from_millis(1));
-                continue;
-            }
-
-            let req = request.unwrap();
-            let client_id = client_name.unwrap();
-            
-            // Phase 1: Send proposal to all participants
-            self.state = CoordinatorState::ProposalSent;
-            info!("Coordinator sending proposal for txid: {}", req.txid);
-            
-            let propose_msg = ProtocolMessage::generate(
-                MessageType::CoordinatorPropose,
-                req.txid.clone(),
-                "coordinator".to_string(),
-                req.opid,
-            );
-
-            // Send proposal to all participants
-            for (_name, (tx, _rx)) in self.participant_map.iter() {
-                tx.send(propose_msg.clone()).unwrap_or(());
-            }
-
-            // Phase 2: Collect votes from all participants
-            let mut votes_commit = 0;
-            let mut votes_abort = 0;
-            let num_participants = self.participant_map.len();
-            let timeout = Duration::from_millis(200);
-            let start_time = std::time::Instant::now();
-
-            while votes_commit + votes_abort < num_participants {
-                if start_time.elapsed() > timeout {
-                    trace!("Timeout waiting for votes on txid: {}", req.txid);
-                    break;
-                }
-
-                if !self.running.load(Ordering::SeqCst) {
-                    break;
-                }
-
-                for (_name, (_tx, rx)) in self.participant_map.iter() {
-                    match rx.try_recv() {
-                        Ok(msg) => {
-                            if msg.txid == req.txid {
-                                if msg.mtype == MessageType::ParticipantVoteCommit {
-                                    votes_commit += 1;
-                                    trace!("Received commit vote for txid: {}", req.txid);
-                                } else if msg.mtype == MessageType::ParticipantVoteAbort {
-                                    votes_abort += 1;
-                                    trace!("Received abort vote for txid: {}", req.txid);
-                                }
-                            }
-                        },
-                        Err(TryRecvError::Empty) => continue,
-                        Err(TryRecvError::IpcError(_)) => continue,
-                    }
-                }
-
-                thread::sleep(Duration::from_millis(1));
-            }
-
-            // Make decision
-            let commit_decision = votes_commit == num_participants && votes_abort == 0;
-            
-            let (decision_msg_type, result_msg_type) = if commit_decision {
-                self.successful_ops += 1;
-                self.state = CoordinatorState::ReceivedVotesCommit;
-                info!("Coordinator decided COMMIT for txid: {}", req.txid);
-                (MessageType::CoordinatorCommit, MessageType::ClientResultCommit)
-            } else {
-                self.failed_ops += 1;
-                self.state = CoordinatorState::ReceivedVotesAbort;
-                info!("Coordinator decided ABORT for txid: {}", req.txid);
-                (MessageType::CoordinatorAbort, MessageType::ClientResultAbort)
-            };
-
-            // Log the decision
-            self.log.append(decision_msg_type, req.txid.clone(), "coordinator".to_string(), req.opid);
-
-            // Send decision to all participants
-            let decision_msg = ProtocolMessage::generate(
-                decision_msg_type,
-                req.txid.clone(),
-                "coordinator".to_string(),
-                req.opid,
-            );
-
-            for (_name, (tx, _rx)) in self.participant_map.iter() {
-                tx.send(decision_msg.clone()).unwrap_or(());
-            }
-
-            // Send result to client
-            let result_msg = ProtocolMessage::generate(
-                result_msg_type,
-                req.txid.clone(),
-                "coordinator".to_string(),
-                req.opid,
-            );
-
-            if let Some((tx, _rx)) = self.client_map.get(&client_id) {
-                tx.send(result_msg).unwrap_or(());
-            }
-
-            self.state = CoordinatorState::SentGlobalDecision;
-        }
-
-        // Send exit messages to all clients and participants
-        for (name, (tx, _rx)) in self.client_map.iter() {
-            let exit_msg = ProtocolMessage::generate(
-                MessageType::CoordinatorExit,
-                "exit".to_string(),
-                "coordinator".to_string(),
-                0,
-            );
-            tx.send(exit_msg).unwrap_or(());
-            trace!("Sent exit to client: {}", name);
-        }
-
-        for (name, (tx, _rx)) in self.participant_map.iter() {
-            let exit_msg = ProtocolMessage::generate(
-                MessageType::CoordinatorExit,
-                "exit".to_string(),
-                "coordinator".to_string(),
-                0,
-            );
-            tx.send(exit_msg).unwrap_or(());
-            trace!("Sent exit to participant: {}", name);
-        }
-
-        // Give children time to receive and process exit messages
-        thread::sleep(Duration::from_millis(50));
-
-        self.report_status();
-    }
-}
+//!
+//! coordinator.rs
+//! Implementation of 2PC coordinator
+//!
+extern crate log;
+extern crate stderrlog;
+extern crate rand;
+
+use std::collections::BTreeMap;
+use std::collections::hash_map::Entry;
+use std::mem;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+
+use clock;
+use crashpoint::CrashPoint;
+use crashpoint::Crashes;
+use detector::FailureDetector;
+use detector::Membership;
+use fixedhash::HashMap;
+use fixedhash::HashSet;
+use mailbox::Mailbox;
+use message::MessageType;
+use message::Operation;
+use message::ProtocolMessage;
+use message::RequestStatus;
+use oplog;
+use partition::PartitionMap;
+use tpcoptions::CommitProtocol;
+use tpcoptions::LoggingMode;
+use tpcoptions::StorageMode;
+use tpcoptions::TPCOptions;
+use transport::Transport;
+use transport::TryRecvError;
+use waitfor::WaitForGraph;
+
+/// CoordinatorState
+/// States for 2PC (and 3PC) state machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoordinatorState {
+    Quiescent,
+    ReceivedRequest,
+    ProposalSent,
+    ReceivedVotesAbort,
+    ReceivedVotesCommit,
+    PreCommitSent,          // 3PC only: waiting for PreCommit acknowledgements
+    Terminating,            // 3PC recovery: asking participants how a pre-committed transaction stands
+    SentGlobalDecision
+}
+
+/// Transaction
+/// Coordinator-side state of a single in-flight transaction
+struct Transaction {
+    state: CoordinatorState,
+    client_id: String,
+    opid: u32,
+    read_only: bool,        // MVCC snapshot read: replies collected in votes, nothing logged
+    participants: HashSet<String>,  // Owners of the keys it touches, the only ones it was sent to
+    one_phase: Option<ProtocolMessage>,  // 1PC: request to the sole participant, re-sent until it answers
+    votes: HashMap<String, MessageType>,
+    acks: HashSet<String>,
+    answers: HashMap<String, RequestStatus>,  // Terminating: what each participant reported
+    started: Instant,
+    phase_started: Instant,
+}
+
+/// Outcome
+/// A decided transaction that participants have not all acknowledged yet
+struct Outcome {
+    decision: MessageType,
+    opid: u32,
+    timestamp: u64,
+    pending: HashSet<String>,
+    last_sent: Instant,
+}
+
+/// Rejoin
+/// A participant or client that (re)connected while the protocol runs,
+/// with the transport to reach it on
+pub struct Rejoin<T: Transport> {
+    pub name: String,
+    pub transport: T,
+}
+
+/// Coordinator
+/// Struct maintaining state for coordinator
+pub struct Coordinator<T: Transport> {
+    transactions: HashMap<String, Transaction>,
+    protocol: CommitProtocol,
+    logging: LoggingMode,
+    storage: StorageMode,
+    partition: PartitionMap,
+    clock: u64,
+    running: Arc<AtomicBool>,
+    log: oplog::OpLog,
+    participant_map: HashMap<String, T>,
+    client_map: HashMap<String, T>,
+    rejoins: Option<mpsc::Receiver<Rejoin<T>>>,
+    mailboxes: HashMap<String, Mailbox>,
+    decisions: HashMap<String, (MessageType, u64)>,
+    finished_reads: HashSet<String>,
+    unacked: HashMap<String, Outcome>,
+    waits: WaitForGraph,
+    detector: FailureDetector,
+    successful_ops: u64,
+    failed_ops: u64,
+    unknown_ops: u64,
+    crashes: Crashes,
+    recovering: bool,
+}
+
+///
+/// Coordinator
+/// Implementation of coordinator functionality
+/// Required:
+/// 1. new -- Constructor
+/// 2. protocol -- Implementation of coordinator side of protocol
+/// 3. report_status -- Report of aggregate commit/abort/unknown stats on exit.
+/// 4. participant_join -- What to do when a participant joins
+/// 5. client_join -- What to do when a client joins
+/// 6. from_log -- Constructor that recovers from an existing log
+///
+impl<T: Transport> Coordinator<T> {
+
+    ///
+    /// new()
+    /// Initialize a new coordinator
+    ///
+    /// <params>
+    ///     log_path: directory for log files --> create a new log there.
+    ///     r: atomic bool --> still running?
+    ///     opts: CLI options (commit protocol, logging mode, ...)
+    ///
+    pub fn new(
+        log_path: String,
+        r: &Arc<AtomicBool>,
+        opts: &TPCOptions) -> Coordinator<T> {
+
+        Self::with_log(oplog::OpLog::new(log_path), r, opts, false)
+    }
+
+    ///
+    /// from_log()
+    /// Initialize a coordinator that recovers from the log left behind by a
+    /// previous incarnation. Outstanding decisions are resolved and re-sent
+    /// once participants have joined and protocol() starts.
+    ///
+    /// <params>
+    ///     log_path: path of an existing coordinator log --> append to it.
+    ///     r: atomic bool --> still running?
+    ///     opts: CLI options (commit protocol, logging mode, ...)
+    ///
+    pub fn from_log(
+        log_path: String,
+        r: &Arc<AtomicBool>,
+        opts: &TPCOptions) -> Coordinator<T> {
+
+        Self::with_log(oplog::OpLog::from_file(log_path), r, opts, true)
+    }
+
+    fn with_log(
+        log: oplog::OpLog,
+        r: &Arc<AtomicBool>,
+        opts: &TPCOptions,
+        recovering: bool) -> Coordinator<T> {
+
+        let mut crashes = opts.crash_points.arm("coordinator", recovering);
+        // A scripted crash happens once: not again while recovering from it
+        if let (false, Some(trigger)) = (recovering, opts.scenario.crash_point("coordinator")) {
+            crashes.crash_after(trigger);
+        }
+
+        Coordinator {
+            transactions: HashMap::default(),
+            protocol: opts.protocol,
+            logging: opts.logging,
+            storage: opts.storage,
+            partition: opts.partition_map.clone(),
+            clock: 0,
+            log,
+            running: r.clone(),
+            participant_map: HashMap::default(),
+            client_map: HashMap::default(),
+            rejoins: None,
+            mailboxes: HashMap::default(),
+            decisions: HashMap::default(),
+            finished_reads: HashSet::default(),
+            unacked: HashMap::default(),
+            waits: WaitForGraph::new(),
+            detector: FailureDetector::new(),
+            successful_ops: 0,
+            failed_ops: 0,
+            unknown_ops: 0,
+            crashes,
+            recovering,
+        }
+    }
+
+    ///
+    /// participant_join()
+    /// Adds a new participant for the coordinator to keep track of
+    ///
+    /// HINT: Keep track of any channels involved!
+    /// HINT: You may need to change the signature of this function
+    ///
+    pub fn participant_join(&mut self, name: &str, transport: T) {
+        assert!(self.transactions.is_empty());
+
+        self.participant_map.insert(name.to_string(), transport);
+        self.mailboxes.insert(name.to_string(), Mailbox::new());
+        self.detector.heard(name);
+    }
+
+    ///
+    /// client_join()
+    /// Adds a new client for the coordinator to keep track of
+    ///
+    /// HINT: Keep track of any channels involved!
+    /// HINT: You may need to change the signature of this function
+    ///
+    pub fn client_join(&mut self, name: &str, transport: T) {
+        assert!(self.transactions.is_empty());
+
+        self.client_map.insert(name.to_string(), transport);
+    }
+
+    ///
+    /// accept_rejoins()
+    /// Lets participants and clients (re)connect while the protocol runs:
+    /// whatever arrives on rejoins replaces the transport of the process with
+    /// that name (see poll_rejoins)
+    ///
+    pub fn accept_rejoins(&mut self, rejoins: mpsc::Receiver<Rejoin<T>>) {
+        self.rejoins = Some(rejoins);
+    }
+
+    ///
+    /// poll_rejoins()
+    /// Swaps in the transport of every process that reconnected. A restarted
+    /// participant recovers on its own and asks for what it missed; decisions
+    /// it has not acknowledged are re-sent to it as usual. Returns true if
+    /// anyone rejoined.
+    ///
+    fn poll_rejoins(&mut self) -> bool {
+        let rejoined: Vec<Rejoin<T>> = match &self.rejoins {
+            Some(rejoins) => rejoins.try_iter().collect(),
+            None => return false,
+        };
+        let received = !rejoined.is_empty();
+        for rejoin in rejoined {
+            info!("coordinator::{} rejoined", rejoin.name);
+            if rejoin.name.starts_with("participant_") {
+                self.mailboxes.entry(rejoin.name.clone()).or_default();
+                if let Some(change) = self.detector.heard(&rejoin.name) {
+                    self.membership_changed(&rejoin.name, change);
+                }
+                self.participant_map.insert(rejoin.name, rejoin.transport);
+            } else {
+                self.client_map.insert(rejoin.name, rejoin.transport);
+            }
+        }
+        received
+    }
+
+    ///
+    /// check_members()
+    /// Lets the failure detector suspect or declare down the participants
+    /// that went silent
+    ///
+    fn check_members(&mut self) {
+        for (name, change) in self.detector.check() {
+            self.membership_changed(&name, change);
+        }
+    }
+
+    ///
+    /// membership_changed()
+    /// Reports a change in what we believe about a participant, and logs it.
+    /// Transactions involving a participant that is down are aborted by
+    /// check_transactions() rather than waiting out their vote timeout.
+    ///
+    fn membership_changed(&mut self, name: &str, membership: Membership) {
+        info!("coordinator::{} is {:?}", name, membership);
+        let mtype = match membership {
+            Membership::Up => MessageType::CoordinatorMemberUp,
+            Membership::Suspected => MessageType::CoordinatorMemberSuspected,
+            Membership::Down => MessageType::CoordinatorMemberDown,
+        };
+        self.log_record(mtype, name.to_string(), 0);
+    }
+
+    ///
+    /// report_status()
+    /// Report the abort/commit/unknown status (aggregate) of all transaction
+    /// requests made by this coordinator before exiting, along with the
+    /// number of log writes (LW) and how many of them were forced (LF).
+    ///
+    pub fn report_status(&mut self) {
+        println!("coordinator:\tC:{}\tA:{}\tU:{}\tLW:{}\tLF:{}",
+                 self.successful_ops, self.failed_ops, self.unknown_ops,
+                 self.log.writes(), self.log.forced_writes());
+    }
+
+    ///
+    /// log_record()
+    /// Appends a coordinator record to the log, forcing it only if the
+    /// logging mode requires it
+    ///
+    fn log_record(&mut self, mtype: MessageType, txid: String, opid: u32) {
+        self.log_record_with_ops(mtype, txid, opid, Vec::new());
+    }
+
+    ///
+    /// log_record_with_ops()
+    /// Like log_record(), but also records the transaction payload
+    ///
+    fn log_record_with_ops(&mut self, mtype: MessageType, txid: String, opid: u32, ops: Vec<Operation>) {
+        let force = self.logging.coordinator_forces(mtype);
+        self.log.append_with_ops(mtype, txid.clone(), "coordinator".to_string(), opid, ops, force);
+        self.crashes.logged(mtype, &txid);
+    }
+
+    ///
+    /// log_decision()
+    /// Appends the record of a global decision, with its commit timestamp
+    ///
+    fn log_decision(&mut self, decision: MessageType, txid: String, opid: u32, timestamp: u64) {
+        let mut pm = ProtocolMessage::instantiate(decision, 0, txid.clone(), "coordinator".to_string(), opid);
+        pm.timestamp = timestamp;
+        let force = self.logging.coordinator_forces(decision);
+        self.log.append_message(pm, force);
+        self.crashes.logged(decision, &txid);
+    }
+
+    ///
+    /// recover()
+    /// Replays the log of a previous incarnation. Every transaction that was
+    /// proposed but never decided is aborted (and the abort logged), unless
+    /// it was pre-committed under 3PC: participants may have terminated it
+    /// on their own either way, so they are asked where it stands before it
+    /// is decided (see check_transactions()). Every decision that
+    /// needs acknowledging and has no End record is then re-sent until all
+    /// participants the proposal went to acknowledge it, since we cannot
+    /// know which of them saw it before the crash. The timestamp clock
+    /// resumes after the latest commit timestamp in the log.
+    ///
+    /// A one-phase transaction is decided by its participant, so one without
+    /// a logged decision is not resolved here: its request is sent again and
+    /// it is tracked until the participant reports the outcome.
+    ///
+    fn recover(&mut self) {
+        info!("coordinator::Recovering from {}", self.log.path());
+
+        // Rebuild the outcome of each transaction from the log, in log order
+        let mut proposed: Vec<(String, u32)> = Vec::new();
+        let mut involved: HashMap<String, HashSet<String>> = HashMap::default();
+        let mut one_phase: HashMap<String, ProtocolMessage> = HashMap::default();
+        let mut precommitted: HashSet<String> = HashSet::default();
+        let mut decided: HashMap<String, (MessageType, u32, u64)> = HashMap::default();
+        let mut ended: HashSet<String> = HashSet::default();
+        for pm in self.log.entries() {
+            match pm.mtype {
+                MessageType::CoordinatorPropose => {
+                    involved.insert(pm.txid.clone(), self.split(&pm.ops).into_keys().collect());
+                    proposed.push((pm.txid, pm.opid));
+                },
+                MessageType::CoordinatorOnePhaseCommit => {
+                    self.clock = self.clock.max(pm.timestamp);
+                    one_phase.insert(pm.txid.clone(), pm);
+                },
+                MessageType::CoordinatorPreCommit => {
+                    precommitted.insert(pm.txid);
+                },
+                MessageType::CoordinatorCommit | MessageType::CoordinatorAbort => {
+                    self.clock = self.clock.max(pm.timestamp);
+                    decided.insert(pm.txid, (pm.mtype, pm.opid, pm.timestamp));
+                },
+                MessageType::CoordinatorEnd => {
+                    ended.insert(pm.txid);
+                },
+                _ => {},
+            }
+        }
+
+        // Anything proposed but undecided may have been voted on, so it must
+        // be resolved before new requests are served
+        let mut terminating: Vec<(String, u32)> = Vec::new();
+        for (txid, opid) in proposed {
+            if let Entry::Vacant(e) = decided.entry(txid) {
+                if precommitted.contains(e.key()) {
+                    terminating.push((e.key().clone(), opid));
+                    continue;
+                }
+                info!("coordinator::Recovery aborting undecided txid: {}", e.key());
+                self.log_decision(MessageType::CoordinatorAbort, e.key().clone(), opid, 0);
+                e.insert((MessageType::CoordinatorAbort, opid, 0));
+            }
+        }
+
+        for (txid, (mtype, opid, timestamp)) in decided.iter() {
+            if ended.contains(txid) || one_phase.contains_key(txid) || !self.logging.acknowledges(*mtype) {
+                continue;
+            }
+            trace!("coordinator::Recovery re-sending {:?} for txid: {}", mtype, txid);
+            let mut decision_msg = ProtocolMessage::generate(
+                *mtype,
+                txid.clone(),
+                "coordinator".to_string(),
+                *opid,
+            );
+            decision_msg.timestamp = *timestamp;
+            let recipients = match involved.remove(txid) {
+                Some(recipients) => recipients,
+                None => self.participant_map.keys().cloned().collect(),
+            };
+            for name in recipients.iter() {
+                if let Some(tx) = self.participant_map.get(name) {
+                    tx.send(decision_msg.clone()).unwrap_or(());
+                }
+            }
+            self.await_acks(txid, *mtype, *opid, *timestamp, recipients);
+        }
+
+        for (txid, request) in one_phase.into_iter().filter(|(txid, _)| !decided.contains_key(txid)) {
+            info!("coordinator::Recovery asking again for one-phase txid: {}", txid);
+            let participants: HashSet<String> = self.split(&request.ops).into_keys().collect();
+            self.send_one_phase(&request, &participants);
+            self.track(String::new(), &request, false, participants, Some(request.clone()));
+        }
+
+        for (txid, opid) in terminating {
+            info!("coordinator::Recovery asking participants about pre-committed txid: {}", txid);
+            let participants = involved.remove(&txid).unwrap_or_default();
+            let request = ProtocolMessage::generate(
+                MessageType::CoordinatorTerminationRequest,
+                txid.clone(),
+                "coordinator".to_string(),
+                opid,
+            );
+            self.send_termination_request(&request, &participants);
+            self.track(String::new(), &request, false, participants, None);
+            if let Some(txn) = self.transactions.get_mut(&txid) {
+                txn.state = CoordinatorState::Terminating;
+            }
+        }
+
+        info!("coordinator::Recovered {} decided transactions", decided.len());
+        self.decisions = decided.into_iter().map(|(txid, (mtype, _, timestamp))| (txid, (mtype, timestamp))).collect();
+        self.recovering = false;
+    }
+
+    ///
+    /// answer_decision_request()
+    /// Replies to a participant asking about the outcome of a transaction with
+    /// a CoordinatorDecisionReply carrying the decision recorded in the log,
+    /// and the commit timestamp of a commit.
+    /// Transactions still being voted on are reported as Unknown so the
+    /// participant asks again later. Anything else the log has no
+    /// decision for was never proposed by us, so it is presumed aborted.
+    ///
+    fn answer_decision_request(&self, request: &ProtocolMessage) {
+        let status = if self.transactions.contains_key(&request.txid) {
+            RequestStatus::Unknown
+        } else {
+            let decision = self.logged_decision(&request.txid).unwrap_or(self.logging.presumed_outcome());
+            match decision {
+                MessageType::CoordinatorCommit => RequestStatus::Committed,
+                _ => RequestStatus::Aborted,
+            }
+        };
+
+        trace!("coordinator::Answering {} with {:?} for txid: {}", request.senderid, status, request.txid);
+        let mut reply = ProtocolMessage::generate(
+            MessageType::CoordinatorDecisionReply,
+            request.txid.clone(),
+            "coordinator".to_string(),
+            request.opid,
+        );
+        reply.status = status;
+        if status == RequestStatus::Committed {
+            reply.timestamp = self.decisions.get(&request.txid).map_or(0, |(_, timestamp)| *timestamp);
+        }
+        if let Some(tx) = self.participant_map.get(&request.senderid) {
+            tx.send(reply).unwrap_or(());
+        }
+    }
+
+    ///
+    /// logged_decision()
+    /// Returns the decision (CoordinatorCommit/CoordinatorAbort) recorded in
+    /// the log for txid, if any. Decisions are cached as they are logged (or
+    /// recovered), so this does not need to rescan the log.
+    ///
+    fn logged_decision(&self, txid: &str) -> Option<MessageType> {
+        self.decisions.get(txid).map(|(decision, _)| *decision)
+    }
+
+    ///
+    /// split()
+    /// Splits the operations of a transaction among the participants owning
+    /// the keys they touch, by participant name. A transaction without
+    /// operations goes to everyone.
+    ///
+    fn split(&self, ops: &[Operation]) -> BTreeMap<String, Vec<Operation>> {
+        if ops.is_empty() {
+            return self.participant_map.keys().map(|name| (name.clone(), Vec::new())).collect();
+        }
+        self.partition.split(ops).into_iter()
+            .map(|(owner, part)| (format!("participant_{}", owner), part))
+            .collect()
+    }
+
+    ///
+    /// next_timestamp()
+    /// MVCC only: assigns the next commit timestamp. Outside MVCC decisions
+    /// carry no timestamp (0).
+    ///
+    fn next_timestamp(&mut self) -> u64 {
+        if self.storage != StorageMode::Mvcc {
+            return 0;
+        }
+        self.clock += 1;
+        self.clock
+    }
+
+    ///
+    /// snapshot()
+    /// MVCC only: the timestamp new transactions read at. It stays below the
+    /// commit timestamp of any one-phase transaction still out, since its
+    /// participant may commit it at any moment.
+    ///
+    fn snapshot(&self) -> u64 {
+        self.transactions.values()
+            .filter_map(|txn| txn.one_phase.as_ref().map(|request| request.timestamp.saturating_sub(1)))
+            .min()
+            .unwrap_or(self.clock)
+    }
+
+    ///
+    /// poll_clients()
+    /// Starts a transaction for every request waiting on a client channel.
+    /// Returns true if any request was received.
+    ///
+    fn poll_clients(&mut self) -> bool {
+        let mut requests: Vec<(String, ProtocolMessage)> = Vec::new();
+        for (name, rx) in self.client_map.iter() {
+            loop {
+                match rx.try_recv() {
+                    Ok(msg) => {
+                        if msg.mtype == MessageType::ClientRequest {
+                            trace!("Coordinator received request from {}", name);
+                            requests.push((name.clone(), msg));
+                        }
+                    },
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => break,
+                }
+            }
+        }
+
+        let received = !requests.is_empty();
+        for (client_id, req) in requests {
+            self.start_transaction(client_id, req);
+        }
+        received
+    }
+
+    ///
+    /// start_transaction()
+    /// Phase 1: logs and sends the proposal for a client request to all
+    /// participants owning the keys it touches, each of which only gets its
+    /// own operations, then tracks the transaction until it is decided. Under
+    /// MVCC the proposal carries the snapshot its reads see, and a read-only
+    /// request skips voting altogether: it is sent as a snapshot read and
+    /// nothing is logged for it. A request touching a single participant is
+    /// handed to it to decide on its own (one-phase commit), skipping the
+    /// vote round trip. A request for a transaction already decided, here or
+    /// by an incarnation we recovered from, is answered with the recorded
+    /// outcome instead.
+    ///
+    fn start_transaction(&mut self, client_id: String, req: ProtocolMessage) {
+        if let Some(decision) = self.logged_decision(&req.txid) {
+            info!("Coordinator answering {} with recorded {:?} for txid: {}", client_id, decision, req.txid);
+            let result_msg_type = match decision {
+                MessageType::CoordinatorCommit => MessageType::ClientResultCommit,
+                _ => MessageType::ClientResultAbort,
+            };
+            let result_msg = ProtocolMessage::generate(result_msg_type, req.txid.clone(), "coordinator".to_string(), req.opid);
+            if let Some(tx) = self.client_map.get(&client_id) {
+                tx.send(result_msg).unwrap_or(());
+            }
+            return;
+        }
+        if self.transactions.contains_key(&req.txid) || self.finished_reads.contains(&req.txid) {
+            trace!("Coordinator ignoring duplicate request for txid: {}", req.txid);
+            return;
+        }
+
+        let parts = self.split(&req.ops);
+        let participants: HashSet<String> = parts.keys().cloned().collect();
+        let snapshot = self.snapshot();
+        let read_only = self.storage == StorageMode::Mvcc && !req.ops.iter().any(|op| op.is_write());
+        if read_only {
+            info!("Coordinator sending snapshot read at {} for txid: {}", snapshot, req.txid);
+            let mut read_msg = ProtocolMessage::generate(
+                MessageType::CoordinatorSnapshotRead,
+                req.txid.clone(),
+                "coordinator".to_string(),
+                req.opid,
+            );
+            read_msg.timestamp = snapshot;
+            for (name, part) in parts {
+                if let Some(tx) = self.participant_map.get(&name) {
+                    read_msg.ops = part;
+                    tx.send(read_msg.clone()).unwrap_or(());
+                }
+            }
+            self.track(client_id, &req, true, participants, None);
+            return;
+        }
+
+        if participants.len() == 1 {
+            info!("Coordinator sending one-phase commit for txid: {}", req.txid);
+            let mut request = ProtocolMessage::generate(
+                MessageType::CoordinatorOnePhaseCommit,
+                req.txid.clone(),
+                "coordinator".to_string(),
+                req.opid,
+            );
+            request.ops = req.ops.clone();
+            // Assigned up front: the participant commits without asking us
+            request.timestamp = self.next_timestamp();
+            self.log.append_message(request.clone(), self.logging.coordinator_forces(request.mtype));
+            self.crashes.logged(request.mtype, &request.txid);
+            self.send_one_phase(&request, &participants);
+            self.track(client_id, &req, false, participants, Some(request));
+            return;
+        }
+
+        info!("Coordinator sending proposal for txid: {}", req.txid);
+
+        // Log the proposal so a recovering coordinator knows to resolve it
+        self.log_record_with_ops(MessageType::CoordinatorPropose, req.txid.clone(), req.opid, req.ops.clone());
+
+        let mut propose_msg = ProtocolMessage::generate(
+            MessageType::CoordinatorPropose,
+            req.txid.clone(),
+            "coordinator".to_string(),
+            req.opid,
+        );
+        propose_msg.timestamp = snapshot;
+
+        // Send each owning participant its part of the proposal
+        for (name, part) in parts {
+            if let Some(tx) = self.participant_map.get(&name) {
+                propose_msg.ops = part;
+                tx.send(propose_msg.clone()).unwrap_or(());
+            }
+        }
+        self.crashes.reach(CrashPoint::AfterPropose, &req.txid);
+
+        self.track(client_id, &req, false, participants, None);
+    }
+
+    ///
+    /// send_one_phase()
+    /// 1PC: sends the request to the transaction's sole participant
+    ///
+    fn send_one_phase(&self, request: &ProtocolMessage, participants: &HashSet<String>) {
+        for name in participants.iter() {
+            if let Some(tx) = self.participant_map.get(name) {
+                tx.send(request.clone()).unwrap_or(());
+            }
+        }
+    }
+
+    ///
+    /// send_termination_request()
+    /// 3PC recovery: asks participants where a pre-committed transaction
+    /// stands
+    ///
+    fn send_termination_request(&self, request: &ProtocolMessage, participants: &HashSet<String>) {
+        for name in participants.iter() {
+            if let Some(tx) = self.participant_map.get(name) {
+                tx.send(request.clone()).unwrap_or(());
+            }
+        }
+    }
+
+    ///
+    /// track()
+    /// Starts tracking a transaction whose proposal (or snapshot read) went
+    /// out to the participants
+    ///
+    fn track(&mut self, client_id: String, req: &ProtocolMessage, read_only: bool, participants: HashSet<String>,
+             one_phase: Option<ProtocolMessage>) {
+        self.transactions.insert(req.txid.clone(), Transaction {
+            state: CoordinatorState::ProposalSent,
+            client_id,
+            opid: req.opid,
+            read_only,
+            participants,
+            one_phase,
+            votes: HashMap::default(),
+            acks: HashSet::default(),
+            answers: HashMap::default(),
+            started: clock::now(),
+            phase_started: clock::now(),
+        });
+    }
+
+    ///
+    /// poll_participants()
+    /// Drains every participant channel into that participant's mailbox,
+    /// answering decision requests and recording lock waits right away,
+    /// then delivers held messages to the transaction they belong to (by
+    /// txid). Returns true if any message was received.
+    ///
+    fn poll_participants(&mut self) -> bool {
+        let mut requests: Vec<ProtocolMessage> = Vec::new();
+        let mut reports: Vec<(String, ProtocolMessage)> = Vec::new();
+        let mut received = false;
+        let mut changes: Vec<(String, Membership)> = Vec::new();
+        for (name, rx) in self.participant_map.iter() {
+            let mailbox = self.mailboxes.get_mut(name).unwrap();
+            while let Ok(msg) = rx.try_recv() {
+                received = true;
+                // Anything a participant sends shows it is alive
+                if let Some(change) = self.detector.heard(name) {
+                    changes.push((name.clone(), change));
+                }
+                if msg.mtype == MessageType::ParticipantHeartbeat {
+                    continue;
+                } else if msg.mtype == MessageType::ParticipantDecisionRequest {
+                    requests.push(msg);
+                } else if msg.mtype == MessageType::ParticipantWaitFor {
+                    reports.push((name.clone(), msg));
+                } else {
+                    mailbox.hold(msg);
+                }
+            }
+        }
+
+        for (name, change) in changes {
+            self.membership_changed(&name, change);
+        }
+        for request in requests {
+            self.answer_decision_request(&request);
+        }
+        for (name, report) in reports {
+            // A report may be overtaken by the decision of its transaction
+            if self.transactions.contains_key(&report.txid) {
+                trace!("{} reports txid: {} waiting for {:?}", name, report.txid, report.waits_for);
+                self.waits.record(&name, &report.txid, report.waits_for);
+            }
+        }
+        self.deliver();
+        received
+    }
+
+    ///
+    /// deliver()
+    /// Hands held votes to their in-flight transaction, and acks to the
+    /// decided transaction waiting for them. A vote that arrives after its
+    /// transaction was decided is answered with the decision so the late
+    /// voter does not have to ask for it. Messages for a txid we know nothing
+    /// about stay held until they expire.
+    ///
+    fn deliver(&mut self) {
+        let mut late: Vec<(String, String, (MessageType, u64), u32)> = Vec::new();
+        let mut acked: Vec<String> = Vec::new();
+        for (name, mailbox) in self.mailboxes.iter_mut() {
+            for txid in mailbox.txids() {
+                if let Some(txn) = self.transactions.get_mut(&txid) {
+                    for msg in mailbox.take(&txid) {
+                        match msg.mtype {
+                            MessageType::ParticipantVoteCommit
+                            | MessageType::ParticipantVoteAbort
+                            | MessageType::ParticipantVoteReadOnly => {
+                                trace!("Received {:?} from {} for txid: {}", msg.mtype, msg.senderid, msg.txid);
+                                // Having voted, it no longer waits for locks there
+                                self.waits.record(name, &txid, Vec::new());
+                                txn.votes.insert(msg.senderid, msg.mtype);
+                            },
+                            MessageType::ParticipantOnePhaseReply => {
+                                trace!("Received one-phase {:?} from {} for txid: {}", msg.status, msg.senderid, msg.txid);
+                                let vote = if msg.status == RequestStatus::Committed {
+                                    MessageType::ParticipantVoteCommit
+                                } else {
+                                    MessageType::ParticipantVoteAbort
+                                };
+                                self.waits.record(name, &txid, Vec::new());
+                                txn.votes.insert(msg.senderid, vote);
+                            },
+                            MessageType::ParticipantSnapshotReply => {
+                                trace!("Received snapshot reply from {} for txid: {}", msg.senderid, msg.txid);
+                                txn.votes.insert(msg.senderid, msg.mtype);
+                            },
+                            MessageType::ParticipantPreCommitAck => {
+                                trace!("Received PreCommit ack from {} for txid: {}", msg.senderid, msg.txid);
+                                txn.acks.insert(msg.senderid);
+                            },
+                            MessageType::ParticipantTerminationReply => {
+                                trace!("{} reports {:?} for txid: {}", msg.senderid, msg.status, msg.txid);
+                                txn.answers.insert(msg.senderid, msg.status);
+                            },
+                            _ => trace!("coordinator::Dropping unexpected {:?} for txid: {}", msg.mtype, msg.txid),
+                        }
+                    }
+                } else if let Some(decision) = self.decisions.get(&txid) {
+                    for msg in mailbox.take(&txid) {
+                        if msg.mtype == MessageType::ParticipantAck {
+                            if let Some(outcome) = self.unacked.get_mut(&txid) {
+                                trace!("Received ack from {} for txid: {}", msg.senderid, txid);
+                                outcome.pending.remove(&msg.senderid);
+                                if outcome.pending.is_empty() {
+                                    acked.push(txid.clone());
+                                }
+                            }
+                        } else if msg.mtype != MessageType::ParticipantOnePhaseReply {
+                            trace!("coordinator::Late {:?} from {} for decided txid: {}", msg.mtype, name, txid);
+                            late.push((name.clone(), txid.clone(), *decision, msg.opid));
+                        }
+                    }
+                }
+            }
+
+            for msg in mailbox.expire(Duration::from_millis(1000)) {
+                trace!("coordinator::Dropping expired {:?} from {} for txid: {}", msg.mtype, name, msg.txid);
+            }
+        }
+
+        for (name, txid, (decision, timestamp), opid) in late {
+            let mut decision_msg = ProtocolMessage::generate(decision, txid, "coordinator".to_string(), opid);
+            decision_msg.timestamp = timestamp;
+            if let Some(tx) = self.participant_map.get(&name) {
+                tx.send(decision_msg).unwrap_or(());
+            }
+        }
+
+        // Everyone applied the decision, so the transaction can be forgotten
+        for txid in acked {
+            if let Some(outcome) = self.unacked.remove(&txid) {
+                trace!("Coordinator ending txid: {}", txid);
+                self.log_record(MessageType::CoordinatorEnd, txid, outcome.opid);
+            }
+        }
+    }
+
+    ///
+    /// await_acks()
+    /// Remembers a decision until every participant in pending acknowledges
+    /// it. With nobody to wait for, the transaction ends right away.
+    ///
+    fn await_acks(&mut self, txid: &str, decision: MessageType, opid: u32, timestamp: u64,
+                  pending: HashSet<String>) {
+        if pending.is_empty() {
+            trace!("Coordinator ending txid: {}", txid);
+            self.log_record(MessageType::CoordinatorEnd, txid.to_string(), opid);
+            return;
+        }
+        self.unacked.insert(txid.to_string(), Outcome {
+            decision,
+            opid,
+            timestamp,
+            pending,
+            last_sent: clock::now(),
+        });
+    }
+
+    ///
+    /// check_unacked()
+    /// Re-sends decisions to participants that have not acknowledged them in
+    /// time (the decision or the ack may have been lost)
+    ///
+    fn check_unacked(&mut self) {
+        let timeout = Duration::from_millis(500);
+        for (txid, outcome) in self.unacked.iter_mut() {
+            if clock::elapsed(outcome.last_sent) <= timeout {
+                continue;
+            }
+            trace!("Coordinator re-sending {:?} for txid: {} to {:?}", outcome.decision, txid, outcome.pending);
+            let mut decision_msg = ProtocolMessage::generate(
+                outcome.decision,
+                txid.clone(),
+                "coordinator".to_string(),
+                outcome.opid,
+            );
+            decision_msg.timestamp = outcome.timestamp;
+            for name in outcome.pending.iter() {
+                if let Some(tx) = self.participant_map.get(name) {
+                    tx.send(decision_msg.clone()).unwrap_or(());
+                }
+            }
+            outcome.last_sent = clock::now();
+        }
+    }
+
+    ///
+    /// check_transactions()
+    /// Moves every transaction whose current phase is complete (or timed out)
+    /// on to its next phase. Under 2PC, a transaction with all commit votes is
+    /// committed; under 3PC it is pre-committed first, and committed once
+    /// every participant acknowledged the PreCommit. Once a PreCommit has been
+    /// sent the transaction can no longer abort, since participants that time
+    /// out in the pre-committed state commit on their own. After a restart, a
+    /// transaction pre-committed before the crash commits if any participant
+    /// reports it pre-committed or committed, and aborts if one reports it
+    /// aborted or all of them report nothing: then the PreCommit never got
+    /// out, and participants that lost us terminate it with abort. Participants that
+    /// voted ReadOnly count towards the votes but take no part after that.
+    /// A snapshot read is done once every participant replied. A one-phase
+    /// transaction takes the outcome its participant reports, and is never
+    /// aborted by us: on a timeout its request is sent again. While its
+    /// participant is down nothing is sent; the client is told the outcome is
+    /// unknown, and the outcome is still logged once the participant is back.
+    ///
+    fn check_transactions(&mut self) {
+        let timeout = Duration::from_millis(200);
+
+        let mut to_precommit: Vec<String> = Vec::new();
+        let mut to_decide: Vec<(String, bool)> = Vec::new();
+        let mut to_finish: Vec<(String, bool)> = Vec::new();
+        let mut to_resend: Vec<(ProtocolMessage, HashSet<String>)> = Vec::new();
+        let mut to_ask: Vec<(ProtocolMessage, HashSet<String>)> = Vec::new();
+        let mut to_report_unknown: Vec<(String, String, u32)> = Vec::new();
+        let detector = &self.detector;
+        for (txid, txn) in self.transactions.iter_mut() {
+            match txn.state {
+                CoordinatorState::ProposalSent if txn.one_phase.is_some() => {
+                    if let Some(vote) = txn.votes.values().next() {
+                        let commit = *vote == MessageType::ParticipantVoteCommit;
+                        txn.state = if commit { CoordinatorState::ReceivedVotesCommit } else { CoordinatorState::ReceivedVotesAbort };
+                        to_decide.push((txid.clone(), commit));
+                    } else if txn.participants.iter().any(|p| detector.is_down(p)) {
+                        // It may have decided before going down, so we cannot
+                        // abort; the client stops waiting, and we ask again
+                        // once the participant is back
+                        if !txn.client_id.is_empty() {
+                            trace!("Participant down, outcome of one-phase txid: {} unknown", txid);
+                            to_report_unknown.push((mem::take(&mut txn.client_id), txid.clone(), txn.opid));
+                        }
+                    } else if clock::elapsed(txn.phase_started) > timeout {
+                        trace!("Timeout waiting for one-phase outcome of txid: {}", txid);
+                        txn.phase_started = clock::now();
+                        to_resend.extend(txn.one_phase.clone().map(|req| (req, txn.participants.clone())));
+                    }
+                },
+                CoordinatorState::ProposalSent if txn.read_only => {
+                    if txn.votes.len() == txn.participants.len() {
+                        to_finish.push((txid.clone(), true));
+                    } else if txn.participants.iter().any(|p| !txn.votes.contains_key(p) && detector.is_down(p)) {
+                        trace!("Participant down, giving up snapshot read txid: {}", txid);
+                        to_finish.push((txid.clone(), false));
+                    } else if clock::elapsed(txn.phase_started) > timeout {
+                        trace!("Timeout waiting for snapshot replies on txid: {}", txid);
+                        to_finish.push((txid.clone(), false));
+                    }
+                },
+                CoordinatorState::ProposalSent => {
+                    if txn.votes.values().any(|v| *v == MessageType::ParticipantVoteAbort) {
+                        txn.state = CoordinatorState::ReceivedVotesAbort;
+                        to_decide.push((txid.clone(), false));
+                    } else if txn.votes.len() == txn.participants.len() {
+                        txn.state = CoordinatorState::ReceivedVotesCommit;
+                        if self.protocol == CommitProtocol::ThreePhase {
+                            to_precommit.push(txid.clone());
+                        } else {
+                            to_decide.push((txid.clone(), true));
+                        }
+                    } else if txn.participants.iter().any(|p| !txn.votes.contains_key(p) && detector.is_down(p)) {
+                        trace!("Participant down, aborting txid: {}", txid);
+                        txn.state = CoordinatorState::ReceivedVotesAbort;
+                        to_decide.push((txid.clone(), false));
+                    } else if clock::elapsed(txn.phase_started) > timeout {
+                        trace!("Timeout waiting for votes on txid: {}", txid);
+                        txn.state = CoordinatorState::ReceivedVotesAbort;
+                        to_decide.push((txid.clone(), false));
+                    }
+                },
+                CoordinatorState::Terminating => {
+                    let answered = |status: RequestStatus| txn.answers.values().any(|s| *s == status);
+                    if answered(RequestStatus::Committed) || answered(RequestStatus::PreCommitted) {
+                        to_decide.push((txid.clone(), true));
+                    } else if answered(RequestStatus::Aborted) || txn.answers.len() == txn.participants.len() {
+                        to_decide.push((txid.clone(), false));
+                    } else if clock::elapsed(txn.phase_started) > timeout {
+                        trace!("Timeout waiting for termination replies on txid: {}", txid);
+                        txn.phase_started = clock::now();
+                        let request = ProtocolMessage::generate(
+                            MessageType::CoordinatorTerminationRequest,
+                            txid.clone(),
+                            "coordinator".to_string(),
+                            txn.opid,
+                        );
+                        let silent = txn.participants.iter().filter(|p| !txn.answers.contains_key(*p)).cloned().collect();
+                        to_ask.push((request, silent));
+                    }
+                },
+                CoordinatorState::PreCommitSent => {
+                    let num_updaters = txn.votes.values().filter(|v| **v == MessageType::ParticipantVoteCommit).count();
+                    if txn.acks.len() == num_updaters {
+                        to_decide.push((txid.clone(), true));
+                    } else if clock::elapsed(txn.phase_started) > timeout {
+                        trace!("Timeout waiting for PreCommit acks on txid: {}", txid);
+                        to_decide.push((txid.clone(), true));
+                    }
+                },
+                _ => {},
+            }
+        }
+
+        for txid in to_precommit {
+            self.precommit(&txid);
+        }
+        for (txid, commit) in to_decide {
+            self.decide(&txid, commit);
+        }
+        for (txid, success) in to_finish {
+            self.finish_snapshot_read(&txid, success);
+        }
+        for (request, participants) in to_resend {
+            self.send_one_phase(&request, &participants);
+        }
+        for (request, participants) in to_ask {
+            self.send_termination_request(&request, &participants);
+        }
+        for (client_id, txid, opid) in to_report_unknown {
+            let result_msg = ProtocolMessage::generate(MessageType::ClientResultUnknown, txid, "coordinator".to_string(), opid);
+            if let Some(tx) = self.client_map.get(&client_id) {
+                tx.send(result_msg).unwrap_or(());
+            }
+        }
+
+        self.break_deadlocks();
+    }
+
+    ///
+    /// break_deadlocks()
+    /// Looks for cycles in the wait-for graph participants reported and
+    /// aborts the youngest transaction still collecting votes on each, rather
+    /// than waiting for lock or vote timeouts to untangle them. One-phase
+    /// transactions are decided by their participant, so they are never
+    /// picked; a cycle made up of them only is left to its lock timeouts,
+    /// and their waits are kept.
+    ///
+    fn break_deadlocks(&mut self) {
+        while let Some(cycle) = self.waits.find_cycle() {
+            let victim = cycle.iter()
+                .filter_map(|txid| self.transactions.get(txid).map(|txn| (txn, txid)))
+                .filter(|(txn, _)| txn.state == CoordinatorState::ProposalSent && txn.one_phase.is_none())
+                .max_by_key(|(txn, _)| txn.started)
+                .map(|(_, txid)| txid.clone());
+            match victim {
+                Some(victim) => {
+                    info!("Coordinator found deadlock among {:?}, aborting txid: {}", cycle, victim);
+                    self.decide(&victim, false);
+                },
+                None => {
+                    // Reports of transactions past their votes are stale
+                    let stale: Vec<&String> = cycle.iter()
+                        .filter(|txid| self.transactions.get(*txid).is_none_or(|txn| txn.one_phase.is_none()))
+                        .collect();
+                    if stale.is_empty() {
+                        break;
+                    }
+                    for txid in stale {
+                        self.waits.remove(txid);
+                    }
+                }
+            }
+        }
+    }
+
+    ///
+    /// phase_two_participants()
+    /// Participants that take part in phase two of a transaction: everyone
+    /// it was proposed to except those that voted ReadOnly
+    ///
+    fn phase_two_participants(&self, txn: &Transaction) -> HashSet<String> {
+        txn.participants.iter()
+            .filter(|name| txn.votes.get(*name) != Some(&MessageType::ParticipantVoteReadOnly))
+            .cloned()
+            .collect()
+    }
+
+    ///
+    /// precommit()
+    /// 3PC only: logs and sends a PreCommit for a transaction every
+    /// participant voted to commit (or ReadOnly)
+    ///
+    fn precommit(&mut self, txid: &String) {
+        let (opid, recipients) = match self.transactions.get(txid) {
+            Some(txn) => (txn.opid, self.phase_two_participants(txn)),
+            None => return,
+        };
+
+        info!("Coordinator sending PreCommit for txid: {}", txid);
+        self.log_record(MessageType::CoordinatorPreCommit, txid.clone(), opid);
+
+        let precommit_msg = ProtocolMessage::generate(
+            MessageType::CoordinatorPreCommit,
+            txid.clone(),
+            "coordinator".to_string(),
+            opid,
+        );
+        for name in recipients.iter() {
+            if let Some(tx) = self.participant_map.get(name) {
+                tx.send(precommit_msg.clone()).unwrap_or(());
+            }
+        }
+
+        if let Some(txn) = self.transactions.get_mut(txid) {
+            txn.state = CoordinatorState::PreCommitSent;
+            txn.phase_started = clock::now();
+        }
+    }
+
+    ///
+    /// finish_snapshot_read()
+    /// MVCC only: reports the result of a read-only transaction to its
+    /// client. There is no decision to log or send.
+    ///
+    fn finish_snapshot_read(&mut self, txid: &String, success: bool) {
+        let txn = match self.transactions.remove(txid) {
+            Some(txn) => txn,
+            None => return,
+        };
+        self.finished_reads.insert(txid.clone());
+
+        let result_msg_type = if success {
+            self.successful_ops += 1;
+            info!("Coordinator finished snapshot read for txid: {}", txid);
+            MessageType::ClientResultCommit
+        } else {
+            self.failed_ops += 1;
+            info!("Coordinator gave up on snapshot read for txid: {}", txid);
+            MessageType::ClientResultAbort
+        };
+        let result_msg = ProtocolMessage::generate(
+            result_msg_type,
+            txid.clone(),
+            "coordinator".to_string(),
+            txn.opid,
+        );
+        if let Some(tx) = self.client_map.get(&txn.client_id) {
+            tx.send(result_msg).unwrap_or(());
+        }
+    }
+
+    ///
+    /// decide()
+    /// Phase 2: logs the global decision for a transaction whose votes are
+    /// in, sends it to all participants that did not vote ReadOnly and
+    /// reports the result to the client. Under MVCC a commit is assigned the
+    /// next commit timestamp here. A one-phase transaction was decided by its
+    /// participant, so only the outcome it reported is logged.
+    ///
+    fn decide(&mut self, txid: &String, commit_decision: bool) {
+        let mut txn = match self.transactions.remove(txid) {
+            Some(txn) => txn,
+            None => return,
+        };
+        self.waits.remove(txid);
+        let recipients = if txn.one_phase.is_some() { HashSet::default() } else { self.phase_two_participants(&txn) };
+
+        let (decision_msg_type, result_msg_type) = if commit_decision {
+            self.successful_ops += 1;
+            info!("Coordinator decided COMMIT for txid: {}", txid);
+            (MessageType::CoordinatorCommit, MessageType::ClientResultCommit)
+        } else {
+            self.failed_ops += 1;
+            info!("Coordinator decided ABORT for txid: {}", txid);
+            (MessageType::CoordinatorAbort, MessageType::ClientResultAbort)
+        };
+
+        let timestamp = match (&txn.one_phase, commit_decision) {
+            (Some(request), true) => request.timestamp,
+            (None, true) => self.next_timestamp(),
+            (_, false) => 0,
+        };
+
+        // Log the decision
+        self.log_decision(decision_msg_type, txid.clone(), txn.opid, timestamp);
+        self.crashes.reach(CrashPoint::AfterLogDecision, txid);
+        self.decisions.insert(txid.clone(), (decision_msg_type, timestamp));
+
+        // Send decision to every participant still taking part
+        let mut decision_msg = ProtocolMessage::generate(
+            decision_msg_type,
+            txid.clone(),
+            "coordinator".to_string(),
+            txn.opid,
+        );
+        decision_msg.timestamp = timestamp;
+
+        for name in recipients.iter() {
+            if let Some(tx) = self.participant_map.get(name) {
+                tx.send(decision_msg.clone()).unwrap_or(());
+            }
+        }
+        if self.logging.acknowledges(decision_msg_type) && txn.one_phase.is_none() {
+            self.await_acks(txid, decision_msg_type, txn.opid, timestamp, recipients);
+        }
+
+        // Send result to client
+        let result_msg = ProtocolMessage::generate(
+            result_msg_type,
+            txid.clone(),
+            "coordinator".to_string(),
+            txn.opid,
+        );
+
+        if let Some(tx) = self.client_map.get(&txn.client_id) {
+            tx.send(result_msg).unwrap_or(());
+        }
+
+        txn.state = CoordinatorState::SentGlobalDecision;
+        trace!("Coordinator finished txid: {} in state {:?}", txid, txn.state);
+    }
+
+    ///
+    /// step()
+    /// One round of the coordinator protocol: takes in whoever rejoined, new
+    /// requests and participant messages, then checks on timeouts. Returns
+    /// true if anything was received.
+    ///
+    pub fn step(&mut self) -> bool {
+        if self.recovering {
+            self.recover();
+        }
+
+        let mut busy = self.poll_rejoins();
+        busy |= self.poll_clients();
+        busy |= self.poll_participants();
+        self.check_members();
+        self.check_transactions();
+        self.check_unacked();
+        busy
+    }
+
+    ///
+    /// shutdown()
+    /// Counts whatever is still in flight as unknown and tells all clients
+    /// and participants to exit
+    ///
+    pub fn shutdown(&mut self) {
+        // Anything still in flight never got a decision
+        self.unknown_ops += self.transactions.len() as u64;
+
+        // Send exit messages to all clients and participants
+        for (name, tx) in self.client_map.iter() {
+            let exit_msg = ProtocolMessage::generate(
+                MessageType::CoordinatorExit,
+                "exit".to_string(),
+                "coordinator".to_string(),
+                0,
+            );
+            tx.send(exit_msg).unwrap_or(());
+            trace!("Sent exit to client: {}", name);
+        }
+
+        for (name, tx) in self.participant_map.iter() {
+            let exit_msg = ProtocolMessage::generate(
+                MessageType::CoordinatorExit,
+                "exit".to_string(),
+                "coordinator".to_string(),
+                0,
+            );
+            tx.send(exit_msg).unwrap_or(());
+            trace!("Sent exit to participant: {}", name);
+        }
+    }
+
+    ///
+    /// protocol()
+    /// Implements the coordinator side of the 2PC protocol. Requests from
+    /// different clients are processed concurrently: each one becomes a
+    /// Transaction tracked by txid until its votes are in.
+    /// HINT: If the simulation ends early, don't keep handling requests!
+    /// HINT: Wait for some kind of exit signal before returning from the protocol!
+    ///
+    pub fn protocol(&mut self) {
+        while self.running.load(Ordering::SeqCst) {
+            if !self.step() {
+                clock::sleep(Duration::from_millis(1));
+            }
+        }
+
+        self.shutdown();
+
+        // Give children time to receive and process exit messages
+        clock::sleep(Duration::from_millis(50));
+
+        self.report_status();
+    }
+}
@@ -0,0 +1,288 @@
+//!
+//! crashpoint.rs
+//! Process crash injection. The coordinator and participants pass named
+//! points of their protocols, and a CrashPlan makes a process exit on the
+//! spot at some of them, leaving behind exactly what it had logged. A
+//! scenario can also script a crash right after a given log record. The
+//! "run" harness then restarts crashed participants to recover from their
+//! logs, which is what exercises the logging for durability. It cannot
+//! restart the coordinator it runs in, so it refuses plans that crash it.
+//!
+use std::process;
+use std::sync::Mutex;
+
+use clock;
+use message::MessageType;
+use scenario;
+use scenario::LogTrigger;
+
+/// What the process does right before it crashes on purpose, if anything
+type CrashHook = Box<dyn FnOnce() + Send>;
+
+static BEFORE_CRASH: Mutex<Option<CrashHook>> = Mutex::new(None);
+
+///
+/// before_crash(hook)
+/// Makes crash() run hook first, e.g. so a crashing "run" harness takes its
+/// child processes down with it instead of leaving them running
+///
+pub fn before_crash<F: FnOnce() + Send + 'static>(hook: F) {
+    *BEFORE_CRASH.lock().unwrap() = Some(Box::new(hook));
+}
+
+///
+/// crash()
+/// Exits the process with scenario::CRASH_STATUS, after running the hook
+/// set by before_crash(). Holds the hook's lock until the exit, so a
+/// second thread crashing at the same time waits for the first.
+///
+pub fn crash() -> ! {
+    let mut hook = BEFORE_CRASH.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(hook) = hook.take() {
+        hook();
+    }
+    process::exit(scenario::CRASH_STATUS);
+}
+
+///
+/// CrashPoint
+/// A point in the protocol where a process can be made to crash
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CrashPoint {
+    AfterPropose,           // Coordinator: proposal sent to the participants, nothing decided
+    AfterLogVote,           // Participant: commit vote logged, not sent
+    BeforeSendVote,         // Participant: vote about to be sent
+    AfterLogDecision,       // Either: decision logged, not sent (or acknowledged)
+}
+
+impl CrashPoint {
+    pub fn parse(s: &str) -> CrashPoint {
+        match s {
+            "after_propose" => CrashPoint::AfterPropose,
+            "after_log_vote" => CrashPoint::AfterLogVote,
+            "before_send_vote" => CrashPoint::BeforeSendVote,
+            "after_log_decision" => CrashPoint::AfterLogDecision,
+            _ => panic!("unknown crash point: {}", s),
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CrashPoint::AfterPropose => "after_propose",
+            CrashPoint::AfterLogVote => "after_log_vote",
+            CrashPoint::BeforeSendVote => "before_send_vote",
+            CrashPoint::AfterLogDecision => "after_log_decision",
+        }
+    }
+    pub fn in_coordinator(&self) -> bool {
+        matches!(self, CrashPoint::AfterPropose | CrashPoint::AfterLogDecision)
+    }
+}
+
+///
+/// CrashPlan
+/// Parsed from a spec of comma-separated "[PROCESS:]POINT[=PROBABILITY]"
+/// entries. PROCESS is a process name ("coordinator", "participant_1"),
+/// where a trailing '*' matches any suffix; without one the entry applies
+/// to every process that passes POINT. Without a probability a process
+/// crashes the first time it reaches the point, but not again once
+/// restarted to recover; with one it crashes there with that probability,
+/// every time. "none" crashes nothing.
+///
+#[derive(Clone, Debug)]
+pub struct CrashPlan {
+    spec: String,
+    entries: Vec<(Option<String>, CrashPoint, Option<f64>)>,
+}
+
+impl CrashPlan {
+
+    ///
+    /// parse(spec)
+    /// Builds the plan described by spec. Panics on a malformed spec, like
+    /// the other option parsers.
+    ///
+    pub fn parse(spec: &str) -> CrashPlan {
+        let mut entries = Vec::new();
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty() && *e != "none") {
+            let (process, point) = match entry.split_once(':') {
+                Some((process, point)) => (Some(process.to_string()), point),
+                None => (None, entry),
+            };
+            let (point, probability) = match point.split_once('=') {
+                Some((point, p)) => {
+                    let p = p.parse::<f64>().expect("crash probability must be a number");
+                    if !(0.0..=1.0).contains(&p) {
+                        panic!("crash probability out of range: {}", entry);
+                    }
+                    (point, Some(p))
+                },
+                None => (point, None),
+            };
+            entries.push((process, CrashPoint::parse(point), probability));
+        }
+        CrashPlan { spec: spec.to_string(), entries }
+    }
+
+    ///
+    /// as_str()
+    /// The spec this plan was parsed from
+    ///
+    pub fn as_str(&self) -> &str {
+        &self.spec
+    }
+
+    ///
+    /// is_empty()
+    /// True if the plan crashes nothing
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    ///
+    /// covers(name)
+    /// True if process name may be crashed by the plan
+    ///
+    pub fn covers(&self, name: &str) -> bool {
+        self.entries.iter().any(|(process, _, _)| Self::matches(process, name))
+    }
+
+    ///
+    /// crashes_coordinator()
+    /// True if the plan may crash the coordinator at a point it passes
+    ///
+    pub fn crashes_coordinator(&self) -> bool {
+        self.entries.iter().any(|(process, point, _)| Self::matches(process, "coordinator") && point.in_coordinator())
+    }
+
+    ///
+    /// arm(name, recovering)
+    /// The crash points of process name. A recovering process only gets
+    /// the ones with a probability.
+    ///
+    pub fn arm(&self, name: &str, recovering: bool) -> Crashes {
+        let points = self.entries.iter()
+            .filter(|(process, _, p)| Self::matches(process, name) && !(recovering && p.is_none()))
+            .map(|(_, point, p)| (*point, p.unwrap_or(1.0)))
+            .collect();
+        Crashes { name: name.to_string(), points, scripted: None }
+    }
+
+    fn matches(process: &Option<String>, name: &str) -> bool {
+        match process {
+            None => true,
+            Some(pattern) => match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => pattern == name,
+            },
+        }
+    }
+}
+
+///
+/// Crashes
+/// The crash points one process is armed with, and how likely each one is
+/// to fire, plus the log record a scenario makes it crash after
+///
+#[derive(Clone, Debug)]
+pub struct Crashes {
+    name: String,
+    points: Vec<(CrashPoint, f64)>,
+    scripted: Option<LogTrigger>,
+}
+
+impl Crashes {
+
+    ///
+    /// reach(point, txid)
+    /// Called as the process passes point while working on txid. Crashes
+    /// the process (see crash()) if the point fires.
+    ///
+    pub fn reach(&self, point: CrashPoint, txid: &str) {
+        for (armed, p) in self.points.iter() {
+            if *armed == point && (*p >= 1.0 || clock::random::<f64>() < *p) {
+                error!("{}::Crashing at {} for txid: {}", self.name, point.as_str(), txid);
+                crash();
+            }
+        }
+    }
+
+    ///
+    /// crash_after(trigger)
+    /// Makes the process crash as soon as it logged a record matching
+    /// trigger, as a scenario scripts it
+    ///
+    pub fn crash_after(&mut self, trigger: LogTrigger) {
+        self.scripted = Some(trigger);
+    }
+
+    ///
+    /// logged(mtype, txid)
+    /// Called right after the process appended a record to its log. Crashes
+    /// the process (see crash()) if the scenario crashes it there.
+    ///
+    pub fn logged(&self, mtype: MessageType, txid: &str) {
+        if self.scripted.as_ref().is_some_and(|t| t.mtype == mtype && t.txid == txid) {
+            error!("{}::Crashing after logging {:?} for txid: {}, as scripted", self.name, mtype, txid);
+            crash();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn none_crashes_nothing() {
+        let plan = CrashPlan::parse("none");
+        assert!(plan.is_empty());
+        assert!(!plan.covers("participant_0"));
+        assert!(plan.arm("participant_0", false).points.is_empty());
+    }
+
+    #[test]
+    fn points_round_trip() {
+        for name in ["after_propose", "after_log_vote", "before_send_vote", "after_log_decision"].iter() {
+            assert_eq!(CrashPoint::parse(name).as_str(), *name);
+        }
+    }
+
+    #[test]
+    fn entries_match_processes() {
+        let plan = CrashPlan::parse("participant_*:after_log_vote=0.5, participant_1:before_send_vote");
+        assert!(plan.covers("participant_3"));
+        assert!(!plan.covers("coordinator"));
+        assert_eq!(plan.arm("participant_0", false).points, vec![(CrashPoint::AfterLogVote, 0.5)]);
+        assert_eq!(plan.arm("participant_1", false).points,
+                   vec![(CrashPoint::AfterLogVote, 0.5), (CrashPoint::BeforeSendVote, 1.0)]);
+        assert!(plan.arm("coordinator", false).points.is_empty());
+    }
+
+    #[test]
+    fn recovering_process_keeps_only_probabilistic_points() {
+        let plan = CrashPlan::parse("after_log_vote, after_log_decision=0.1");
+        assert_eq!(plan.arm("participant_2", true).points, vec![(CrashPoint::AfterLogDecision, 0.1)]);
+    }
+
+    #[test]
+    fn tells_whether_the_coordinator_can_crash() {
+        assert!(CrashPlan::parse("after_log_decision").crashes_coordinator());
+        assert!(CrashPlan::parse("coordinator:after_propose").crashes_coordinator());
+        assert!(!CrashPlan::parse("after_log_vote").crashes_coordinator());
+        assert!(!CrashPlan::parse("participant_*:after_log_decision").crashes_coordinator());
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown crash point")]
+    fn rejects_unknown_point() {
+        CrashPlan::parse("participant_0:before_commit");
+    }
+
+    #[test]
+    #[should_panic(expected = "crash probability out of range")]
+    fn rejects_bad_probability() {
+        CrashPlan::parse("after_log_vote=2");
+    }
+}
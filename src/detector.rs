@@ -0,0 +1,171 @@
+//!
+//! detector.rs
+//! Heartbeat failure detector the coordinator keeps on its participants.
+//! Participants send a heartbeat every HEARTBEAT_INTERVAL; any message
+//! counts as a sign of life. A participant not heard from for a while is
+//! suspected, then declared down, and is up again as soon as it is heard.
+//!
+use std::time::Duration;
+use std::time::Instant;
+
+use clock;
+use fixedhash::HashMap;
+
+/// How often participants send a heartbeat
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Silence after which a participant is suspected (a few heartbeats lost)
+const SUSPECT_AFTER: Duration = Duration::from_millis(150);
+
+/// Silence after which a participant is declared down
+const DOWN_AFTER: Duration = Duration::from_millis(400);
+
+///
+/// Membership
+/// What the coordinator believes about a participant
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Membership {
+    Up,
+    Suspected,
+    Down,
+}
+
+///
+/// FailureDetector
+/// When each participant was last heard from, and what that makes it
+///
+#[derive(Debug, Default)]
+pub struct FailureDetector {
+    members: HashMap<String, (Instant, Membership)>,
+}
+
+impl FailureDetector {
+
+    ///
+    /// new()
+    /// Returns a detector that knows no participants yet
+    ///
+    pub fn new() -> FailureDetector {
+        FailureDetector::default()
+    }
+
+    ///
+    /// heard(name)
+    /// Records a sign of life from name. Returns Some(Up) if that changes
+    /// what we believe about it.
+    ///
+    pub fn heard(&mut self, name: &str) -> Option<Membership> {
+        match self.members.insert(name.to_string(), (clock::now(), Membership::Up)) {
+            Some((_, Membership::Up)) | None => None,
+            Some(_) => Some(Membership::Up),
+        }
+    }
+
+    ///
+    /// check()
+    /// Suspects or declares down the participants that have been silent too
+    /// long. Returns the changes, in no particular order.
+    ///
+    pub fn check(&mut self) -> Vec<(String, Membership)> {
+        let mut changes = Vec::new();
+        for (name, (last_heard, membership)) in self.members.iter_mut() {
+            let silence = clock::elapsed(*last_heard);
+            let now = if silence > DOWN_AFTER {
+                Membership::Down
+            } else if silence > SUSPECT_AFTER {
+                Membership::Suspected
+            } else {
+                Membership::Up
+            };
+            if now != *membership {
+                *membership = now;
+                changes.push((name.clone(), now));
+            }
+        }
+        changes
+    }
+
+    ///
+    /// is_down(name)
+    /// True if name has been declared down
+    ///
+    pub fn is_down(&self, name: &str) -> bool {
+        self.members.get(name).is_some_and(|(_, m)| *m == Membership::Down)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A detector on virtual time, which the tests advance by hand
+    fn detector() -> FailureDetector {
+        clock::simulate(0);
+        FailureDetector::new()
+    }
+
+    #[test]
+    fn unknown_participant_is_not_down() {
+        let mut detector = detector();
+        assert!(!detector.is_down("participant_0"));
+        assert!(detector.check().is_empty());
+        assert_eq!(detector.heard("participant_0"), None);
+    }
+
+    #[test]
+    fn silence_suspects_then_declares_down() {
+        let mut detector = detector();
+        detector.heard("participant_0");
+        clock::advance(SUSPECT_AFTER);
+        assert!(detector.check().is_empty());
+
+        clock::advance(Duration::from_millis(1));
+        assert_eq!(detector.check(), vec![("participant_0".to_string(), Membership::Suspected)]);
+        assert!(!detector.is_down("participant_0"));
+        // Changes are only reported once
+        assert!(detector.check().is_empty());
+
+        clock::advance(DOWN_AFTER - SUSPECT_AFTER);
+        assert_eq!(detector.check(), vec![("participant_0".to_string(), Membership::Down)]);
+        assert!(detector.is_down("participant_0"));
+    }
+
+    #[test]
+    fn long_silence_goes_straight_to_down() {
+        let mut detector = detector();
+        detector.heard("participant_0");
+        clock::advance(DOWN_AFTER * 2);
+        assert_eq!(detector.check(), vec![("participant_0".to_string(), Membership::Down)]);
+    }
+
+    #[test]
+    fn heard_brings_participant_back_up() {
+        let mut detector = detector();
+        detector.heard("participant_0");
+        clock::advance(DOWN_AFTER * 2);
+        detector.check();
+        assert_eq!(detector.heard("participant_0"), Some(Membership::Up));
+        assert!(!detector.is_down("participant_0"));
+        assert!(detector.check().is_empty());
+
+        // A suspected participant heard from is up again too
+        clock::advance(SUSPECT_AFTER * 2);
+        detector.check();
+        assert_eq!(detector.heard("participant_0"), Some(Membership::Up));
+    }
+
+    #[test]
+    fn heartbeats_keep_participant_up() {
+        let mut detector = detector();
+        detector.heard("participant_0");
+        detector.heard("participant_1");
+        for _ in 0..20 {
+            clock::advance(HEARTBEAT_INTERVAL);
+            detector.heard("participant_0");
+            detector.check();
+        }
+        assert!(!detector.is_down("participant_0"));
+        assert!(detector.is_down("participant_1"));
+    }
+}
@@ -0,0 +1,415 @@
+//!
+//! faults.rs
+//! Network fault injection. FaultyTransport wraps the sending side of any
+//! transport and drops, delays, duplicates or reorders what goes through
+//! it, as configured per link by a FaultPlan. Every process wraps its own
+//! ends of its links, so each direction of a link fails independently. A
+//! link can also be cut altogether for a while (see scenario.rs).
+//!
+use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::time::Instant;
+
+use clock;
+use message::ProtocolMessage;
+use transport::SendError;
+use transport::Transport;
+use transport::TryRecvError;
+
+/// A message held back to be reordered goes out after the next one on its
+/// link, or after this long if nothing else is sent
+const REORDER_WINDOW: Duration = Duration::from_millis(5);
+
+///
+/// Delay
+/// How long a message is held back before it is sent
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Delay {
+    Zero,
+    Uniform(f64, f64),      // Milliseconds, uniformly distributed between the two
+    Exponential(f64),       // Milliseconds, exponentially distributed with this mean
+}
+
+impl Delay {
+    fn parse(s: &str) -> Delay {
+        let bad = || panic!("fault delay must be \"LO-HI\" or \"expMEAN\" (milliseconds): {}", s);
+        if let Some(mean) = s.strip_prefix("exp") {
+            return Delay::Exponential(mean.parse::<f64>().unwrap_or_else(|_| bad()));
+        }
+        match s.split_once('-') {
+            Some((lo, hi)) => {
+                let lo = lo.parse::<f64>().unwrap_or_else(|_| bad());
+                let hi = hi.parse::<f64>().unwrap_or_else(|_| bad());
+                if lo > hi {
+                    bad();
+                }
+                Delay::Uniform(lo, hi)
+            },
+            None => {
+                let ms = s.parse::<f64>().unwrap_or_else(|_| bad());
+                Delay::Uniform(ms, ms)
+            },
+        }
+    }
+
+    fn sample(&self) -> Duration {
+        let ms = match *self {
+            Delay::Zero => 0.0,
+            Delay::Uniform(lo, hi) => lo + (hi - lo) * clock::random::<f64>(),
+            Delay::Exponential(mean) => -mean * (1.0 - clock::random::<f64>()).ln(),
+        };
+        Duration::from_micros((ms * 1000.0) as u64)
+    }
+}
+
+///
+/// LinkFaults
+/// What can go wrong on one direction of one link
+///
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LinkFaults {
+    drop: f64,              // Probability a message is lost
+    duplicate: f64,         // Probability a message is sent twice
+    reorder: f64,           // Probability a message is overtaken by the next one
+    delay: Delay,
+}
+
+impl LinkFaults {
+    fn none() -> LinkFaults {
+        LinkFaults { drop: 0.0, duplicate: 0.0, reorder: 0.0, delay: Delay::Zero }
+    }
+
+    ///
+    /// is_none()
+    /// True if nothing ever goes wrong on the link
+    ///
+    pub fn is_none(&self) -> bool {
+        *self == LinkFaults::none()
+    }
+}
+
+///
+/// Setting
+/// One entry of a FaultPlan
+///
+#[derive(Clone, Copy, Debug)]
+enum Setting {
+    Drop(f64),
+    Duplicate(f64),
+    Reorder(f64),
+    Delay(Delay),
+}
+
+///
+/// FaultPlan
+/// Parsed from a spec of comma-separated "[FROM>TO:]SETTING=VALUE" entries.
+/// SETTING is one of "drop", "duplicate" and "reorder", each taking a
+/// probability, or "delay" taking "LO-HI" (uniform) or "expMEAN"
+/// (exponential) milliseconds. An entry without a link applies to every
+/// link; FROM and TO are process names ("coordinator", "participant_1",
+/// "client_0"), where a trailing '*' matches any suffix. Later entries
+/// override earlier ones, so "drop=0.01,participant_*>coordinator:drop=0.2"
+/// loses 1% of all messages but 20% of those participants send. "none"
+/// injects no faults.
+///
+#[derive(Clone, Debug)]
+pub struct FaultPlan {
+    spec: String,
+    entries: Vec<(Option<(String, String)>, Setting)>,
+}
+
+impl FaultPlan {
+
+    ///
+    /// parse(spec)
+    /// Builds the plan described by spec. Panics on a malformed spec, like
+    /// the other option parsers.
+    ///
+    pub fn parse(spec: &str) -> FaultPlan {
+        let mut entries = Vec::new();
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty() && *e != "none") {
+            let (link, setting) = match entry.split_once(':') {
+                Some((link, setting)) => match link.split_once('>') {
+                    Some((from, to)) => (Some((from.to_string(), to.to_string())), setting),
+                    None => panic!("fault link must be FROM>TO: {}", link),
+                },
+                None => (None, entry),
+            };
+            let (name, value) = match setting.split_once('=') {
+                Some(pair) => pair,
+                None => panic!("unknown fault entry: {}", entry),
+            };
+            let probability = || {
+                let p = value.parse::<f64>().expect("fault probability must be a number");
+                if !(0.0..=1.0).contains(&p) {
+                    panic!("fault probability out of range: {}", entry);
+                }
+                p
+            };
+            let setting = match name {
+                "drop" => Setting::Drop(probability()),
+                "duplicate" => Setting::Duplicate(probability()),
+                "reorder" => Setting::Reorder(probability()),
+                "delay" => Setting::Delay(Delay::parse(value)),
+                _ => panic!("unknown fault setting: {}", name),
+            };
+            entries.push((link, setting));
+        }
+        FaultPlan { spec: spec.to_string(), entries }
+    }
+
+    ///
+    /// as_str()
+    /// The spec this plan was parsed from
+    ///
+    pub fn as_str(&self) -> &str {
+        &self.spec
+    }
+
+    ///
+    /// link(from, to)
+    /// The faults of messages from process from to process to
+    ///
+    pub fn link(&self, from: &str, to: &str) -> LinkFaults {
+        let matches = |pattern: &str, name: &str| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => pattern == name,
+        };
+        let mut faults = LinkFaults::none();
+        for (link, setting) in self.entries.iter() {
+            if let Some((f, t)) = link {
+                if !matches(f, from) || !matches(t, to) {
+                    continue;
+                }
+            }
+            match *setting {
+                Setting::Drop(p) => faults.drop = p,
+                Setting::Duplicate(p) => faults.duplicate = p,
+                Setting::Reorder(p) => faults.reorder = p,
+                Setting::Delay(delay) => faults.delay = delay,
+            }
+        }
+        faults
+    }
+}
+
+///
+/// Held
+/// A message waiting to be sent
+///
+struct Held {
+    release: Instant,
+    after_next: bool,       // Also goes out right after the next message sent
+    msg: ProtocolMessage,
+}
+
+///
+/// FaultyTransport
+/// A transport whose outgoing messages suffer the faults of their link.
+/// Held back messages go out once their time has come, whenever the
+/// transport is used next. While the link is isolated, nothing gets through
+/// in either direction.
+///
+pub struct FaultyTransport<T: Transport> {
+    inner: T,
+    name: String,
+    faults: LinkFaults,
+    held: RefCell<Vec<Held>>,
+    isolated: Arc<AtomicBool>,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+
+    ///
+    /// new(inner, from, to, plan)
+    /// Wraps inner, which carries messages from process from to process to
+    ///
+    pub fn new(inner: T, from: &str, to: &str, plan: &FaultPlan) -> FaultyTransport<T> {
+        FaultyTransport {
+            inner,
+            name: format!("{}>{}", from, to),
+            faults: plan.link(from, to),
+            held: RefCell::new(Vec::new()),
+            isolated: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    ///
+    /// isolation()
+    /// The switch that cuts this link while it is set
+    ///
+    pub fn isolation(&self) -> Arc<AtomicBool> {
+        self.isolated.clone()
+    }
+
+    ///
+    /// discard()
+    /// Loses whatever arrived while the link is cut. Returns Disconnected if
+    /// the other end is gone, Empty otherwise.
+    ///
+    fn discard(&self) -> TryRecvError {
+        loop {
+            match self.inner.try_recv() {
+                Ok(msg) => trace!("faults::{} isolated, losing {:?} for txid: {}", self.name, msg.mtype, msg.txid),
+                Err(e) => return e,
+            }
+        }
+    }
+
+    ///
+    /// release(next_sent)
+    /// Sends the held messages that are due, in the order they are due.
+    /// next_sent says whether a message just went out, which is what
+    /// reordered messages wait for.
+    ///
+    fn release(&self, next_sent: bool) {
+        let now = clock::now();
+        let mut due: Vec<Held> = Vec::new();
+        {
+            let mut held = self.held.borrow_mut();
+            let mut i = 0;
+            while i < held.len() {
+                if held[i].release <= now || (next_sent && held[i].after_next) {
+                    due.push(held.remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        due.sort_by_key(|h| h.release);
+        let isolated = self.isolated.load(Ordering::SeqCst);
+        for h in due {
+            if isolated {
+                trace!("faults::{} isolated, losing {:?} for txid: {}", self.name, h.msg.mtype, h.msg.txid);
+            } else {
+                self.inner.send(h.msg).unwrap_or(());
+            }
+        }
+    }
+
+    ///
+    /// submit(msg)
+    /// Sends one copy of msg, or holds it back. Returns true if it went out.
+    ///
+    fn submit(&self, msg: ProtocolMessage) -> Result<bool, SendError> {
+        let delay = self.faults.delay.sample();
+        let reorder = self.faults.reorder > 0.0 && clock::random::<f64>() < self.faults.reorder;
+        if delay == Duration::from_millis(0) && !reorder {
+            return self.inner.send(msg).map(|_| true);
+        }
+        trace!("faults::{} holding back {:?} for txid: {} ({:?}{})", self.name, msg.mtype, msg.txid,
+               delay, if reorder { ", reordered" } else { "" });
+        let release = clock::now() + delay + if reorder { REORDER_WINDOW } else { Duration::from_millis(0) };
+        self.held.borrow_mut().push(Held { release, after_next: reorder, msg });
+        Ok(false)
+    }
+}
+
+impl<T: Transport> Transport for FaultyTransport<T> {
+    fn send(&self, msg: ProtocolMessage) -> Result<(), SendError> {
+        self.release(false);
+        if self.isolated.load(Ordering::SeqCst) {
+            trace!("faults::{} isolated, losing {:?} for txid: {}", self.name, msg.mtype, msg.txid);
+            return Ok(());
+        }
+        if self.faults.is_none() {
+            return self.inner.send(msg);
+        }
+        if clock::random::<f64>() < self.faults.drop {
+            trace!("faults::{} dropping {:?} for txid: {}", self.name, msg.mtype, msg.txid);
+            return Ok(());
+        }
+        let mut sent = false;
+        if self.faults.duplicate > 0.0 && clock::random::<f64>() < self.faults.duplicate {
+            trace!("faults::{} duplicating {:?} for txid: {}", self.name, msg.mtype, msg.txid);
+            sent |= self.submit(msg.clone())?;
+        }
+        sent |= self.submit(msg)?;
+        if sent {
+            self.release(true);
+        }
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<ProtocolMessage, TryRecvError> {
+        self.release(false);
+        if self.isolated.load(Ordering::SeqCst) {
+            return Err(self.discard());
+        }
+        self.inner.try_recv()
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<ProtocolMessage, TryRecvError> {
+        self.release(false);
+        if self.isolated.load(Ordering::SeqCst) {
+            return Err(self.discard());
+        }
+        self.inner.recv_timeout(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn none_injects_nothing() {
+        let plan = FaultPlan::parse("none");
+        assert!(plan.link("coordinator", "participant_0").is_none());
+        assert_eq!(plan.as_str(), "none");
+    }
+
+    #[test]
+    fn entry_without_link_applies_everywhere() {
+        let plan = FaultPlan::parse("drop=0.1, delay=2-4");
+        let faults = plan.link("client_0", "coordinator");
+        assert_eq!(faults.drop, 0.1);
+        assert_eq!(faults.delay, Delay::Uniform(2.0, 4.0));
+        assert_eq!(plan.link("coordinator", "participant_2"), faults);
+    }
+
+    #[test]
+    fn later_entries_override_on_matching_links() {
+        let plan = FaultPlan::parse("drop=0.01,participant_*>coordinator:drop=0.2,coordinator>participant_1:reorder=0.5");
+        assert_eq!(plan.link("participant_3", "coordinator").drop, 0.2);
+        assert_eq!(plan.link("coordinator", "participant_3").drop, 0.01);
+        assert_eq!(plan.link("coordinator", "participant_1").reorder, 0.5);
+        assert_eq!(plan.link("coordinator", "participant_10").reorder, 0.0);
+    }
+
+    #[test]
+    fn parses_delays() {
+        assert_eq!(Delay::parse("3"), Delay::Uniform(3.0, 3.0));
+        assert_eq!(Delay::parse("1-5"), Delay::Uniform(1.0, 5.0));
+        assert_eq!(Delay::parse("exp2.5"), Delay::Exponential(2.5));
+        assert_eq!(Delay::Zero.sample(), Duration::from_millis(0));
+        assert_eq!(Delay::Uniform(3.0, 3.0).sample(), Duration::from_millis(3));
+    }
+
+    #[test]
+    #[should_panic(expected = "fault probability out of range")]
+    fn rejects_bad_probability() {
+        FaultPlan::parse("duplicate=1.5");
+    }
+
+    #[test]
+    #[should_panic(expected = "fault link must be FROM>TO")]
+    fn rejects_link_without_direction() {
+        FaultPlan::parse("coordinator:drop=0.1");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown fault setting")]
+    fn rejects_unknown_setting() {
+        FaultPlan::parse("corrupt=0.1");
+    }
+
+    #[test]
+    #[should_panic(expected = "fault delay must be")]
+    fn rejects_inverted_delay_range() {
+        FaultPlan::parse("delay=5-1");
+    }
+}
@@ -0,0 +1,12 @@
+//!
+//! fixedhash.rs
+//! HashMap and HashSet with a fixed hasher. std's default hasher is keyed
+//! randomly per process, so the roles would iterate their maps in a
+//! different order on every run and a simulation could not be replayed
+//! from its seed. Build them with default() rather than new().
+//!
+use std::collections::hash_map::DefaultHasher;
+use std::hash::BuildHasherDefault;
+
+pub type HashMap<K, V> = std::collections::HashMap<K, V, BuildHasherDefault<DefaultHasher>>;
+pub type HashSet<T> = std::collections::HashSet<T, BuildHasherDefault<DefaultHasher>>;
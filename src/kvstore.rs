@@ -0,0 +1,322 @@
+//!
+//! kvstore.rs
+//! Key-value store each participant manages as its resource. Writes of a
+//! transaction are staged when it votes and only become visible once the
+//! global decision commits them; an abort discards them. A multi-version
+//! store keeps every committed version, tagged with the commit timestamp
+//! the coordinator assigned, so transactions can read a snapshot.
+//!
+use fixedhash::HashMap;
+use message::Operation;
+
+///
+/// KvStore
+/// Committed data plus the writes staged by transactions awaiting a decision
+///
+#[derive(Debug, Default)]
+pub struct KvStore {
+    multiversion: bool,
+    versions: HashMap<String, Vec<(u64, Option<String>)>>,  // Oldest first; None: deleted
+    staged: HashMap<String, Vec<(String, Option<String>)>>,   // None deletes the key
+    latest_ts: u64,
+}
+
+impl KvStore {
+
+    ///
+    /// new(multiversion)
+    /// Returns an empty store. A single-version store only keeps the latest
+    /// committed value of each key.
+    ///
+    pub fn new(multiversion: bool) -> KvStore {
+        KvStore {
+            multiversion,
+            versions: HashMap::default(),
+            staged: HashMap::default(),
+            latest_ts: 0,
+        }
+    }
+
+    ///
+    /// get(key)
+    /// Returns the latest committed value of key, if any
+    ///
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.versions.get(key).and_then(|v| v.last()).and_then(|(_, value)| value.as_ref())
+    }
+
+    ///
+    /// get_at(key, ts)
+    /// Returns the value of key in the snapshot at ts: the latest version
+    /// committed at or before ts
+    ///
+    pub fn get_at(&self, key: &str, ts: u64) -> Option<&String> {
+        self.versions.get(key)
+            .and_then(|v| v.iter().rev().find(|(vts, _)| *vts <= ts))
+            .and_then(|(_, value)| value.as_ref())
+    }
+
+    ///
+    /// len()
+    /// Number of keys that currently have a committed value
+    ///
+    pub fn len(&self) -> usize {
+        self.versions.keys().filter(|key| self.get(key).is_some()).count()
+    }
+
+    ///
+    /// is_empty()
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    ///
+    /// staged_by(key)
+    /// Returns the txid that has a write to key staged, if any
+    ///
+    pub fn staged_by(&self, key: &str) -> Option<&String> {
+        self.staged.iter()
+            .find(|(_, writes)| writes.iter().any(|(k, _)| k == key))
+            .map(|(txid, _)| txid)
+    }
+
+    ///
+    /// stage(txid, ops, snapshot)
+    /// Executes the operations of a transaction in order and stages its
+    /// writes. Reads see the latest committed data, or the snapshot at
+    /// snapshot if one is given. Fails (and stages nothing) if any key it
+    /// touches has a write staged by another transaction, since reading or
+    /// overwriting it would depend on an outcome nobody knows yet, or if a
+    /// compare-and-swap does not find the expected value. Under a snapshot
+    /// a write also fails if the key was committed after the snapshot
+    /// (first committer wins). Returns whether the transaction can commit.
+    ///
+    pub fn stage(&mut self, txid: &str, ops: &[Operation], snapshot: Option<u64>) -> bool {
+        for op in ops.iter() {
+            let key = op.key();
+            if let Some(owner) = self.staged_by(key) {
+                if owner != txid {
+                    trace!("kvstore::{} conflicts with {} on key {}", txid, owner, key);
+                    return false;
+                }
+            }
+            if let Some(ts) = snapshot {
+                let committed_ts = self.versions.get(key).and_then(|v| v.last()).map_or(0, |(vts, _)| *vts);
+                if op.is_write() && committed_ts > ts {
+                    trace!("kvstore::{} write to {} conflicts with a commit at {} after snapshot {}", txid, key, committed_ts, ts);
+                    return false;
+                }
+            }
+        }
+        self.stage_writes(txid, ops, snapshot, true)
+    }
+
+    ///
+    /// redo(txid, ops)
+    /// Stages the writes of a transaction that already voted to commit (e.g.
+    /// replayed from the log), without re-checking its compare-and-swaps
+    ///
+    pub fn redo(&mut self, txid: &str, ops: &[Operation]) {
+        self.stage_writes(txid, ops, None, false);
+    }
+
+    ///
+    /// stage_writes(txid, ops, snapshot, check)
+    /// Runs ops against the committed data (at snapshot, if given) overlaid
+    /// with the transaction's own earlier writes, and stages the writes if
+    /// every check passes
+    ///
+    fn stage_writes(&mut self, txid: &str, ops: &[Operation], snapshot: Option<u64>, check: bool) -> bool {
+        let mut writes: Vec<(String, Option<String>)> = Vec::new();
+        for op in ops.iter() {
+            let current = match writes.iter().rev().find(|(k, _)| k == op.key()) {
+                Some((_, value)) => value.clone(),
+                None => match snapshot {
+                    Some(ts) => self.get_at(op.key(), ts).cloned(),
+                    None => self.get(op.key()).cloned(),
+                },
+            };
+            match op {
+                Operation::Get(key) => {
+                    trace!("kvstore::{} read {} = {:?}", txid, key, current);
+                },
+                Operation::Put(key, value) => writes.push((key.clone(), Some(value.clone()))),
+                Operation::Delete(key) => writes.push((key.clone(), None)),
+                Operation::CompareAndSwap(key, expected, value) => {
+                    if check && current != *expected {
+                        trace!("kvstore::{} compare-and-swap on {} expected {:?}, found {:?}", txid, key, expected, current);
+                        return false;
+                    }
+                    writes.push((key.clone(), Some(value.clone())));
+                },
+            }
+        }
+        if !writes.is_empty() {
+            self.staged.insert(txid.to_string(), writes);
+        }
+        true
+    }
+
+    ///
+    /// read_at(txid, ops, ts)
+    /// Serves the reads of a read-only transaction from the snapshot at ts
+    ///
+    pub fn read_at(&self, txid: &str, ops: &[Operation], ts: u64) {
+        for op in ops.iter() {
+            trace!("kvstore::{} read {} = {:?} at {}", txid, op.key(), self.get_at(op.key(), ts), ts);
+        }
+    }
+
+    ///
+    /// commit(txid, ts)
+    /// Applies the writes staged by txid as versions at commit timestamp ts.
+    /// A ts of 0 means the commit timestamp is unknown (e.g. the transaction
+    /// was terminated without the coordinator); the writes are then placed
+    /// at the latest timestamp seen.
+    ///
+    pub fn commit(&mut self, txid: &str, ts: u64) {
+        let ts = if ts == 0 { self.latest_ts } else { ts };
+        if ts > self.latest_ts {
+            self.latest_ts = ts;
+        }
+        if let Some(writes) = self.staged.remove(txid) {
+            for (key, value) in writes {
+                let versions = self.versions.entry(key).or_default();
+                if !self.multiversion {
+                    versions.clear();
+                }
+                versions.push((ts, value));
+            }
+        }
+    }
+
+    ///
+    /// abort(txid)
+    /// Discards the writes staged by txid
+    ///
+    pub fn abort(&mut self, txid: &str) {
+        self.staged.remove(txid);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(key: &str, value: &str) -> Operation {
+        Operation::Put(key.to_string(), value.to_string())
+    }
+
+    fn cas(key: &str, expected: Option<&str>, value: &str) -> Operation {
+        Operation::CompareAndSwap(key.to_string(), expected.map(|e| e.to_string()), value.to_string())
+    }
+
+    #[test]
+    fn staged_writes_are_visible_only_once_committed() {
+        let mut store = KvStore::new(false);
+        assert!(store.stage("t1", &[put("k", "a")], None));
+        assert_eq!(store.get("k"), None);
+        store.commit("t1", 0);
+        assert_eq!(store.get("k"), Some(&"a".to_string()));
+    }
+
+    #[test]
+    fn abort_discards_staged_writes() {
+        let mut store = KvStore::new(false);
+        assert!(store.stage("t1", &[put("k", "a")], None));
+        store.abort("t1");
+        store.commit("t1", 0);
+        assert_eq!(store.get("k"), None);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn staged_write_blocks_other_transactions() {
+        let mut store = KvStore::new(false);
+        assert!(store.stage("t1", &[put("k", "a")], None));
+        assert!(!store.stage("t2", &[Operation::Get("k".to_string())], None));
+        assert!(store.stage("t2", &[put("j", "b")], None));
+        assert_eq!(store.staged_by("k"), Some(&"t1".to_string()));
+    }
+
+    #[test]
+    fn compare_and_swap_checks_expected_value() {
+        let mut store = KvStore::new(false);
+        assert!(!store.stage("t1", &[cas("k", Some("a"), "b")], None));
+        assert!(store.stage("t1", &[cas("k", None, "a")], None));
+        store.commit("t1", 0);
+        assert!(!store.stage("t2", &[cas("k", None, "b")], None));
+        assert!(store.stage("t2", &[cas("k", Some("a"), "b")], None));
+        store.commit("t2", 0);
+        assert_eq!(store.get("k"), Some(&"b".to_string()));
+    }
+
+    #[test]
+    fn compare_and_swap_sees_own_earlier_writes() {
+        let mut store = KvStore::new(false);
+        assert!(store.stage("t1", &[put("k", "a"), cas("k", Some("a"), "b")], None));
+        store.commit("t1", 0);
+        assert_eq!(store.get("k"), Some(&"b".to_string()));
+    }
+
+    #[test]
+    fn redo_skips_compare_and_swap_checks() {
+        let mut store = KvStore::new(false);
+        store.redo("t1", &[cas("k", Some("missing"), "a")]);
+        store.commit("t1", 0);
+        assert_eq!(store.get("k"), Some(&"a".to_string()));
+    }
+
+    #[test]
+    fn snapshot_sees_versions_committed_at_or_before_it() {
+        let mut store = KvStore::new(true);
+        assert!(store.stage("t1", &[put("k", "a")], None));
+        store.commit("t1", 10);
+        assert!(store.stage("t2", &[put("k", "b")], None));
+        store.commit("t2", 20);
+        assert!(store.stage("t3", &[Operation::Delete("k".to_string())], None));
+        store.commit("t3", 30);
+        assert_eq!(store.get_at("k", 5), None);
+        assert_eq!(store.get_at("k", 10), Some(&"a".to_string()));
+        assert_eq!(store.get_at("k", 25), Some(&"b".to_string()));
+        assert_eq!(store.get_at("k", 30), None);
+        assert_eq!(store.get("k"), None);
+    }
+
+    #[test]
+    fn single_version_store_keeps_latest_only() {
+        let mut store = KvStore::new(false);
+        assert!(store.stage("t1", &[put("k", "a")], None));
+        store.commit("t1", 10);
+        assert!(store.stage("t2", &[put("k", "b")], None));
+        store.commit("t2", 20);
+        assert_eq!(store.get_at("k", 15), None);
+        assert_eq!(store.get("k"), Some(&"b".to_string()));
+    }
+
+    #[test]
+    fn snapshot_reads_ignore_later_commits() {
+        let mut store = KvStore::new(true);
+        assert!(store.stage("t1", &[put("k", "a")], None));
+        store.commit("t1", 10);
+        assert!(store.stage("t2", &[put("k", "b")], None));
+        store.commit("t2", 20);
+        // A compare-and-swap at snapshot 15 reads "a", but its write
+        // conflicts with the commit at 20
+        assert!(!store.stage("t3", &[cas("k", Some("a"), "c")], Some(15)));
+        assert!(store.stage("t3", &[cas("j", None, "c")], Some(15)));
+        assert!(store.stage("t4", &[cas("k", Some("b"), "c")], Some(20)));
+    }
+
+    #[test]
+    fn unknown_commit_timestamp_uses_latest_seen() {
+        let mut store = KvStore::new(true);
+        assert!(store.stage("t1", &[put("k", "a")], None));
+        store.commit("t1", 10);
+        assert!(store.stage("t2", &[put("j", "b")], None));
+        store.commit("t2", 0);
+        assert_eq!(store.get_at("j", 9), None);
+        assert_eq!(store.get_at("j", 10), Some(&"b".to_string()));
+    }
+}
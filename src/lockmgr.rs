@@ -0,0 +1,146 @@
+//!
+//! lockmgr.rs
+//! Lock manager for strict two-phase locking at a participant. Transactions
+//! take shared locks for reads and exclusive locks for writes while they
+//! execute, and hold all of them until the global decision releases them.
+//!
+use fixedhash::HashMap;
+use fixedhash::HashSet;
+
+///
+/// LockMode
+/// Shared locks are compatible with each other; exclusive locks with nothing
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+///
+/// Lock
+/// Current mode of a key's lock and the transactions holding it
+///
+#[derive(Debug)]
+struct Lock {
+    mode: LockMode,
+    holders: HashSet<String>,
+}
+
+///
+/// LockManager
+/// Lock table keyed by data key
+///
+#[derive(Debug, Default)]
+pub struct LockManager {
+    locks: HashMap<String, Lock>,
+}
+
+impl LockManager {
+
+    ///
+    /// new()
+    /// Returns an empty lock table
+    ///
+    pub fn new() -> LockManager {
+        LockManager {
+            locks: HashMap::default(),
+        }
+    }
+
+    ///
+    /// acquire(txid, key, mode)
+    /// Grants txid a lock on key if no other transaction holds a conflicting
+    /// one. A shared lock held by txid alone is upgraded in place. On
+    /// conflict nothing changes and the transactions txid would have to wait
+    /// for are returned.
+    ///
+    pub fn acquire(&mut self, txid: &str, key: &str, mode: LockMode) -> Result<(), Vec<String>> {
+        let lock = match self.locks.get_mut(key) {
+            Some(lock) => lock,
+            None => {
+                let mut holders = HashSet::default();
+                holders.insert(txid.to_string());
+                self.locks.insert(key.to_string(), Lock { mode, holders });
+                return Ok(());
+            }
+        };
+
+        let others: Vec<String> = lock.holders.iter().filter(|h| *h != txid).cloned().collect();
+        let compatible = others.is_empty()
+            || (mode == LockMode::Shared && lock.mode == LockMode::Shared);
+        if !compatible {
+            return Err(others);
+        }
+
+        lock.holders.insert(txid.to_string());
+        if mode == LockMode::Exclusive {
+            lock.mode = LockMode::Exclusive;
+        }
+        Ok(())
+    }
+
+    ///
+    /// release_all(txid)
+    /// Releases every lock txid holds
+    ///
+    pub fn release_all(&mut self, txid: &str) {
+        for lock in self.locks.values_mut() {
+            lock.holders.remove(txid);
+        }
+        self.locks.retain(|_, lock| !lock.holders.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_locks_are_compatible() {
+        let mut locks = LockManager::new();
+        assert_eq!(locks.acquire("t1", "k", LockMode::Shared), Ok(()));
+        assert_eq!(locks.acquire("t2", "k", LockMode::Shared), Ok(()));
+    }
+
+    #[test]
+    fn exclusive_lock_conflicts_with_others() {
+        let mut locks = LockManager::new();
+        assert_eq!(locks.acquire("t1", "k", LockMode::Exclusive), Ok(()));
+        assert_eq!(locks.acquire("t2", "k", LockMode::Shared), Err(vec!["t1".to_string()]));
+        assert_eq!(locks.acquire("t2", "k", LockMode::Exclusive), Err(vec!["t1".to_string()]));
+        // Other keys are unaffected
+        assert_eq!(locks.acquire("t2", "j", LockMode::Exclusive), Ok(()));
+    }
+
+    #[test]
+    fn sole_shared_holder_upgrades() {
+        let mut locks = LockManager::new();
+        assert_eq!(locks.acquire("t1", "k", LockMode::Shared), Ok(()));
+        assert_eq!(locks.acquire("t1", "k", LockMode::Exclusive), Ok(()));
+        assert_eq!(locks.acquire("t2", "k", LockMode::Shared), Err(vec!["t1".to_string()]));
+        // Re-acquiring a weaker lock keeps the exclusive one
+        assert_eq!(locks.acquire("t1", "k", LockMode::Shared), Ok(()));
+        assert_eq!(locks.acquire("t2", "k", LockMode::Shared), Err(vec!["t1".to_string()]));
+    }
+
+    #[test]
+    fn shared_lock_does_not_upgrade_while_shared() {
+        let mut locks = LockManager::new();
+        assert_eq!(locks.acquire("t1", "k", LockMode::Shared), Ok(()));
+        assert_eq!(locks.acquire("t2", "k", LockMode::Shared), Ok(()));
+        assert_eq!(locks.acquire("t1", "k", LockMode::Exclusive), Err(vec!["t2".to_string()]));
+        // The failed upgrade left the lock shared
+        assert_eq!(locks.acquire("t3", "k", LockMode::Shared), Ok(()));
+    }
+
+    #[test]
+    fn release_all_frees_every_key() {
+        let mut locks = LockManager::new();
+        assert_eq!(locks.acquire("t1", "k", LockMode::Exclusive), Ok(()));
+        assert_eq!(locks.acquire("t1", "j", LockMode::Shared), Ok(()));
+        locks.release_all("t1");
+        assert_eq!(locks.acquire("t2", "k", LockMode::Exclusive), Ok(()));
+        assert_eq!(locks.acquire("t2", "j", LockMode::Exclusive), Ok(()));
+    }
+}
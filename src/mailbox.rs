@@ -0,0 +1,133 @@
+//!
+//! mailbox.rs
+//! Demultiplexer for protocol messages that arrive for a transaction the
+//! receiver is not (or no longer) tracking. Instead of being dropped, such
+//! messages are held by txid until the transaction claims them or they expire.
+//!
+use std::time::Duration;
+use std::time::Instant;
+
+use clock;
+use fixedhash::HashMap;
+use message::ProtocolMessage;
+
+///
+/// Mailbox
+/// Messages from one peer that have not been delivered yet, keyed by txid
+///
+#[derive(Debug, Default)]
+pub struct Mailbox {
+    held: HashMap<String, Vec<(Instant, ProtocolMessage)>>,
+}
+
+impl Mailbox {
+
+    ///
+    /// new()
+    /// Returns an empty mailbox
+    ///
+    pub fn new() -> Mailbox {
+        Mailbox {
+            held: HashMap::default(),
+        }
+    }
+
+    ///
+    /// hold(msg)
+    /// Buffers a message until the transaction it belongs to claims it
+    ///
+    pub fn hold(&mut self, msg: ProtocolMessage) {
+        self.held.entry(msg.txid.clone()).or_default().push((clock::now(), msg));
+    }
+
+    ///
+    /// take(txid)
+    /// Removes and returns every message held for txid, in arrival order
+    ///
+    pub fn take(&mut self, txid: &str) -> Vec<ProtocolMessage> {
+        match self.held.remove(txid) {
+            Some(msgs) => msgs.into_iter().map(|(_, pm)| pm).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    ///
+    /// txids()
+    /// Returns the txids that have messages waiting
+    ///
+    pub fn txids(&self) -> Vec<String> {
+        self.held.keys().cloned().collect()
+    }
+
+    ///
+    /// expire(ttl)
+    /// Removes and returns messages that have been held longer than ttl
+    ///
+    pub fn expire(&mut self, ttl: Duration) -> Vec<ProtocolMessage> {
+        let mut expired = Vec::new();
+        for msgs in self.held.values_mut() {
+            let (old, young): (Vec<_>, Vec<_>) = msgs.drain(..).partition(|(t, _)| clock::elapsed(*t) > ttl);
+            expired.extend(old.into_iter().map(|(_, pm)| pm));
+            *msgs = young;
+        }
+        self.held.retain(|_, msgs| !msgs.is_empty());
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use message::MessageType;
+
+    fn msg(mtype: MessageType, txid: &str) -> ProtocolMessage {
+        ProtocolMessage::generate(mtype, txid.to_string(), "coordinator".to_string(), 1)
+    }
+
+    #[test]
+    fn early_messages_are_held_until_taken() {
+        let mut mailbox = Mailbox::new();
+        mailbox.hold(msg(MessageType::CoordinatorCommit, "t1"));
+        mailbox.hold(msg(MessageType::CoordinatorPropose, "t2"));
+        let mut txids = mailbox.txids();
+        txids.sort();
+        assert_eq!(txids, vec!["t1".to_string(), "t2".to_string()]);
+
+        let taken = mailbox.take("t1");
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].mtype, MessageType::CoordinatorCommit);
+        // Taking claims the messages: they are not handed out again
+        assert!(mailbox.take("t1").is_empty());
+        assert_eq!(mailbox.txids(), vec!["t2".to_string()]);
+    }
+
+    #[test]
+    fn take_returns_messages_in_arrival_order() {
+        let mut mailbox = Mailbox::new();
+        mailbox.hold(msg(MessageType::CoordinatorPreCommit, "t1"));
+        mailbox.hold(msg(MessageType::CoordinatorPropose, "t2"));
+        mailbox.hold(msg(MessageType::CoordinatorCommit, "t1"));
+        let mtypes: Vec<MessageType> = mailbox.take("t1").iter().map(|m| m.mtype).collect();
+        assert_eq!(mtypes, vec![MessageType::CoordinatorPreCommit, MessageType::CoordinatorCommit]);
+    }
+
+    #[test]
+    fn expire_removes_only_stale_messages() {
+        clock::simulate(0);
+        let mut mailbox = Mailbox::new();
+        mailbox.hold(msg(MessageType::CoordinatorCommit, "t1"));
+        mailbox.hold(msg(MessageType::CoordinatorAbort, "t2"));
+        clock::advance(Duration::from_millis(100));
+        mailbox.hold(msg(MessageType::CoordinatorPropose, "t2"));
+        assert!(mailbox.expire(Duration::from_millis(100)).is_empty());
+
+        clock::advance(Duration::from_millis(50));
+        let mut expired: Vec<String> = mailbox.expire(Duration::from_millis(100)).into_iter().map(|m| m.txid).collect();
+        expired.sort();
+        assert_eq!(expired, vec!["t1".to_string(), "t2".to_string()]);
+        // Only the younger message of t2 is left; t1 is gone altogether
+        assert_eq!(mailbox.txids(), vec!["t2".to_string()]);
+        assert_eq!(mailbox.take("t2")[0].mtype, MessageType::CoordinatorPropose);
+    }
+}
@@ -6,37 +6,78 @@ extern crate ctrlc;
 extern crate ipc_channel;
 use std::env;
 use std::fs;
+use std::path::Path;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::process::{Child,Command};
+use std::sync::mpsc;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use std::process::{self,Child,Command};
 use ipc_channel::ipc::IpcSender as Sender;
 use ipc_channel::ipc::IpcReceiver as Receiver;
 use ipc_channel::ipc::IpcOneShotServer;
 use ipc_channel::ipc::channel;
+pub mod clock;
+pub mod fixedhash;
 pub mod message;
 pub mod oplog;
+pub mod mailbox;
+pub mod kvstore;
+pub mod lockmgr;
+pub mod waitfor;
+pub mod partition;
 pub mod coordinator;
 pub mod participant;
 pub mod client;
 pub mod checker;
+pub mod crashpoint;
+pub mod detector;
+pub mod simulate;
 pub mod tpcoptions;
+pub mod tcp;
+pub mod transport;
+pub mod faults;
+pub mod scenario;
 use message::ProtocolMessage;
+use faults::FaultyTransport;
+use scenario::{Action, Partitions};
+use transport::IpcTransport;
+use transport::Transport;
+use fixedhash::HashMap;
+
+/// How long the supervisor waits before restarting a participant that died;
+/// doubled for each time the same participant died before
+const RESTART_DELAY: Duration = Duration::from_millis(100);
+
+/// How many times the supervisor restarts a participant that keeps dying
+/// before it gives up and fails the run
+const MAX_RESTARTS: u32 = 5;
+
+/// Links between participants, handed out by the parent: the name of the
+/// peer at the other end, and the channel ends to send to and receive from it
+type PeerLinks = Vec<(String, Sender<ProtocolMessage>, Receiver<ProtocolMessage>)>;
+
+/// Where each participant takes in its PeerLinks, by name
+type PeerDirectory = Vec<(String, Sender<PeerLinks>)>;
 
 ///
-/// pub fn spawn_child_and_connect(child_opts: &mut tpcoptions::TPCOptions) -> (std::process::Child, Sender<ProtocolMessage>, Receiver<ProtocolMessage>)
+/// pub fn spawn_child_and_connect(child_opts: &mut tpcoptions::TPCOptions) -> (std::process::Child, IpcTransport)
 ///
 ///     child_opts: CLI options for child process
 ///
 /// 1. Set up IPC
 /// 2. Spawn a child process using the child CLI options
 /// 3. Do any required communication to set up the parent / child communication channels
-/// 4. Return the child process handle and the communication channels for the parent
+/// 4. Return the child process handle, the parent's end of the transport,
+///    and where to send the child links to its peers
 ///
 /// HINT: You can change the signature of the function if necessary
 ///
-fn spawn_child_and_connect(child_opts: &mut tpcoptions::TPCOptions) -> (Child, Sender<ProtocolMessage>, Receiver<ProtocolMessage>) {
-    // Create IPC one-shot server that accepts a tuple of (Sender, Receiver)
-    let (server, server_name) = IpcOneShotServer::<(Sender<ProtocolMessage>, Receiver<ProtocolMessage>)>::new().unwrap();
+fn spawn_child_and_connect(child_opts: &mut tpcoptions::TPCOptions) -> (Child, IpcTransport, Sender<PeerLinks>) {
+    // Create IPC one-shot server that accepts a tuple of (Sender, Receiver, Sender)
+    let (server, server_name) = IpcOneShotServer::<(Sender<ProtocolMessage>, Receiver<ProtocolMessage>, Sender<PeerLinks>)>::new().unwrap();
     
     // Pass server name to child
     child_opts.ipc_path = server_name;
@@ -47,37 +88,62 @@ fn spawn_child_and_connect(child_opts: &mut tpcoptions::TPCOptions) -> (Child, S
         .spawn()
         .expect("Failed to execute child process"); // TODO: update error message
 
-    // Accept connection - child sends us a (Sender, Receiver) tuple
+    // Accept connection - child sends us a (Sender, Receiver, Sender) tuple
     // This represents the child's end of the communication
-    let (_, (child_tx, child_rx)): (_, (Sender<ProtocolMessage>, Receiver<ProtocolMessage>)) = server.accept().unwrap();
+    let (_, (child_tx, child_rx, peer_links)): (_, (Sender<ProtocolMessage>, Receiver<ProtocolMessage>, Sender<PeerLinks>)) = server.accept().unwrap();
     
     // child_tx: parent uses this to send TO child
     // child_rx: parent uses this to receive FROM child
-    (child_process, child_tx, child_rx)
+    // peer_links: parent uses this to link the child to its peers
+    (child_process, IpcTransport::new(child_tx, child_rx), peer_links)
 }
 
 ///
-/// pub fn connect_to_coordinator(opts: &tpcoptions::TPCOptions) -> (Sender<ProtocolMessage>, Receiver<ProtocolMessage>)
+/// pub fn connect_to_coordinator(opts: &tpcoptions::TPCOptions) -> IpcTransport
 ///
 ///     opts: CLI options for this process
 ///
 /// 1. Connect to the parent via IPC
 /// 2. Do any required communication to set up the parent / child communication channels
-/// 3. Return the communication channels for the child
+/// 3. Return the child's end of the transport, and where the parent sends
+///    links to peers
 ///
 /// HINT: You can change the signature of the function if necessasry
 ///
-fn connect_to_coordinator(opts: &tpcoptions::TPCOptions) -> (Sender<ProtocolMessage>, Receiver<ProtocolMessage>) {
-    // Create two channels - one for each direction
+fn connect_to_coordinator(opts: &tpcoptions::TPCOptions) -> (IpcTransport, Receiver<PeerLinks>) {
+    // Create two channels - one for each direction - and one for peer links
     let (to_parent_tx, to_parent_rx) = channel().unwrap();
     let (from_parent_tx, from_parent_rx) = channel().unwrap();
+    let (peer_links_tx, peer_links_rx) = channel().unwrap();
     
-    // Connect to parent's server and send both channel ends
+    // Connect to parent's server and send the channel ends
     let bootstrap = Sender::connect(opts.ipc_path.clone()).unwrap();
-    bootstrap.send((from_parent_tx, to_parent_rx)).unwrap();
+    bootstrap.send((from_parent_tx, to_parent_rx, peer_links_tx)).unwrap();
     
-    // Return (tx to send TO parent, rx to receive FROM parent)
-    (to_parent_tx, from_parent_rx)
+    // Return (tx to send TO parent, rx to receive FROM parent), and peer links
+    (IpcTransport::new(to_parent_tx, from_parent_rx), peer_links_rx)
+}
+
+///
+/// fn link_peer(name, links, directory)
+///     links: where participant name takes in links to its peers
+///     directory: the participants linked so far
+///
+/// Links participant name to every participant in the directory with a
+/// pair of channels each, for cooperative termination, and adds it to the
+/// directory in place of any previous incarnation
+///
+fn link_peer(name: &str, links: Sender<PeerLinks>, directory: &mut PeerDirectory) {
+    directory.retain(|(peer, _)| peer != name);
+    let mut new_links = Vec::new();
+    for (peer, peer_links) in directory.iter() {
+        let (to_new_tx, to_new_rx) = channel().unwrap();
+        let (to_peer_tx, to_peer_rx) = channel().unwrap();
+        peer_links.send(vec![(name.to_string(), to_new_tx, to_peer_rx)]).unwrap_or(());
+        new_links.push((peer.clone(), to_peer_tx, to_new_rx));
+    }
+    links.send(new_links).unwrap_or(());
+    directory.push((name.to_string(), links));
 }
 
 ///
@@ -86,58 +152,279 @@ fn connect_to_coordinator(opts: &tpcoptions::TPCOptions) -> (Sender<ProtocolMess
 ///     running: An atomically reference counted (ARC) AtomicBool(ean) that is
 ///         set to be false whenever Ctrl+C is pressed
 ///
-/// 1. Creates a new coordinator
+/// 1. Creates a new coordinator (or recovers one from its log with --recover)
 /// 2. Spawns and connects to new clients processes and then registers them with
 ///    the coordinator
-/// 3. Spawns and connects to new participant processes and then registers them
-///    with the coordinator
-/// 4. Starts the coordinator protocol
-/// 5. Wait until the children finish execution
+/// 3. Spawns and connects to new participant processes, links them to each
+///    other and then registers them with the coordinator
+/// 4. Starts supervising the children in the background: participants that
+///    die are restarted to recover and rejoin, and the scenario (if any) is
+///    executed
+/// 5. Starts the coordinator protocol
+/// 6. Wait until the children finish execution, and fails the run if the
+///    supervisor gave up on a participant
 ///
 fn run(opts: & tpcoptions::TPCOptions, running: Arc<AtomicBool>) {
-    let coord_log_path = format!("{}//{}", opts.log_path, "coordinator.log");
-
-    // Create coordinator
-    let mut coordinator = coordinator::Coordinator::new(coord_log_path, &running);
+    // Create coordinator, recovering from its previous log if asked to
+    let mut coordinator = new_coordinator::<FaultyTransport<IpcTransport>>(opts, &running);
+    let mut partitions = Partitions::new();
+    let mut children = Vec::new();
+    let mut directory = PeerDirectory::new();
     
     // Spawn and connect participants
-    let mut participant_children = Vec::new();
     for i in 0..opts.num_participants {
         let mut participant_opts = opts.clone();
         participant_opts.mode = "participant".to_string();
         participant_opts.num = i;
         
-        let (child, tx, rx) = spawn_child_and_connect(&mut participant_opts);
+        let (child, transport, peer_links) = spawn_child_and_connect(&mut participant_opts);
         let participant_name = format!("participant_{}", i);
+        link_peer(&participant_name, peer_links, &mut directory);
         
-        coordinator.participant_join(&participant_name, tx, rx);
-        participant_children.push(child);
+        let transport = FaultyTransport::new(transport, "coordinator", &participant_name, &opts.faults);
+        partitions.add(&participant_name, transport.isolation());
+        coordinator.participant_join(&participant_name, transport);
+        children.push((participant_name, child));
     }
     
     // Spawn and connect clients
-    let mut client_children = Vec::new();
     for i in 0..opts.num_clients {
         let mut client_opts = opts.clone();
         client_opts.mode = "client".to_string();
         client_opts.num = i;
         
-        let (child, tx, rx) = spawn_child_and_connect(&mut client_opts);
+        let (child, transport, _) = spawn_child_and_connect(&mut client_opts);
         let client_name = format!("client_{}", i);
         
-        coordinator.client_join(&client_name, tx, rx);
-        client_children.push(child);
+        let transport = FaultyTransport::new(transport, "coordinator", &client_name, &opts.faults);
+        partitions.add(&client_name, transport.isolation());
+        coordinator.client_join(&client_name, transport);
+        children.push((client_name, child));
+    }
+
+    // Supervise the children alongside the protocol
+    let children = Arc::new(Mutex::new(children));
+
+    // A scripted coordinator crash takes the children down with it, so none
+    // of them keeps running into a --recover rerun writing the same logs
+    {
+        let children = children.clone();
+        crashpoint::before_crash(move || {
+            for (_, child) in children.lock().unwrap_or_else(|e| e.into_inner()).iter_mut() {
+                child.kill().unwrap_or(());
+                let _ = child.wait();
+            }
+        });
     }
+    let finished = Arc::new(AtomicBool::new(false));
+    let (rejoin_tx, rejoin_rx) = mpsc::channel();
+    coordinator.accept_rejoins(rejoin_rx);
+    let supervisor = {
+        let (opts, children, finished, running) = (opts.clone(), children.clone(), finished.clone(), running.clone());
+        thread::spawn(move || supervise(&opts, &children, partitions, directory, &rejoin_tx, &finished, &running))
+    };
     
     // Start coordinator protocol
     coordinator.protocol();
+    finished.store(true, Ordering::SeqCst);
+    let supervised = supervisor.join().unwrap();
     
     // Wait for all children to finish
-    for mut child in client_children {
+    for (_, child) in children.lock().unwrap().iter_mut() {
         let _ = child.wait();
     }
-    
-    for mut child in participant_children {
-        let _ = child.wait();
+    if !supervised {
+        error!("run::Failed, a participant kept dying");
+        process::exit(1);
+    }
+}
+
+///
+/// fn supervise(opts, children, partitions, directory, rejoins, finished)
+///     children: the child processes by name; dead ones are replaced by
+///         their restarted incarnation
+///     partitions: the coordinator's links to the children
+///     directory: the participants' peer links; restarted ones are relinked
+///     rejoins: where the coordinator takes in restarted participants
+///     finished: set once the coordinator protocol is done
+///     running: cleared to stop the coordinator when the supervisor gives up
+///
+/// Watches the children until the protocol is done. A participant that
+/// dies (anything but a clean exit) is restarted with the same --num to
+/// recover from its log, and rejoins the coordinator (and its peers) in
+/// place of its previous incarnation. Each time the same participant dies
+/// the restart waits twice as long, and after MAX_RESTARTS restarts the
+/// supervisor gives up: it stops the protocol and returns false. Crashes
+/// the scenario or opts.crash_points caused are the exception: those
+/// follow the scenario's restart_after_ms, or are restarted right away for
+/// crash points, however often they happen. A client that dies is not
+/// restarted, it could not pick up where it left off.
+///
+/// Also executes the timeline of opts.scenario against the coordinator's
+/// links and the child processes.
+///
+fn supervise(opts: & tpcoptions::TPCOptions,
+             children: &Mutex<Vec<(String, Child)>>,
+             mut partitions: Partitions,
+             mut directory: PeerDirectory,
+             rejoins: &mpsc::Sender<coordinator::Rejoin<FaultyTransport<IpcTransport>>>,
+             finished: &AtomicBool,
+             running: &AtomicBool) -> bool {
+    let mut timeline = opts.scenario.timeline();
+    let mut restarts: HashMap<String, u32> = HashMap::default();
+    let start = clock::now();
+    while !finished.load(Ordering::SeqCst) {
+        // Children that died; one that exited cleanly is left to be waited for
+        let mut died = Vec::new();
+        children.lock().unwrap().retain_mut(|(name, child)| {
+            match child.try_wait() {
+                Ok(Some(status)) if !status.success() => {
+                    died.push((name.clone(), status));
+                    false
+                },
+                _ => true,
+            }
+        });
+        for (name, status) in died {
+            let restart_after = if status.code() == Some(scenario::CRASH_STATUS) {
+                match opts.scenario.restart_after(&name) {
+                    None if opts.crash_points.covers(&name) => Some(Duration::from_millis(0)),
+                    after => after,
+                }
+            } else if name.starts_with("participant_") {
+                let count = restarts.entry(name.clone()).or_insert(0);
+                if *count == MAX_RESTARTS {
+                    error!("supervisor::{} died ({}) after {} restarts, giving up", name, status, MAX_RESTARTS);
+                    running.store(false, Ordering::SeqCst);
+                    return false;
+                }
+                *count += 1;
+                Some(RESTART_DELAY * 2u32.pow(*count - 1))
+            } else {
+                None
+            };
+            match restart_after {
+                Some(after) if name.starts_with("participant_") => {
+                    info!("supervisor::{} died ({}), restarting it in {:?}", name, status, after);
+                    timeline.push((clock::elapsed(start) + after, Action::Restart(name)));
+                },
+                _ => info!("supervisor::{} died ({})", name, status),
+            }
+        }
+        timeline.sort_by_key(|(at, _)| *at);
+
+        while !timeline.is_empty() && timeline[0].0 <= clock::elapsed(start) {
+            let (_, action) = timeline.remove(0);
+            match action {
+                Action::Crash(ref name) if name == "coordinator" => {
+                    error!("scenario::Crashing coordinator, as scripted");
+                    crashpoint::crash();
+                },
+                Action::Crash(ref name) => {
+                    info!("scenario::Crashing {}", name);
+                    children.lock().unwrap().retain_mut(|(n, child)| {
+                        if n != name {
+                            return true;
+                        }
+                        child.kill().unwrap_or(());
+                        let _ = child.wait();
+                        false
+                    });
+                },
+                Action::Restart(ref name) => {
+                    info!("supervisor::Restarting {}", name);
+                    let mut participant_opts = opts.clone();
+                    participant_opts.mode = "participant".to_string();
+                    participant_opts.num = name["participant_".len()..].parse::<u32>().unwrap();
+                    participant_opts.recover = true;
+
+                    let (child, transport, peer_links) = spawn_child_and_connect(&mut participant_opts);
+                    link_peer(name, peer_links, &mut directory);
+                    let transport = FaultyTransport::new(transport, "coordinator", name, &opts.faults);
+                    partitions.add(name, transport.isolation());
+                    rejoins.send(coordinator::Rejoin { name: name.clone(), transport }).unwrap_or(());
+                    children.lock().unwrap().push((name.clone(), child));
+                },
+                _ => partitions.apply(&action),
+            }
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+    true
+}
+
+///
+/// pub fn run_coordinator(opts: &tpcoptions:TPCOptions, running: Arc<AtomicBool>)
+///     opts: An options structure containing the CLI arguments
+///     running: An atomically reference counted (ARC) AtomicBool(ean) that is
+///         set to be false whenever Ctrl+C is pressed
+///
+/// 1. Creates a new coordinator (or recovers one from its log with --recover)
+/// 2. Listens on the address and registers participants and clients as they
+///    connect, until all of them have
+/// 3. Keeps accepting connections in the background, so processes that
+///    were restarted can rejoin
+/// 4. Starts the coordinator protocol
+///
+fn run_coordinator(opts: & tpcoptions::TPCOptions, running: Arc<AtomicBool>) {
+    let mut coordinator = new_coordinator::<FaultyTransport<tcp::TcpTransport>>(opts, &running);
+
+    let listener = TcpListener::bind(&opts.address).expect("Failed to listen on address");
+    info!("coordinator::Listening on {}", opts.address);
+
+    // Wait for everyone to join
+    let (mut participants, mut clients) = (0, 0);
+    while participants < opts.num_participants || clients < opts.num_clients {
+        let (name, transport) = match tcp::accept(&listener) {
+            Ok(joined) => joined,
+            Err(e) => {
+                error!("coordinator::Failed to accept a connection: {:?}", e);
+                continue;
+            }
+        };
+        let transport = FaultyTransport::new(transport, "coordinator", &name, &opts.faults);
+        if name.starts_with("participant_") {
+            coordinator.participant_join(&name, transport);
+            participants += 1;
+        } else {
+            coordinator.client_join(&name, transport);
+            clients += 1;
+        }
+    }
+
+    // Anyone connecting from now on rejoins
+    let (rejoin_tx, rejoin_rx) = mpsc::channel();
+    coordinator.accept_rejoins(rejoin_rx);
+    let faults = opts.faults.clone();
+    thread::spawn(move || {
+        loop {
+            match tcp::accept(&listener) {
+                Ok((name, transport)) => {
+                    let transport = FaultyTransport::new(transport, "coordinator", &name, &faults);
+                    if rejoin_tx.send(coordinator::Rejoin { name, transport }).is_err() {
+                        break;
+                    }
+                },
+                Err(e) => error!("coordinator::Failed to accept a connection: {:?}", e),
+            }
+        }
+    });
+
+    coordinator.protocol();
+}
+
+///
+/// fn new_coordinator<T: Transport>(opts: &tpcoptions::TPCOptions, running: &Arc<AtomicBool>) -> coordinator::Coordinator<T>
+///
+/// Creates a new coordinator, or recovers one from its previous log with
+/// --recover
+///
+fn new_coordinator<T: Transport>(opts: & tpcoptions::TPCOptions, running: &Arc<AtomicBool>) -> coordinator::Coordinator<T> {
+    let coord_log_path = format!("{}//{}", opts.log_path, "coordinator.log");
+    if opts.recover && Path::new(&coord_log_path).exists() {
+        coordinator::Coordinator::from_log(coord_log_path, running, opts)
+    } else {
+        coordinator::Coordinator::new(coord_log_path, running, opts)
     }
 }
 
@@ -147,18 +434,26 @@ fn run(opts: & tpcoptions::TPCOptions, running: Arc<AtomicBool>) {
 ///     running: An atomically reference counted (ARC) AtomicBool(ean) that is
 ///         set to be false whenever Ctrl+C is pressed
 ///
-/// 1. Connects to the coordinator to get tx/rx
+/// 1. Connects to the coordinator: over TCP if an address was given, via IPC
+///    to the parent otherwise
 /// 2. Constructs a new client
 /// 3. Starts the client protocol
 ///
 fn run_client(opts: & tpcoptions::TPCOptions, running: Arc<AtomicBool>) {
     let client_id_str = format!("client_{}", opts.num);
-    
-    // Connect to coordinator
-    let (tx, rx) = connect_to_coordinator(opts);
-    
+    if opts.address != "none" {
+        let transport = tcp::connect(&opts.address, &client_id_str).expect("Failed to connect to coordinator");
+        client_protocol(opts, running, client_id_str, transport);
+    } else {
+        let (transport, _) = connect_to_coordinator(opts);
+        client_protocol(opts, running, client_id_str, transport);
+    }
+}
+
+fn client_protocol<T: Transport>(opts: & tpcoptions::TPCOptions, running: Arc<AtomicBool>, client_id_str: String, transport: T) {
     // Create client
-    let mut client = client::Client::new(client_id_str, running, tx, rx);
+    let transport = FaultyTransport::new(transport, &client_id_str, "coordinator", &opts.faults);
+    let mut client = client::Client::new(client_id_str, opts.num_keys, running, transport);
     
     // Start client protocol
     client.protocol(opts.num_requests);
@@ -170,27 +465,65 @@ fn run_client(opts: & tpcoptions::TPCOptions, running: Arc<AtomicBool>) {
 ///     running: An atomically reference counted (ARC) AtomicBool(ean) that is
 ///         set to be false whenever Ctrl+C is pressed
 ///
-/// 1. Connects to the coordinator to get tx/rx
-/// 2. Constructs a new participant
-/// 3. Starts the participant protocol
+/// 1. Connects to the coordinator: over TCP if an address was given, via IPC
+///    to the parent otherwise
+/// 2. Via IPC, takes in the links to its peers the parent hands out, in the
+///    background (over TCP participants have no peers)
+/// 3. Constructs a new participant (or recovers one from its log with --recover)
+/// 4. Starts the participant protocol
 ///
 fn run_participant(opts: & tpcoptions::TPCOptions, running: Arc<AtomicBool>) {
     let participant_id_str = format!("participant_{}", opts.num);
+    if opts.address != "none" {
+        let transport = tcp::connect(&opts.address, &participant_id_str).expect("Failed to connect to coordinator");
+        participant_protocol(opts, running, participant_id_str, transport, None);
+    } else {
+        let (transport, peer_links) = connect_to_coordinator(opts);
+        let (joins_tx, joins_rx) = mpsc::channel();
+        let (name, faults) = (participant_id_str.clone(), opts.faults.clone());
+        thread::spawn(move || {
+            while let Ok(links) = peer_links.recv() {
+                for (peer, tx, rx) in links {
+                    let transport = FaultyTransport::new(IpcTransport::new(tx, rx), &name, &peer, &faults);
+                    if joins_tx.send((peer, transport)).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        participant_protocol(opts, running, participant_id_str, transport, Some(joins_rx));
+    }
+}
+
+fn participant_protocol<T: Transport>(opts: & tpcoptions::TPCOptions,
+                                      running: Arc<AtomicBool>,
+                                      participant_id_str: String,
+                                      transport: T,
+                                      peers: Option<mpsc::Receiver<(String, FaultyTransport<T>)>>) {
     let participant_log_path = format!("{}//{}.log", opts.log_path, participant_id_str);
+    let transport = FaultyTransport::new(transport, &participant_id_str, "coordinator", &opts.faults);
 
-    // Connect to coordinator
-    let (tx, rx) = connect_to_coordinator(opts);
-    
-    // Create participant
-    let mut participant = participant::Participant::new(
-        participant_id_str,
-        participant_log_path,
-        running,
-        opts.send_success_probability,
-        opts.operation_success_probability,
-        tx,
-        rx,
-    );
+    // Create participant, recovering from its previous log if asked to
+    let mut participant = if opts.recover && Path::new(&participant_log_path).exists() {
+        participant::Participant::from_log(
+            participant_id_str,
+            participant_log_path,
+            running,
+            opts,
+            transport,
+        )
+    } else {
+        participant::Participant::new(
+            participant_id_str,
+            participant_log_path,
+            running,
+            opts,
+            transport,
+        )
+    };
+    if let Some(peers) = peers {
+        participant.accept_peers(peers);
+    }
     
     // Start participant protocol
     participant.protocol();
@@ -207,9 +540,8 @@ fn main() {
             .verbosity(opts.verbosity)
             .init()
             .unwrap();
-    match fs::create_dir_all(opts.log_path.clone()) {
-        Err(e) => error!("Failed to create log_path: \"{:?}\". Error \"{:?}\"", opts.log_path, e),
-        _ => (),
+    if let Err(e) = fs::create_dir_all(opts.log_path.clone()) {
+        error!("Failed to create log_path: \"{:?}\". Error \"{:?}\"", opts.log_path, e);
     }
 
     // Set-up Ctrl-C / SIGINT handler
@@ -219,16 +551,18 @@ fn main() {
     ctrlc::set_handler(move || {
         r.store(false, Ordering::SeqCst);
         if m == "run" {
-            print!("\n");
+            println!();
         }
     }).expect("Error setting signal handler!");
 
     // Execute main logic
     match opts.mode.as_ref() {
         "run" => run(&opts, running),
+        "coordinator" => run_coordinator(&opts, running),
         "client" => run_client(&opts, running),
         "participant" => run_participant(&opts, running),
-        "check" => checker::check_last_run(opts.num_clients, opts.num_requests, opts.num_participants, &opts.log_path),
+        "simulate" => simulate::run(&opts, running),
+        "check" => checker::check_last_run(opts.num_clients, opts.num_requests, opts.num_participants, &opts.log_path, opts.protocol, &opts.partition_map),
         _ => panic!("Unknown mode"),
     }
 }
@@ -1,94 +1,171 @@
-//!
-//! client.rs
-//! Implementation of 2PC client
-//!
-extern crate serde;
-extern crate serde_json;
-
-use std::sync::atomic::{AtomicU32, Ordering};
-
-use self::serde_json::Value;
-
-///
-/// MessageType
-/// Message type codes that various 2PC parties may want to send or receive.
-///
-/// HINT: You should not need to modify this, but can add to it if necessary
-///
-#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
-pub enum MessageType {
-    ClientRequest,          // Request a transaction from the coordinator
-    CoordinatorPropose,     // Coordinator sends propose work to participants
-    ParticipantVoteCommit,  // Participant votes to commit in phase 1
-    ParticipantVoteAbort,   // Participant votes to abort in phase 1
-    CoordinatorAbort,       // Coordinator aborts in phase 2
-    CoordinatorCommit,      // Coordinator commits phase 2
-    ClientResultCommit,     // result (success/fail) communicated to client
-    ClientResultAbort,      // result (success/fail) communicated to client
-    CoordinatorExit,        // Coordinator telling client/participant about shut down
-}
-
-///
-/// RequestStatus
-/// Status of request from client.
-///
-/// HINT: You should not need to modify this, but can add to it if necessary
-///
-#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
-pub enum RequestStatus {
-    Committed,              // Request succeeded
-    Aborted,                // Request explicitly aborted
-    Unknown,                // Request status unknown (typically timed out)
-}
-
-/// generator for unique ids of messages
-static COUNTER: AtomicU32 = AtomicU32::new(1);
-
-///
-/// ProtocolMessage
-/// Message struct to be send as part of 2PC protocol
-///
-/// HINT: You should not need to modify this, but can add to it if necessary.
-///       It is mostly vital that the txid meets the below conditions that for
-///       each transaction initiated by each client, it needs to be unique.
-///       Namely, client_0_tx_1, client_0_tx_2, client_1_tx_1, client_1_tx_2 or
-///       something along these lines
-///
-#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
-pub struct ProtocolMessage {
-    pub mtype: MessageType,  //
-    pub uid: u32,            // Unique ID relative to the current process
-    pub txid: String,        // Transaction ID from the client (unique relative to other transactions)
-    pub senderid: String,    // Sender ID (unique across all senders)
-    pub opid: u32,           // Operation ID (relative to the original client who started this transaction)
-}
-
-///
-/// ProtocolMessage Implementation
-///
-impl ProtocolMessage {
-    pub fn generate(t: MessageType, tid: String, sid: String, oid: u32) -> ProtocolMessage {
-        ProtocolMessage {
-            mtype: t,
-            uid: COUNTER.fetch_add(1, Ordering::SeqCst),
-            txid: tid,
-            senderid: sid,
-            opid: oid,
-        }
-    }
-    pub fn instantiate(t: MessageType, u: u32, tid: String, sid: String, oid: u32) -> ProtocolMessage {
-        ProtocolMessage {
-            mtype: t,
-            uid: u,
-            txid: tid,
-            senderid: sid,
-            opid: oid,
-        }
-    }
-    pub fn from_string(line: &String) -> ProtocolMessage {
-        let data: Value = serde_json::from_str(&line.to_string()).unwrap();
-        let pm: ProtocolMessage = serde_json::from_value(data).unwrap();
-        pm
-    }
-
-}
+//!
+//! client.rs
+//! Implementation of 2PC client
+//!
+extern crate serde;
+extern crate serde_json;
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use self::serde_json::Value;
+
+///
+/// MessageType
+/// Message type codes that various 2PC parties may want to send or receive.
+///
+/// HINT: You should not need to modify this, but can add to it if necessary
+///
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageType {
+    ClientRequest,          // Request a transaction from the coordinator
+    CoordinatorPropose,     // Coordinator sends propose work to participants
+    ParticipantVoteCommit,  // Participant votes to commit in phase 1
+    ParticipantVoteAbort,   // Participant votes to abort in phase 1
+    CoordinatorAbort,       // Coordinator aborts in phase 2
+    CoordinatorCommit,      // Coordinator commits phase 2
+    ClientResultCommit,     // result (success/fail) communicated to client
+    ClientResultAbort,      // result (success/fail) communicated to client
+    CoordinatorExit,        // Coordinator telling client/participant about shut down
+    ParticipantDecisionRequest, // Participant asks the coordinator for the outcome of a transaction
+    CoordinatorDecisionReply,   // Coordinator answers a decision request (outcome in status)
+    CoordinatorPreCommit,       // 3PC: coordinator tells participants everyone voted to commit
+    ParticipantPreCommitAck,    // 3PC: participant acknowledges a PreCommit
+    ParticipantAck,             // Participant acknowledges it applied the global decision
+    CoordinatorEnd,             // Log record: every required ack arrived, transaction can be forgotten
+    ParticipantWaitFor,         // Participant reports the transactions txid waits for on locks (in waits_for)
+    CoordinatorSnapshotRead,    // MVCC: read-only transaction served at a snapshot (timestamp), without voting
+    ParticipantSnapshotReply,   // MVCC: participant finished a snapshot read
+    ParticipantVoteReadOnly,    // Participant's part performed no writes; it sits out phase two
+    CoordinatorOnePhaseCommit,  // 1PC: the sole participant executes and decides the transaction itself
+    ParticipantOnePhaseReply,   // 1PC: participant reports the outcome it decided (in status)
+    ParticipantHeartbeat,       // Participant tells the coordinator it is alive
+    CoordinatorMemberUp,        // Log record: participant (in txid) is heard from again
+    CoordinatorMemberSuspected, // Log record: participant (in txid) missed some heartbeats
+    CoordinatorMemberDown,      // Log record: participant (in txid) is declared down
+    ParticipantTerminationRequest,  // Participant asks its peers for the outcome of a transaction
+    ParticipantTerminationReply,    // Peer answers a termination request (outcome in status)
+    CoordinatorTerminationRequest,  // 3PC: recovering coordinator asks a participant where a pre-committed transaction stands
+    ParticipantRecovered,       // Log record: participant restarted and replayed its log
+    ClientResultUnknown,        // Coordinator cannot learn the outcome of the request for now
+}
+
+///
+/// RequestStatus
+/// Status of request from client.
+///
+/// HINT: You should not need to modify this, but can add to it if necessary
+///
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum RequestStatus {
+    Committed,              // Request succeeded
+    Aborted,                // Request explicitly aborted
+    #[default]
+    Unknown,                // Request status unknown (typically timed out)
+    PreCommitted,           // 3PC termination reply: participant is pre-committed, so the transaction commits
+}
+
+///
+/// Operation
+/// A single operation a transaction performs against the participants'
+/// key-value stores. A transaction's payload is a list of these, executed
+/// in order.
+///
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Get(String),            // Read the value of a key
+    Put(String, String),    // Write a value to a key
+    Delete(String),         // Remove a key
+    CompareAndSwap(String, Option<String>, String), // Write a value if the key holds the expected one (None: absent)
+}
+
+impl Operation {
+
+    ///
+    /// key()
+    /// Returns the key this operation touches
+    ///
+    pub fn key(&self) -> &String {
+        match self {
+            Operation::Get(key) => key,
+            Operation::Put(key, _) => key,
+            Operation::Delete(key) => key,
+            Operation::CompareAndSwap(key, _, _) => key,
+        }
+    }
+
+    ///
+    /// is_write()
+    /// Returns true if the operation may modify its key
+    ///
+    pub fn is_write(&self) -> bool {
+        !matches!(self, Operation::Get(_))
+    }
+}
+
+/// generator for unique ids of messages
+static COUNTER: AtomicU32 = AtomicU32::new(1);
+
+///
+/// ProtocolMessage
+/// Message struct to be send as part of 2PC protocol
+///
+/// HINT: You should not need to modify this, but can add to it if necessary.
+///       It is mostly vital that the txid meets the below conditions that for
+///       each transaction initiated by each client, it needs to be unique.
+///       Namely, client_0_tx_1, client_0_tx_2, client_1_tx_1, client_1_tx_2 or
+///       something along these lines
+///
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+pub struct ProtocolMessage {
+    pub mtype: MessageType,  //
+    pub uid: u32,            // Unique ID relative to the current process
+    pub txid: String,        // Transaction ID from the client (unique relative to other transactions)
+    pub senderid: String,    // Sender ID (unique across all senders)
+    pub opid: u32,           // Operation ID (relative to the original client who started this transaction)
+    #[serde(default)]
+    pub status: RequestStatus, // Outcome carried by a CoordinatorDecisionReply
+    #[serde(default)]
+    pub ops: Vec<Operation>, // Transaction payload (ClientRequest, CoordinatorPropose and their log records)
+    #[serde(default)]
+    pub waits_for: Vec<String>, // Lock holders blocking txid, carried by a ParticipantWaitFor
+    #[serde(default)]
+    pub timestamp: u64,      // Snapshot of a proposal / snapshot read, or commit timestamp of a commit decision
+}
+
+///
+/// ProtocolMessage Implementation
+///
+impl ProtocolMessage {
+    pub fn generate(t: MessageType, tid: String, sid: String, oid: u32) -> ProtocolMessage {
+        ProtocolMessage {
+            mtype: t,
+            uid: COUNTER.fetch_add(1, Ordering::SeqCst),
+            txid: tid,
+            senderid: sid,
+            opid: oid,
+            status: RequestStatus::Unknown,
+            ops: Vec::new(),
+            waits_for: Vec::new(),
+            timestamp: 0,
+        }
+    }
+    pub fn instantiate(t: MessageType, u: u32, tid: String, sid: String, oid: u32) -> ProtocolMessage {
+        ProtocolMessage {
+            mtype: t,
+            uid: u,
+            txid: tid,
+            senderid: sid,
+            opid: oid,
+            status: RequestStatus::Unknown,
+            ops: Vec::new(),
+            waits_for: Vec::new(),
+            timestamp: 0,
+        }
+    }
+    pub fn from_string(line: &String) -> ProtocolMessage {
+        let data: Value = serde_json::from_str(&line.to_string()).unwrap();
+        let pm: ProtocolMessage = serde_json::from_value(data).unwrap();
+        pm
+    }
+
+}
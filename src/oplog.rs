@@ -1,109 +1,305 @@
-extern crate serde;
-extern crate serde_json;
-extern crate bincode;
-
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::BufReader;
-use std::io::prelude::*;
-use std::sync::Arc;
-use std::sync::Mutex;
-
-use message;
-
-#[derive(Debug)]
-pub struct OpLog {
-    seqno: u32,
-    log_arc: Arc<Mutex<HashMap<u32, message::ProtocolMessage>>>,
-    path: String,
-    lf: File,
-}
-
-impl OpLog {
-
-    /// 
-    /// new(fpath: String)
-    /// Creates a new Operations Log at the designated file path
-    ///
-    pub fn new(fpath: String) -> OpLog {
-        let l = HashMap::new();
-        let lck = Mutex::new(l);
-        let arc = Arc::new(lck);
-        OpLog {
-            seqno: 0,
-            log_arc: arc,
-            path: fpath.to_string(),
-            lf: File::create(fpath).unwrap(),
-        }
-    }
-
-    ///
-    /// from_file(fpath: String)
-    ///
-    /// Reads in and returns an existing Operations Log from the designated file
-    ///
-    pub fn from_file(fpath: String) -> OpLog {
-        let mut seqno = 0;
-        let mut l = HashMap::new();
-        let scopy = fpath.clone();
-        let tlf = File::open(fpath).unwrap();
-        let mut reader = BufReader::new(&tlf);
-        let mut line = String::new();
-        let mut len = reader.read_line(&mut line).unwrap();
-        while len > 0 {
-            let pm = message::ProtocolMessage::from_string(&line);
-            if pm.uid > seqno {
-                seqno = pm.uid;
-            }
-            l.insert(pm.uid, pm);
-            line.clear();
-            len = reader.read_line(&mut line).unwrap();
-        }
-        let lck = Mutex::new(l);
-        let arc = Arc::new(lck);
-        OpLog {
-            seqno: seqno,
-            log_arc: arc,
-            path: scopy,
-            lf: tlf,
-        }
-    }
-
-    ///
-    /// append(t, tid, sender, op)
-    ///
-    /// Appends an entry to the Operations Log
-    ///
-    pub fn append(&mut self, t: message::MessageType, tid: String, sender: String, op: u32) {
-        let lck = Arc::clone(&self.log_arc);
-        let mut log = lck.lock().unwrap();
-        self.seqno += 1;
-        let id = self.seqno;
-        let pm = message::ProtocolMessage::generate(t, tid, sender, op);
-        serde_json::to_writer(&mut self.lf, &pm).unwrap();
-        writeln!(&mut self.lf).unwrap();
-        self.lf.flush().unwrap();
-        log.insert(id, pm);
-    }
-
-    ///
-    /// read(offset)
-    ///
-    /// Returns the log entry corresponding with the given offset
-    ///
-    pub fn read(&mut self, offset: &u32) -> message::ProtocolMessage {
-        let lck = Arc::clone(&self.log_arc);
-        let log = lck.lock().unwrap();
-        let pm = log[&offset].clone();
-        pm
-    }
-
-    ///
-    /// arc
-    ///
-    /// Returns an ARC of the mutex locked log
-    ///
-    pub fn arc(&self) -> Arc<Mutex<HashMap<u32, message::ProtocolMessage>>> {
-        Arc::clone(&self.log_arc)
-    }
-}
+extern crate serde;
+extern crate serde_json;
+extern crate bincode;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufReader;
+use std::io::prelude::*;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use message;
+
+#[derive(Debug)]
+pub struct OpLog {
+    seqno: u32,
+    writes: u64,
+    forced_writes: u64,
+    log_arc: Arc<Mutex<HashMap<u32, message::ProtocolMessage>>>,
+    path: String,
+    lf: File,
+}
+
+impl OpLog {
+
+    /// 
+    /// new(fpath: String)
+    /// Creates a new Operations Log at the designated file path
+    ///
+    pub fn new(fpath: String) -> OpLog {
+        let l = HashMap::new();
+        let lck = Mutex::new(l);
+        let arc = Arc::new(lck);
+        OpLog {
+            seqno: 0,
+            writes: 0,
+            forced_writes: 0,
+            log_arc: arc,
+            path: fpath.to_string(),
+            lf: File::create(fpath).unwrap(),
+        }
+    }
+
+    ///
+    /// from_file(fpath: String)
+    ///
+    /// Reads in and returns an existing Operations Log from the designated file.
+    /// The file is reopened for appending, so a recovering process can keep
+    /// extending the log it crashed with. A last entry the crash cut short
+    /// was never completely written, so it is dropped and truncated away;
+    /// anything unreadable before it is a corrupt log.
+    ///
+    pub fn from_file(fpath: String) -> OpLog {
+        let tlf = OpenOptions::new().read(true).append(true).open(&fpath).unwrap();
+        let (seqno, l, complete) = OpLog::read_entries(&tlf, &fpath);
+        if complete < tlf.metadata().unwrap().len() {
+            warn!("oplog::Dropping torn last entry of {}", fpath);
+            tlf.set_len(complete).unwrap();
+        }
+        OpLog::with_entries(fpath, tlf, seqno, l)
+    }
+
+    ///
+    /// load(fpath: String)
+    ///
+    /// Reads in an existing Operations Log for inspection (e.g. by the
+    /// checker) without ever modifying the file: a torn last entry is only
+    /// skipped in memory. The file is opened read-only, so the returned log
+    /// must not be appended to.
+    ///
+    pub fn load(fpath: String) -> OpLog {
+        let tlf = File::open(&fpath).unwrap();
+        let (seqno, l, _) = OpLog::read_entries(&tlf, &fpath);
+        OpLog::with_entries(fpath, tlf, seqno, l)
+    }
+
+    ///
+    /// read_entries(file, fpath)
+    ///
+    /// Parses every complete entry of a log file. Returns the highest
+    /// sequence number, the entries by sequence number and the length of the
+    /// file up to the end of the last complete entry. Only the last entry may
+    /// be torn; anything unreadable before it panics.
+    ///
+    fn read_entries(file: &File, fpath: &str) -> (u32, HashMap<u32, message::ProtocolMessage>, u64) {
+        let mut seqno = 0;
+        let mut l = HashMap::new();
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        let mut complete: u64 = 0;
+        let mut len = reader.read_line(&mut line).unwrap();
+        while len > 0 {
+            let parsed = serde_json::from_str::<message::ProtocolMessage>(&line);
+            let pm = match parsed {
+                Ok(pm) if line.ends_with('\n') => pm,
+                _ => {
+                    line.clear();
+                    if reader.read_line(&mut line).unwrap() > 0 {
+                        panic!("corrupt entry in log {} at byte {}", fpath, complete);
+                    }
+                    break;
+                },
+            };
+            complete += len as u64;
+            if pm.uid > seqno {
+                seqno = pm.uid;
+            }
+            l.insert(pm.uid, pm);
+            line.clear();
+            len = reader.read_line(&mut line).unwrap();
+        }
+        (seqno, l, complete)
+    }
+
+    fn with_entries(fpath: String, lf: File, seqno: u32, l: HashMap<u32, message::ProtocolMessage>) -> OpLog {
+        let lck = Mutex::new(l);
+        let arc = Arc::new(lck);
+        OpLog {
+            seqno,
+            writes: 0,
+            forced_writes: 0,
+            log_arc: arc,
+            path: fpath,
+            lf,
+        }
+    }
+
+    ///
+    /// append(t, tid, sender, op, force)
+    ///
+    /// Appends an entry to the Operations Log. A forced entry is synced to
+    /// stable storage before append returns; an unforced one may be lost if
+    /// the machine crashes before the OS writes it back.
+    ///
+    pub fn append(&mut self, t: message::MessageType, tid: String, sender: String, op: u32, force: bool) {
+        self.append_with_ops(t, tid, sender, op, Vec::new(), force);
+    }
+
+    ///
+    /// append_with_ops(t, tid, sender, op, ops, force)
+    ///
+    /// Like append(), but also records the transaction payload in the entry
+    ///
+    pub fn append_with_ops(&mut self, t: message::MessageType, tid: String, sender: String, op: u32,
+                           ops: Vec<message::Operation>, force: bool) {
+        let mut pm = message::ProtocolMessage::instantiate(t, 0, tid, sender, op);
+        pm.ops = ops;
+        self.append_message(pm, force);
+    }
+
+    ///
+    /// append_message(pm, force)
+    ///
+    /// Appends a prepared message as an entry, keeping every field it carries
+    /// (e.g. the commit timestamp of a decision). Its uid is replaced.
+    ///
+    pub fn append_message(&mut self, mut pm: message::ProtocolMessage, force: bool) {
+        let lck = Arc::clone(&self.log_arc);
+        let mut log = lck.lock().unwrap();
+        self.seqno += 1;
+        let id = self.seqno;
+        // The uid doubles as the log sequence number so that from_file()
+        // rebuilds the same map after a restart
+        pm.uid = id;
+        serde_json::to_writer(&mut self.lf, &pm).unwrap();
+        writeln!(&mut self.lf).unwrap();
+        self.lf.flush().unwrap();
+        self.writes += 1;
+        if force {
+            self.lf.sync_data().unwrap();
+            self.forced_writes += 1;
+        }
+        log.insert(id, pm);
+    }
+
+    ///
+    /// writes()
+    ///
+    /// Returns the number of entries appended (forced or not) since opening
+    ///
+    pub fn writes(&self) -> u64 {
+        self.writes
+    }
+
+    ///
+    /// forced_writes()
+    ///
+    /// Returns the number of forced entries appended since opening
+    ///
+    pub fn forced_writes(&self) -> u64 {
+        self.forced_writes
+    }
+
+    ///
+    /// read(offset)
+    ///
+    /// Returns the log entry corresponding with the given offset
+    ///
+    pub fn read(&mut self, offset: &u32) -> message::ProtocolMessage {
+        let lck = Arc::clone(&self.log_arc);
+        let log = lck.lock().unwrap();
+        log[offset].clone()
+    }
+
+    ///
+    /// entries()
+    ///
+    /// Returns a copy of every log entry in the order it was appended
+    ///
+    pub fn entries(&self) -> Vec<message::ProtocolMessage> {
+        let lck = Arc::clone(&self.log_arc);
+        let log = lck.lock().unwrap();
+        let mut seqnos: Vec<&u32> = log.keys().collect();
+        seqnos.sort();
+        seqnos.iter().map(|k| log[*k].clone()).collect()
+    }
+
+    ///
+    /// path()
+    ///
+    /// Returns the file path backing this log
+    ///
+    pub fn path(&self) -> &String {
+        &self.path
+    }
+
+    ///
+    /// arc
+    ///
+    /// Returns an ARC of the mutex locked log
+    ///
+    pub fn arc(&self) -> Arc<Mutex<HashMap<u32, message::ProtocolMessage>>> {
+        Arc::clone(&self.log_arc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use message::MessageType;
+
+    fn log_path(name: &str) -> String {
+        env::temp_dir().join(format!("tpc-oplog-{}-{}.log", name, process::id())).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn torn_last_entry_is_dropped() {
+        let path = log_path("torn");
+        {
+            let mut log = OpLog::new(path.clone());
+            log.append(MessageType::CoordinatorPropose, "t1".to_string(), "coordinator".to_string(), 1, true);
+            log.append(MessageType::CoordinatorCommit, "t1".to_string(), "coordinator".to_string(), 1, true);
+        }
+        let complete = fs::metadata(&path).unwrap().len();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"mtype\":\"Coordinator").unwrap();
+
+        let mut log = OpLog::from_file(path.clone());
+        assert_eq!(log.entries().len(), 2);
+        assert_eq!(fs::metadata(&path).unwrap().len(), complete);
+
+        // Appending after recovery continues the log cleanly
+        log.append(MessageType::CoordinatorEnd, "t1".to_string(), "coordinator".to_string(), 1, true);
+        let mtypes: Vec<MessageType> = OpLog::load(path.clone()).entries().iter().map(|m| m.mtype).collect();
+        assert_eq!(mtypes, vec![MessageType::CoordinatorPropose, MessageType::CoordinatorCommit, MessageType::CoordinatorEnd]);
+        fs::remove_file(&path).unwrap_or(());
+    }
+
+    #[test]
+    fn load_skips_torn_last_entry_without_truncating() {
+        let path = log_path("load");
+        {
+            let mut log = OpLog::new(path.clone());
+            log.append(MessageType::CoordinatorPropose, "t1".to_string(), "coordinator".to_string(), 1, true);
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"mtype\":\"Coordinator").unwrap();
+        let torn = fs::metadata(&path).unwrap().len();
+
+        let log = OpLog::load(path.clone());
+        assert_eq!(log.entries().len(), 1);
+        assert_eq!(fs::metadata(&path).unwrap().len(), torn);
+        fs::remove_file(&path).unwrap_or(());
+    }
+
+    #[test]
+    #[should_panic(expected = "corrupt entry in log")]
+    fn corrupt_entry_before_the_last_panics() {
+        let path = log_path("corrupt");
+        {
+            let mut log = OpLog::new(path.clone());
+            log.append(MessageType::CoordinatorPropose, "t1".to_string(), "coordinator".to_string(), 1, true);
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        writeln!(file, "garbage").unwrap();
+        writeln!(file, "garbage").unwrap();
+        OpLog::from_file(path);
+    }
+}
@@ -1,315 +1,1335 @@
-//!
-//! participant.rs
-//! Implementation of 2PC participant
-//!
-extern crate ipc_channel;
-extern crate log;
-extern crate rand;
-extern crate stderrlog;
-
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
-use std::time::Duration;
-use std::thread;
-
-use participant::rand::prelude::*;
-use participant::ipc_channel::ipc::IpcReceiver as Receiver;
-use participant::ipc_channel::ipc::TryRecvError;
-use participant::ipc_channel::ipc::IpcSender as Sender;
-
-use message::MessageType;
-use message::ProtocolMessage;
-use oplog;
-
-///
-/// ParticipantState
-/// enum for Participant 2PC state machine
-///
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
-pub enum ParticipantState {
-    Quiescent,
-    ReceivedP1,
-    VotedAbort,
-    VotedCommit,
-    AwaitingGlobalDecision,
-}
-
-///
-/// Participant
-/// Structure for maintaining per-participant state and communication/synchronization objects to/from coordinator
-///
-pub struct Participant {
-    id_str: String,
-    state: ParticipantState,
-    log: oplog::OpLog,
-    running: Arc<AtomicBool>,
-    send_success_prob: f64,
-    operation_success_prob: f64,
-    tx: Sender<ProtocolMessage>,
-    rx: Receiver<ProtocolMessage>,
-    successful_ops: u64,
-    failed_ops: u64,
-    unknown_ops: u64,
-}
-
-///
-/// Participant
-/// Implementation of participant for the 2PC protocol
-/// Required:
-/// 1. new -- Constructor
-/// 2. pub fn report_status -- Reports number of committed/aborted/unknown for each participant
-/// 3. pub fn protocol() -- Implements participant side protocol for 2PC
-///
-impl Participant {
-
-    ///
-    /// new()
-    ///
-    /// Return a new participant, ready to run the 2PC protocol with the coordinator.
-    ///
-    /// HINT: You may want to pass some channels or other communication
-    ///       objects that enable coordinator->participant and participant->coordinator
-    ///       messaging to this constructor.
-    /// HINT: You may want to pass some global flags that indicate whether
-    ///       the protocol is still running to this constructor. There are other
-    ///       ways to communicate this, of course.
-    ///
-    pub fn new(
-        id_str: String,
-        log_path: String,
-        r: Arc<AtomicBool>,
-        send_success_prob: f64,
-        operation_success_prob: f64,
-        tx: Sender<ProtocolMessage>,
-        rx: Receiver<ProtocolMessage>) -> Participant {
-
-        Participant {
-            id_str: id_str,
-            state: ParticipantState::Quiescent,
-            log: oplog::OpLog::new(log_path),
-            running: r,
-            send_success_prob: send_success_prob,
-            operation_success_prob: operation_success_prob,
-            tx: tx,
-            rx: rx,
-            successful_ops: 0,
-            failed_ops: 0,
-            unknown_ops: 0,
-        }
-    }
-
-    ///
-    /// send()
-    /// Send a protocol message to the coordinator. This can fail depending on
-    /// the success probability. For testing purposes, make sure to not specify
-    /// the -S flag so the default value of 1 is used for failproof sending.
-    ///
-    /// HINT: You will need to implement the actual sending
-    ///
-    pub fn send(&mut self, pm: ProtocolMessage) {
-        let x: f64 = random();
-        if x <= self.send_success_prob {
-            self.tx.send(pm.clone()).unwrap_or(());
-            trace!("{}::Sent message successfully", self.id_str);
-        } else {
-            trace!("{}::Failed to send message", self.id_str);
-        }
-    }
-
-    ///
-    /// perform_operation
-    /// Perform the operation specified in the 2PC proposal,
-    /// with some probability of success/failure determined by the
-    /// command-line option success_probability.
-    ///
-    /// HINT: The code provided here is not complete--it provides some
-    ///       tracing infrastructure and the probability logic.
-    ///       Your implementation need not preserve the method signature
-    ///       (it's ok to add parameters or return something other than
-    ///       bool if it's more convenient for your design).
-    ///
-    pub fn perform_operation(&mut self, request_option: &Option<ProtocolMessage>) -> bool {
-
-        trace!("{}::Performing operation", self.id_str.clone());
-        let x: f64 = random();
-        if x <= self.operation_success_prob {
-            trace!("{}::Operation successful", self.id_str);
-            true
-        } else {
-            trace!("{}::Operation failed", self.id_str);
-            false
-        }
-    }
-
-    ///
-    /// report_status()
-    /// Report the abort/commit/unknown status (aggregate) of all transaction
-    /// requests made by this coordinator before exiting.
-    ///
-    pub fn report_status(&mut self) {
-        println!("{}:\tC:{}\tA:{}\tU:{}", self.id_str, self.successful_ops, self.failed_ops, self.unknown_ops);
-    }
-
-    ///
-    /// wait_for_exit_signal(&mut self)
-    /// Wait until the running flag is set by the CTRL-C handler
-    ///
-    pub fn wait_for_exit_signal(&mut self) {
-        trace!("{}::Waiting for exit signal", self.id_str.clone());
-
-        loop {
-            match self.rx.try_recv() {
-                Ok(msg) => {
-                    if msg.mtype == MessageType::CoordinatorExit {
-                        trace!("{}::Received exit signal", self.id_str);
-                        break;
-                    }
-                },
-                Err(TryRecvError::Empty) => {
-                    if !self.running.load(std::sync::atomic::Ordering::SeqCst) {
-                        break;
-                    }
-                    thread::sleep(Duration::from_millis(100));
-                },
-                Err(TryRecvError::IpcError(_)) => {
-                    break;
-                }
-            }
-        }
-
-        trace!("{}::Exiting", self.id_str.clone());
-    }
-
-    ///
-    /// protocol()
-    /// Implements the participant side of the 2PC protocol
-    /// HINT: If the simulation ends early, don't keep handling requests!
-    /// HINT: Wait for some kind of exit signal before returning from the protocol!
-    ///
-    pub fn protocol(&mut self) {
-        trace!("{}::Beginning protocol", self.id_str.clone());
-
-        loop {
-            if !self.running.load(std::sync::atomic::Ordering::SeqCst) {
-                break;
-            }
-
-            // Wait for proposal from coordinator
-            match self.rx.try_recv() {
-                Ok(msg) => {
-                    if msg.mtype == MessageType::CoordinatorExit {
-                        trace!("{}::Received exit signal in protocol", self.id_str);
-                        break;
-                    } else if msg.mtype == MessageType::CoordinatorPropose {
-                        trace!("{}::Received proposal for txid: {}", self.id_str, msg.txid);
-                        self.state = ParticipantState::ReceivedP1;
-
-                        // Perform operation to decide vote
-                        let success = self.perform_operation(&Some(msg.clone()));
-
-                        let vote_msg = if success {
-                            self.state = ParticipantState::VotedCommit;
-                            info!("{}::Voting COMMIT for txid: {}", self.id_str, msg.txid);
-                            // Log the local vote commit
-                            self.log.append(
-                                MessageType::ParticipantVoteCommit,
-                                msg.txid.clone(),
-                                self.id_str.clone(),
-                                msg.opid,
-                            );
-                            ProtocolMessage::generate(
-                                MessageType::ParticipantVoteCommit,
-                                msg.txid.clone(),
-                                self.id_str.clone(),
-                                msg.opid,
-                            )
-                        } else {
-                            self.state = ParticipantState::VotedAbort;
-                            info!("{}::Voting ABORT for txid: {}", self.id_str, msg.txid);
-                            ProtocolMessage::generate(
-                                MessageType::ParticipantVoteAbort,
-                                msg.txid.clone(),
-                                self.id_str.clone(),
-                                msg.opid,
-                            )
-                        };
-
-                        // Send vote to coordinator
-                        self.send(vote_msg);
-                        self.state = ParticipantState::AwaitingGlobalDecision;
-
-                        // Wait for global decision from coordinator
-                        let timeout = Duration::from_millis(500);
-                        let start_time = std::time::Instant::now();
-                        let mut decision_received = false;
-
-                        while start_time.elapsed() < timeout {
-                            if !self.running.load(std::sync::atomic::Ordering::SeqCst) {
-                                break;
-                            }
-
-                            match self.rx.try_recv() {
-                                Ok(decision_msg) => {
-                                    if decision_msg.txid == msg.txid {
-                                        if decision_msg.mtype == MessageType::CoordinatorCommit {
-                                            info!("{}::Received COMMIT decision for txid: {}", self.id_str, msg.txid);
-                                            self.successful_ops += 1;
-                                            // Log the global commit decision
-                                            self.log.append(
-                                                MessageType::CoordinatorCommit,
-                                                msg.txid.clone(),
-                                                self.id_str.clone(),
-                                                msg.opid,
-                                            );
-                                            decision_received = true;
-                                            break;
-                                        } else if decision_msg.mtype == MessageType::CoordinatorAbort {
-                                            info!("{}::Received ABORT decision for txid: {}", self.id_str, msg.txid);
-                                            self.failed_ops += 1;
-                                            // Log the global abort decision
-                                            self.log.append(
-                                                MessageType::CoordinatorAbort,
-                                                msg.txid.clone(),
-                                                self.id_str.clone(),
-                                                msg.opid,
-                                            );
-                                            decision_received = true;
-                                            break;
-                                        } else if decision_msg.mtype == MessageType::CoordinatorExit {
-                                            trace!("{}::Received exit signal while waiting for decision", self.id_str);
-                                            self.unknown_ops += 1;
-                                            decision_received = true;
-                                            break;
-                                        }
-                                    }
-                                },
-                                Err(TryRecvError::Empty) => {
-                                    thread::sleep(Duration::from_millis(1));
-                                },
-                                Err(TryRecvError::IpcError(_)) => {
-                                    break;
-                                }
-                            }
-                        }
-
-                        if !decision_received {
-                            trace!("{}::Timeout waiting for decision on txid: {}", self.id_str, msg.txid);
-                            self.unknown_ops += 1;
-                        }
-
-                        self.state = ParticipantState::Quiescent;
-                    }
-                },
-                Err(TryRecvError::Empty) => {
-                    thread::sleep(Duration::from_millis(1));
-                },
-                Err(TryRecvError::IpcError(_)) => {
-                    break;
-                }
-            }
-        }
-
-        self.wait_for_exit_signal();
-        self.report_status();
-    }
-}
+//!
+//! participant.rs
+//! Implementation of 2PC participant
+//!
+extern crate log;
+extern crate stderrlog;
+
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc;
+use std::time::Duration;
+use std::time::Instant;
+
+use clock;
+use crashpoint::CrashPoint;
+use crashpoint::Crashes;
+use detector::HEARTBEAT_INTERVAL;
+use fixedhash::HashMap;
+use fixedhash::HashSet;
+use kvstore::KvStore;
+use lockmgr::LockManager;
+use lockmgr::LockMode;
+use mailbox::Mailbox;
+use message::MessageType;
+use message::Operation;
+use message::ProtocolMessage;
+use message::RequestStatus;
+use oplog;
+use tpcoptions::CommitProtocol;
+use tpcoptions::LoggingMode;
+use tpcoptions::StorageMode;
+use tpcoptions::TPCOptions;
+use transport::Transport;
+use transport::TryRecvError;
+
+/// How long a participant that lost its coordinator stays around to resolve
+/// transactions with its peers, and to answer theirs
+const PEER_GRACE: Duration = Duration::from_millis(2000);
+
+/// How long a 3PC termination round waits for peers that do not answer
+const PEER_ROUND: Duration = Duration::from_millis(1000);
+
+///
+/// ParticipantState
+/// enum for Participant 2PC state machine
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParticipantState {
+    Quiescent,
+    ReceivedP1,             // Acquiring locks for the proposal, has not voted yet
+    VotedAbort,
+    VotedCommit,            // Logged and sent a commit vote, waiting for the global decision
+    PreCommitted,           // 3PC only: received PreCommit, commits unless told otherwise
+}
+
+///
+/// Transaction
+/// Participant-side state of a transaction it was proposed but has not yet
+/// learned the global decision for
+///
+struct Transaction {
+    state: ParticipantState,
+    opid: u32,
+    proposal: ProtocolMessage,
+    one_phase: bool,        // Sole participant: decides the transaction itself instead of voting
+    locked: usize,          // Number of leading operations whose locks are held
+    blocked_on: Vec<String>,  // Lock holders last reported to the coordinator
+    last_heard: Instant,
+    waiting_since: Instant,
+    asked_peers: Option<Instant>,  // Last time we asked peers for the outcome
+    terminating: Option<Instant>,  // 3PC: when our termination round started
+    peer_answers: HashSet<String>,  // Peers that answered since
+}
+
+///
+/// Participant
+/// Structure for maintaining per-participant state and communication/synchronization objects to/from coordinator
+///
+pub struct Participant<T: Transport> {
+    id_str: String,
+    transactions: HashMap<String, Transaction>,
+    decided: HashSet<String>,
+    outcomes: HashMap<String, (MessageType, u64)>,  // Global decisions we know, with commit timestamps
+    one_phase_outcomes: HashMap<String, MessageType>,
+    mailbox: Mailbox,
+    store: KvStore,
+    storage: StorageMode,
+    snapshot_reads: Vec<ProtocolMessage>,
+    locks: LockManager,
+    lock_timeout: Duration,
+    protocol: CommitProtocol,
+    logging: LoggingMode,
+    log: oplog::OpLog,
+    running: Arc<AtomicBool>,
+    send_success_prob: f64,
+    operation_success_prob: f64,
+    transport: T,
+    peers: HashMap<String, T>,
+    peer_joins: Option<mpsc::Receiver<(String, T)>>,
+    coordinator_heard: Instant,
+    last_heartbeat: Instant,
+    exit_received: bool,
+    coordinator_gone: Option<Instant>,  // When the coordinator went away
+    successful_ops: u64,
+    failed_ops: u64,
+    unknown_ops: u64,
+    read_only_ops: u64,  // ReadOnly votes: we never learn how those transactions end
+    crashes: Crashes,
+    recovering: bool,
+}
+
+///
+/// Participant
+/// Implementation of participant for the 2PC protocol
+/// Required:
+/// 1. new -- Constructor
+/// 2. pub fn report_status -- Reports number of committed/aborted/unknown for each participant
+/// 3. pub fn protocol() -- Implements participant side protocol for 2PC
+/// 4. from_log -- Constructor that recovers from an existing log
+///
+impl<T: Transport> Participant<T> {
+
+    ///
+    /// new()
+    ///
+    /// Return a new participant, ready to run the 2PC protocol with the coordinator.
+    /// Send/operation success probabilities, the commit protocol and the
+    /// logging mode are taken from opts.
+    ///
+    /// HINT: You may want to pass some channels or other communication
+    ///       objects that enable coordinator->participant and participant->coordinator
+    ///       messaging to this constructor.
+    /// HINT: You may want to pass some global flags that indicate whether
+    ///       the protocol is still running to this constructor. There are other
+    ///       ways to communicate this, of course.
+    ///
+    pub fn new(
+        id_str: String,
+        log_path: String,
+        r: Arc<AtomicBool>,
+        opts: &TPCOptions,
+        transport: T) -> Participant<T> {
+
+        Self::with_log(id_str, oplog::OpLog::new(log_path), r, opts, transport, false)
+    }
+
+    ///
+    /// from_log()
+    ///
+    /// Return a participant that reopens the log left behind by a previous
+    /// incarnation. Transactions it voted to commit without learning the
+    /// outcome are resolved with the coordinator before protocol() serves
+    /// any new proposals.
+    ///
+    pub fn from_log(
+        id_str: String,
+        log_path: String,
+        r: Arc<AtomicBool>,
+        opts: &TPCOptions,
+        transport: T) -> Participant<T> {
+
+        Self::with_log(id_str, oplog::OpLog::from_file(log_path), r, opts, transport, true)
+    }
+
+    fn with_log(
+        id_str: String,
+        log: oplog::OpLog,
+        r: Arc<AtomicBool>,
+        opts: &TPCOptions,
+        transport: T,
+        recovering: bool) -> Participant<T> {
+
+        let mut crashes = opts.crash_points.arm(&id_str, recovering);
+        // A scripted crash happens once: not again while recovering from it
+        if let (false, Some(trigger)) = (recovering, opts.scenario.crash_point(&id_str)) {
+            crashes.crash_after(trigger);
+        }
+
+        Participant {
+            id_str,
+            transactions: HashMap::default(),
+            decided: HashSet::default(),
+            outcomes: HashMap::default(),
+            one_phase_outcomes: HashMap::default(),
+            mailbox: Mailbox::new(),
+            store: KvStore::new(opts.storage == StorageMode::Mvcc),
+            storage: opts.storage,
+            snapshot_reads: Vec::new(),
+            locks: LockManager::new(),
+            lock_timeout: Duration::from_millis(opts.lock_timeout),
+            protocol: opts.protocol,
+            logging: opts.logging,
+            log,
+            running: r,
+            send_success_prob: opts.send_success_probability,
+            operation_success_prob: opts.operation_success_probability,
+            transport,
+            peers: HashMap::default(),
+            peer_joins: None,
+            coordinator_heard: clock::now(),
+            last_heartbeat: clock::now(),
+            exit_received: false,
+            coordinator_gone: None,
+            successful_ops: 0,
+            failed_ops: 0,
+            unknown_ops: 0,
+            read_only_ops: 0,
+            crashes,
+            recovering,
+        }
+    }
+
+    ///
+    /// peer_join()
+    /// Adds (or replaces) the link to another participant, which the
+    /// cooperative termination protocol uses when the coordinator is
+    /// unreachable
+    ///
+    pub fn peer_join(&mut self, name: &str, transport: T) {
+        trace!("{}::Linked to peer {}", self.id_str, name);
+        self.peers.insert(name.to_string(), transport);
+    }
+
+    ///
+    /// accept_peers()
+    /// Lets links to peers arrive while the protocol runs: whatever arrives
+    /// on joins is added with peer_join(), e.g. once a peer was restarted
+    ///
+    pub fn accept_peers(&mut self, joins: mpsc::Receiver<(String, T)>) {
+        self.peer_joins = Some(joins);
+    }
+
+    ///
+    /// send()
+    /// Send a protocol message to the coordinator. This can fail depending on
+    /// the success probability. For testing purposes, make sure to not specify
+    /// the -S flag so the default value of 1 is used for failproof sending.
+    ///
+    /// HINT: You will need to implement the actual sending
+    ///
+    pub fn send(&mut self, pm: ProtocolMessage) {
+        let x: f64 = clock::random();
+        if x <= self.send_success_prob {
+            self.transport.send(pm.clone()).unwrap_or(());
+            trace!("{}::Sent message successfully", self.id_str);
+        } else {
+            trace!("{}::Failed to send message", self.id_str);
+        }
+    }
+
+    ///
+    /// perform_operation
+    /// Perform the operation specified in the 2PC proposal: run its reads
+    /// and stage its writes in the key-value store. Under MVCC the reads see
+    /// the snapshot the coordinator assigned to the proposal; a one-phase
+    /// request carries its commit timestamp instead, and reads the snapshot
+    /// just before it. Fails if the
+    /// writes cannot be staged, and otherwise with some probability
+    /// determined by the command-line option success_probability.
+    ///
+    pub fn perform_operation(&mut self, request_option: &Option<ProtocolMessage>) -> bool {
+
+        trace!("{}::Performing operation", self.id_str.clone());
+        let request = match request_option {
+            Some(request) => request,
+            None => return false,
+        };
+        let x: f64 = clock::random();
+        let snapshot = match self.storage {
+            StorageMode::Mvcc if request.mtype == MessageType::CoordinatorOnePhaseCommit => Some(request.timestamp.saturating_sub(1)),
+            StorageMode::Mvcc => Some(request.timestamp),
+            StorageMode::Locking => None,
+        };
+        if x <= self.operation_success_prob && self.store.stage(&request.txid, &request.ops, snapshot) {
+            trace!("{}::Operation successful", self.id_str);
+            true
+        } else {
+            trace!("{}::Operation failed", self.id_str);
+            false
+        }
+    }
+
+    ///
+    /// report_status()
+    /// Report the abort/commit/unknown status (aggregate) of all transaction
+    /// requests made by this coordinator before exiting, along with the
+    /// number of ReadOnly votes (RO), whose outcome we never learn, the
+    /// number of log writes (LW) and how many of them were forced (LF).
+    ///
+    pub fn report_status(&mut self) {
+        println!("{}:\tC:{}\tA:{}\tU:{}\tRO:{}\tLW:{}\tLF:{}",
+                 self.id_str, self.successful_ops, self.failed_ops, self.unknown_ops, self.read_only_ops,
+                 self.log.writes(), self.log.forced_writes());
+    }
+
+    ///
+    /// log_record()
+    /// Appends a record to this participant's log, forcing it only if the
+    /// logging mode requires it
+    ///
+    fn log_record(&mut self, mtype: MessageType, txid: String, opid: u32) {
+        self.log_record_with_ops(mtype, txid, opid, Vec::new());
+    }
+
+    ///
+    /// log_record_with_ops()
+    /// Like log_record(), but also records the transaction payload
+    ///
+    fn log_record_with_ops(&mut self, mtype: MessageType, txid: String, opid: u32, ops: Vec<Operation>) {
+        let force = self.logging.participant_forces(mtype);
+        self.log.append_with_ops(mtype, txid.clone(), self.id_str.clone(), opid, ops, force);
+        self.crashes.logged(mtype, &txid);
+    }
+
+    ///
+    /// log_decision()
+    /// Appends the record of a global decision, with its commit timestamp,
+    /// and remembers the decision so peers asking about it can be told
+    ///
+    fn log_decision(&mut self, decision: MessageType, txid: String, opid: u32, timestamp: u64) {
+        self.outcomes.insert(txid.clone(), (decision, timestamp));
+        let mut pm = ProtocolMessage::instantiate(decision, 0, txid.clone(), self.id_str.clone(), opid);
+        pm.timestamp = timestamp;
+        let force = self.logging.participant_forces(decision);
+        self.log.append_message(pm, force);
+        self.crashes.logged(decision, &txid);
+    }
+
+    ///
+    /// wait_for_exit_signal(&mut self)
+    /// Wait until the running flag is set by the CTRL-C handler
+    ///
+    pub fn wait_for_exit_signal(&mut self) {
+        trace!("{}::Waiting for exit signal", self.id_str.clone());
+
+        loop {
+            match self.transport.recv_timeout(Duration::from_millis(100)) {
+                Ok(msg) => {
+                    if msg.mtype == MessageType::CoordinatorExit {
+                        trace!("{}::Received exit signal", self.id_str);
+                        break;
+                    }
+                },
+                Err(TryRecvError::Empty) => {
+                    if !self.running.load(std::sync::atomic::Ordering::SeqCst) {
+                        break;
+                    }
+                },
+                Err(TryRecvError::Disconnected) => {
+                    break;
+                }
+            }
+        }
+
+        trace!("{}::Exiting", self.id_str.clone());
+    }
+
+    ///
+    /// decision_of()
+    /// Extracts the global decision (CoordinatorCommit/CoordinatorAbort) a
+    /// message from the coordinator or a peer carries, if it carries one. A
+    /// reply with an Unknown status means the sender does not know it yet.
+    /// Under 3PC a pre-committed peer means the transaction commits: the
+    /// coordinator never aborts once anyone is pre-committed.
+    ///
+    fn decision_of(msg: &ProtocolMessage) -> Option<MessageType> {
+        match msg.mtype {
+            MessageType::CoordinatorCommit | MessageType::CoordinatorAbort => Some(msg.mtype),
+            MessageType::CoordinatorDecisionReply | MessageType::ParticipantTerminationReply => match msg.status {
+                RequestStatus::Committed => Some(MessageType::CoordinatorCommit),
+                RequestStatus::Aborted => Some(MessageType::CoordinatorAbort),
+                RequestStatus::PreCommitted => Some(MessageType::CoordinatorCommit),
+                RequestStatus::Unknown => None,
+            },
+            _ => None,
+        }
+    }
+
+    ///
+    /// request_decision()
+    /// Ask the coordinator for the outcome of a transaction
+    ///
+    fn request_decision(&mut self, txid: &str, opid: u32) {
+        trace!("{}::Requesting decision for txid: {}", self.id_str, txid);
+        let request = ProtocolMessage::generate(
+            MessageType::ParticipantDecisionRequest,
+            txid.to_string(),
+            self.id_str.clone(),
+            opid,
+        );
+        self.send(request);
+    }
+
+    ///
+    /// recover()
+    /// Rebuilds the key-value store by redoing the logged writes of committed
+    /// transactions. Transactions this participant voted to commit but never
+    /// logged a global decision for have their writes staged again, and we
+    /// keep asking the coordinator about them until every one is resolved. A
+    /// vote to commit is a promise, so these cannot be decided locally.
+    /// One-phase transactions were decided here, and their decision records
+    /// carry the writes to redo.
+    /// Returns false if the coordinator shut down (or went away) before
+    /// recovery finished.
+    ///
+    fn recover(&mut self) -> bool {
+        info!("{}::Recovering from {}", self.id_str, self.log.path());
+        self.log_record(MessageType::ParticipantRecovered, String::new(), 0);
+
+        let mut in_doubt: HashMap<String, u32> = HashMap::default();
+        let mut promised: HashMap<String, Vec<Operation>> = HashMap::default();
+        let mut precommitted: HashSet<String> = HashSet::default();
+        for pm in self.log.entries() {
+            match pm.mtype {
+                MessageType::ParticipantVoteCommit => {
+                    in_doubt.insert(pm.txid.clone(), pm.opid);
+                    promised.insert(pm.txid, pm.ops);
+                },
+                MessageType::CoordinatorPreCommit => {
+                    precommitted.insert(pm.txid);
+                },
+                MessageType::ParticipantVoteReadOnly => {
+                    self.decided.insert(pm.txid);
+                },
+                MessageType::CoordinatorCommit | MessageType::CoordinatorAbort => {
+                    in_doubt.remove(&pm.txid);
+                    self.outcomes.insert(pm.txid.clone(), (pm.mtype, pm.timestamp));
+                    // Redo committed writes in commit order to rebuild the store
+                    let ops = match promised.remove(&pm.txid) {
+                        Some(ops) => Some(ops),
+                        None if !pm.ops.is_empty() => {
+                            self.one_phase_outcomes.insert(pm.txid.clone(), pm.mtype);
+                            Some(pm.ops)
+                        },
+                        None => None,
+                    };
+                    if let Some(ops) = ops {
+                        if pm.mtype == MessageType::CoordinatorCommit {
+                            self.store.redo(&pm.txid, &ops);
+                            self.store.commit(&pm.txid, pm.timestamp);
+                        }
+                    }
+                    self.decided.insert(pm.txid);
+                },
+                _ => {},
+            }
+        }
+        // In-doubt writes are staged again so the outcome can be applied, and
+        // stay locked until it is
+        for (txid, ops) in promised.iter() {
+            self.store.redo(txid, ops);
+            for op in ops.iter().filter(|op| op.is_write()) {
+                self.locks.acquire(txid, op.key(), LockMode::Exclusive).unwrap_or(());
+            }
+        }
+        info!("{}::Found {} in-doubt transactions, store holds {} committed keys",
+              self.id_str, in_doubt.len(), self.store.len());
+
+        let retry = Duration::from_millis(500);
+        while !in_doubt.is_empty() && self.running.load(std::sync::atomic::Ordering::SeqCst) {
+            let pending: Vec<(String, u32)> = in_doubt.iter().map(|(t, o)| (t.clone(), *o)).collect();
+            for (txid, opid) in pending {
+                self.request_decision(&txid, opid);
+            }
+
+            let start_time = clock::now();
+            while !in_doubt.is_empty() && clock::elapsed(start_time) < retry {
+                // Resolving can take a while: keep the failure detector from
+                // declaring us down meanwhile
+                if clock::elapsed(self.last_heartbeat) >= HEARTBEAT_INTERVAL {
+                    self.heartbeat();
+                }
+                match self.transport.recv_timeout(Duration::from_millis(1)) {
+                    Ok(msg) => {
+                        if msg.mtype == MessageType::CoordinatorPropose {
+                            // Not serving new work until in-doubt transactions are resolved
+                            trace!("{}::Voting ABORT for txid: {} while recovering", self.id_str, msg.txid);
+                            let vote_msg = ProtocolMessage::generate(
+                                MessageType::ParticipantVoteAbort,
+                                msg.txid.clone(),
+                                self.id_str.clone(),
+                                msg.opid,
+                            );
+                            self.send(vote_msg);
+                        } else if msg.mtype == MessageType::CoordinatorTerminationRequest {
+                            let (status, timestamp) = match self.outcome_status(&msg.txid) {
+                                Some(known) => known,
+                                None if in_doubt.contains_key(&msg.txid) && precommitted.contains(&msg.txid) => {
+                                    (RequestStatus::PreCommitted, 0)
+                                },
+                                None => (RequestStatus::Unknown, 0),
+                            };
+                            self.reply_termination(&msg, status, timestamp, None);
+                        } else if msg.mtype == MessageType::CoordinatorExit {
+                            trace!("{}::Received exit signal while recovering", self.id_str);
+                            self.unknown_ops += in_doubt.len() as u64;
+                            return false;
+                        } else if let Some(decision) = Self::decision_of(&msg) {
+                            if in_doubt.remove(&msg.txid).is_some() {
+                                info!("{}::Recovered {:?} for txid: {}", self.id_str, decision, msg.txid);
+                                if decision == MessageType::CoordinatorCommit {
+                                    self.store.commit(&msg.txid, msg.timestamp);
+                                } else {
+                                    self.store.abort(&msg.txid);
+                                }
+                                self.locks.release_all(&msg.txid);
+                                self.log_decision(decision, msg.txid.clone(), msg.opid, msg.timestamp);
+                                self.decided.insert(msg.txid.clone());
+                                self.acknowledge(decision, &msg.txid, msg.opid);
+                            }
+                        }
+                    },
+                    Err(TryRecvError::Empty) => {},
+                    Err(TryRecvError::Disconnected) => {
+                        self.unknown_ops += in_doubt.len() as u64;
+                        return false;
+                    }
+                }
+            }
+        }
+
+        self.unknown_ops += in_doubt.len() as u64;
+        self.recovering = false;
+        true
+    }
+
+    ///
+    /// handle_proposal()
+    /// Phase 1: starts tracking the proposed transaction and executes it as
+    /// far as its locks allow
+    ///
+    fn handle_proposal(&mut self, msg: ProtocolMessage) {
+        if self.transactions.contains_key(&msg.txid) || self.decided.contains(&msg.txid) {
+            trace!("{}::Ignoring duplicate proposal for txid: {}", self.id_str, msg.txid);
+            return;
+        }
+        trace!("{}::Received proposal for txid: {}", self.id_str, msg.txid);
+
+        // The decision may have overtaken the proposal (e.g. the coordinator
+        // already timed out); if so there is nothing left to vote on
+        let early = self.mailbox.take(&msg.txid).iter()
+            .filter_map(|m| Self::decision_of(m).map(|d| (d, m.timestamp)))
+            .next();
+        if let Some((decision, timestamp)) = early {
+            trace!("{}::Decision for txid: {} arrived before its proposal", self.id_str, msg.txid);
+            self.handle_decision(decision, &msg.txid, msg.opid, timestamp);
+            return;
+        }
+
+        let txid = msg.txid.clone();
+        self.transactions.insert(txid.clone(), Transaction {
+            state: ParticipantState::ReceivedP1,
+            opid: msg.opid,
+            one_phase: msg.mtype == MessageType::CoordinatorOnePhaseCommit,
+            proposal: msg,
+            locked: 0,
+            blocked_on: Vec::new(),
+            last_heard: clock::now(),
+            waiting_since: clock::now(),
+            asked_peers: None,
+            terminating: None,
+            peer_answers: HashSet::default(),
+        });
+        self.execute(&txid);
+    }
+
+    ///
+    /// execute()
+    /// Strict 2PL growing phase: takes a shared lock for each read and an
+    /// exclusive lock for each write, in operation order. Once every lock is
+    /// held the operation is performed and the vote sent. On a conflict the
+    /// transaction keeps the locks it has and waits (see check_transactions),
+    /// or votes abort right away if lock waits are disabled. A waiting
+    /// transaction reports whom it waits for to the coordinator, which
+    /// breaks deadlocks spanning participants.
+    ///
+    fn execute(&mut self, txid: &str) {
+        let (ops, mut locked) = match self.transactions.get(txid) {
+            Some(txn) if txn.state == ParticipantState::ReceivedP1 => (txn.proposal.ops.clone(), txn.locked),
+            _ => return,
+        };
+        // Snapshot isolation takes no locks
+        if self.storage == StorageMode::Mvcc {
+            locked = ops.len();
+        }
+
+        while locked < ops.len() {
+            let op = &ops[locked];
+            let mode = if op.is_write() { LockMode::Exclusive } else { LockMode::Shared };
+            if let Err(mut holders) = self.locks.acquire(txid, op.key(), mode) {
+                trace!("{}::txid: {} waiting for {:?} lock on {} held by {:?}", self.id_str, txid, mode, op.key(), holders);
+                if self.lock_timeout.as_millis() == 0 {
+                    self.vote(txid, false);
+                    return;
+                }
+                holders.sort();
+                let changed = match self.transactions.get_mut(txid) {
+                    Some(txn) => {
+                        txn.locked = locked;
+                        let changed = txn.blocked_on != holders;
+                        txn.blocked_on = holders.clone();
+                        changed
+                    },
+                    None => false,
+                };
+                if changed {
+                    self.report_wait(txid, holders);
+                }
+                return;
+            }
+            locked += 1;
+        }
+
+        // Perform operation to decide vote
+        let proposal = self.transactions.get(txid).map(|txn| txn.proposal.clone());
+        let success = self.perform_operation(&proposal);
+        self.vote(txid, success);
+    }
+
+    ///
+    /// report_wait()
+    /// Tells the coordinator which transactions txid waits for on locks here
+    ///
+    fn report_wait(&mut self, txid: &str, holders: Vec<String>) {
+        let opid = self.transactions.get(txid).map_or(0, |txn| txn.opid);
+        let mut report = ProtocolMessage::generate(
+            MessageType::ParticipantWaitFor,
+            txid.to_string(),
+            self.id_str.clone(),
+            opid,
+        );
+        report.waits_for = holders;
+        self.send(report);
+    }
+
+    ///
+    /// vote()
+    /// Logs and sends the vote for a transaction. Voting abort ends it here:
+    /// staged writes are discarded and its locks released. A transaction
+    /// that writes nothing here votes ReadOnly instead of commit: the
+    /// outcome cannot change our state, so it ends here too and the
+    /// coordinator leaves us out of phase two. A one-phase transaction is
+    /// decided right here instead.
+    ///
+    fn vote(&mut self, txid: &str, commit: bool) {
+        if self.transactions.get(txid).is_some_and(|txn| txn.one_phase) {
+            self.decide_one_phase(txid, commit);
+            return;
+        }
+        let read_only = self.transactions.get(txid)
+            .is_some_and(|txn| !txn.proposal.ops.iter().any(|op| op.is_write()));
+        if commit && read_only {
+            self.vote_read_only(txid);
+            return;
+        }
+
+        let (opid, ops) = match self.transactions.get_mut(txid) {
+            Some(txn) => {
+                txn.state = if commit { ParticipantState::VotedCommit } else { ParticipantState::VotedAbort };
+                txn.last_heard = clock::now();
+                txn.waiting_since = clock::now();
+                (txn.opid, txn.proposal.ops.clone())
+            },
+            None => return,
+        };
+
+        let vote_msg = if commit {
+            info!("{}::Voting COMMIT for txid: {}", self.id_str, txid);
+            // Log the local vote commit along with the writes it promises to apply
+            self.log_record_with_ops(MessageType::ParticipantVoteCommit, txid.to_string(), opid, ops);
+            self.crashes.reach(CrashPoint::AfterLogVote, txid);
+            ProtocolMessage::generate(
+                MessageType::ParticipantVoteCommit,
+                txid.to_string(),
+                self.id_str.clone(),
+                opid,
+            )
+        } else {
+            info!("{}::Voting ABORT for txid: {}", self.id_str, txid);
+            self.store.abort(txid);
+            self.locks.release_all(txid);
+            ProtocolMessage::generate(
+                MessageType::ParticipantVoteAbort,
+                txid.to_string(),
+                self.id_str.clone(),
+                opid,
+            )
+        };
+
+        // Send vote to coordinator
+        self.crashes.reach(CrashPoint::BeforeSendVote, txid);
+        self.send(vote_msg);
+    }
+
+    ///
+    /// vote_read_only()
+    /// Logs (without forcing) and sends a ReadOnly vote, releasing the
+    /// transaction's read locks right away. We take no part in phase two and
+    /// never learn the global outcome, so it is counted on its own rather
+    /// than as a commit.
+    ///
+    fn vote_read_only(&mut self, txid: &str) {
+        let opid = match self.transactions.remove(txid) {
+            Some(txn) => txn.opid,
+            None => return,
+        };
+        info!("{}::Voting READ-ONLY for txid: {}", self.id_str, txid);
+        self.decided.insert(txid.to_string());
+        self.store.abort(txid);
+        self.locks.release_all(txid);
+        self.read_only_ops += 1;
+        self.log_record(MessageType::ParticipantVoteReadOnly, txid.to_string(), opid);
+        let vote_msg = ProtocolMessage::generate(
+            MessageType::ParticipantVoteReadOnly,
+            txid.to_string(),
+            self.id_str.clone(),
+            opid,
+        );
+        self.crashes.reach(CrashPoint::BeforeSendVote, txid);
+        self.send(vote_msg);
+    }
+
+    ///
+    /// handle_one_phase()
+    /// 1PC: starts a transaction we are the sole participant of. A repeated
+    /// request (our reply may have been lost) is answered with the outcome
+    /// we decided, or ignored while we are still working on it.
+    ///
+    fn handle_one_phase(&mut self, msg: ProtocolMessage) {
+        if let Some(decision) = self.one_phase_outcomes.get(&msg.txid).cloned() {
+            trace!("{}::Repeating one-phase {:?} for txid: {}", self.id_str, decision, msg.txid);
+            self.reply_one_phase(decision, &msg.txid, msg.opid);
+            return;
+        }
+        self.handle_proposal(msg);
+    }
+
+    ///
+    /// decide_one_phase()
+    /// 1PC: applies (or discards) the transaction's writes and releases its
+    /// locks, then logs the decision along with the writes (so they can be
+    /// redone on recovery) and reports it to the coordinator
+    ///
+    fn decide_one_phase(&mut self, txid: &str, commit: bool) {
+        let txn = match self.transactions.remove(txid) {
+            Some(txn) => txn,
+            None => return,
+        };
+        self.decided.insert(txid.to_string());
+
+        let decision = if commit {
+            info!("{}::Deciding COMMIT for one-phase txid: {}", self.id_str, txid);
+            self.store.commit(txid, txn.proposal.timestamp);
+            self.successful_ops += 1;
+            MessageType::CoordinatorCommit
+        } else {
+            info!("{}::Deciding ABORT for one-phase txid: {}", self.id_str, txid);
+            self.store.abort(txid);
+            self.failed_ops += 1;
+            MessageType::CoordinatorAbort
+        };
+        self.locks.release_all(txid);
+
+        let mut pm = ProtocolMessage::instantiate(decision, 0, txid.to_string(), self.id_str.clone(), txn.opid);
+        pm.ops = txn.proposal.ops;
+        pm.timestamp = txn.proposal.timestamp;
+        let force = self.logging.participant_forces(decision);
+        self.log.append_message(pm, force);
+        self.crashes.logged(decision, txid);
+        self.one_phase_outcomes.insert(txid.to_string(), decision);
+        self.reply_one_phase(decision, txid, txn.opid);
+    }
+
+    ///
+    /// reply_one_phase()
+    /// 1PC: tells the coordinator the outcome we decided
+    ///
+    fn reply_one_phase(&mut self, decision: MessageType, txid: &str, opid: u32) {
+        let mut reply = ProtocolMessage::generate(
+            MessageType::ParticipantOnePhaseReply,
+            txid.to_string(),
+            self.id_str.clone(),
+            opid,
+        );
+        reply.status = if decision == MessageType::CoordinatorCommit {
+            RequestStatus::Committed
+        } else {
+            RequestStatus::Aborted
+        };
+        self.send(reply);
+    }
+
+    ///
+    /// serve_snapshot_reads()
+    /// MVCC only: serves pending read-only transactions from the snapshot at
+    /// their timestamp and tells the coordinator they are done. No locks are
+    /// taken and nothing is logged. A read touching a key with a staged
+    /// write waits for that transaction's decision, since it may commit at
+    /// a timestamp inside the snapshot.
+    ///
+    fn serve_snapshot_reads(&mut self) {
+        let pending: Vec<ProtocolMessage> = self.snapshot_reads.drain(..).collect();
+        for msg in pending {
+            if msg.ops.iter().any(|op| self.store.staged_by(op.key()).is_some()) {
+                self.snapshot_reads.push(msg);
+                continue;
+            }
+            trace!("{}::Serving snapshot read for txid: {} at {}", self.id_str, msg.txid, msg.timestamp);
+            self.store.read_at(&msg.txid, &msg.ops, msg.timestamp);
+            let reply = ProtocolMessage::generate(
+                MessageType::ParticipantSnapshotReply,
+                msg.txid.clone(),
+                self.id_str.clone(),
+                msg.opid,
+            );
+            self.send(reply);
+        }
+    }
+
+    ///
+    /// handle_precommit()
+    /// 3PC only: logs the PreCommit for a transaction we voted to commit and
+    /// acknowledges it. From here on the transaction commits unless the
+    /// coordinator says otherwise.
+    ///
+    fn handle_precommit(&mut self, msg: &ProtocolMessage) {
+        let opid = match self.transactions.get_mut(&msg.txid) {
+            Some(txn) if txn.state == ParticipantState::VotedCommit => {
+                txn.state = ParticipantState::PreCommitted;
+                txn.waiting_since = clock::now();
+                txn.opid
+            },
+            Some(_) => return,
+            None => {
+                trace!("{}::Ignoring PreCommit for txid: {}", self.id_str, msg.txid);
+                return;
+            }
+        };
+
+        info!("{}::Received PreCommit for txid: {}", self.id_str, msg.txid);
+        self.log_record(MessageType::CoordinatorPreCommit, msg.txid.clone(), opid);
+        let ack = ProtocolMessage::generate(
+            MessageType::ParticipantPreCommitAck,
+            msg.txid.clone(),
+            self.id_str.clone(),
+            opid,
+        );
+        self.send(ack);
+    }
+
+    ///
+    /// acknowledge()
+    /// Tells the coordinator the decision was applied so it can forget the
+    /// transaction, if the logging mode expects an ack for this decision
+    ///
+    fn acknowledge(&mut self, decision: MessageType, txid: &str, opid: u32) {
+        if !self.logging.acknowledges(decision) {
+            return;
+        }
+        let ack = ProtocolMessage::generate(
+            MessageType::ParticipantAck,
+            txid.to_string(),
+            self.id_str.clone(),
+            opid,
+        );
+        self.send(ack);
+    }
+
+    ///
+    /// handle_decision()
+    /// Phase 2: applies (or discards) the staged writes and releases the
+    /// transaction's locks, then logs and acknowledges the global decision.
+    /// Commits are applied at the commit timestamp the coordinator assigned.
+    ///
+    fn handle_decision(&mut self, decision: MessageType, txid: &str, opid: u32, timestamp: u64) {
+        self.transactions.remove(txid);
+        self.decided.insert(txid.to_string());
+
+        if decision == MessageType::CoordinatorCommit {
+            info!("{}::Received COMMIT decision for txid: {}", self.id_str, txid);
+            self.store.commit(txid, timestamp);
+            self.successful_ops += 1;
+        } else {
+            info!("{}::Received ABORT decision for txid: {}", self.id_str, txid);
+            self.store.abort(txid);
+            self.failed_ops += 1;
+        }
+        self.locks.release_all(txid);
+        // Log the global decision
+        self.log_decision(decision, txid.to_string(), opid, timestamp);
+        self.crashes.reach(CrashPoint::AfterLogDecision, txid);
+        self.acknowledge(decision, txid, opid);
+    }
+
+    ///
+    /// check_transactions()
+    /// Lets transactions waiting for locks retry (in arrival order), and votes
+    /// abort for those that waited longer than the lock timeout. Then asks
+    /// the coordinator for the outcome of every transaction whose decision
+    /// is taking too long (e.g. because it was lost). We keep asking until
+    /// we learn the outcome.
+    ///
+    /// Under 3PC we do not block forever: if the coordinator has gone silent
+    /// for the termination timeout, transactions are settled with our peers
+    /// (see terminate_transactions()).
+    ///
+    /// Under 2PC a silent coordinator makes us ask our peers instead (see
+    /// ask_peers()).
+    ///
+    fn check_transactions(&mut self) {
+        let timeout = Duration::from_millis(500);
+        let termination_timeout = Duration::from_millis(2000);
+
+        self.serve_snapshot_reads();
+
+        let mut blocked: Vec<(Instant, String)> = self.transactions.iter()
+            .filter(|(_, txn)| txn.state == ParticipantState::ReceivedP1)
+            .map(|(txid, txn)| (txn.waiting_since, txid.clone()))
+            .collect();
+        blocked.sort();
+        for (since, txid) in blocked {
+            self.execute(&txid);
+            let still_blocked = self.transactions.get(&txid)
+                .is_some_and(|txn| txn.state == ParticipantState::ReceivedP1);
+            if still_blocked && clock::elapsed(since) > self.lock_timeout {
+                trace!("{}::Timeout waiting for locks on txid: {}", self.id_str, txid);
+                self.vote(&txid, false);
+            }
+        }
+
+        if self.protocol == CommitProtocol::ThreePhase && clock::elapsed(self.coordinator_heard) > termination_timeout {
+            self.terminate_transactions(termination_timeout);
+        }
+        self.ask_peers();
+
+        let overdue: Vec<(String, u32)> = self.transactions.iter()
+            .filter(|(_, txn)| txn.state != ParticipantState::ReceivedP1 && clock::elapsed(txn.last_heard) > timeout)
+            .map(|(txid, txn)| (txid.clone(), txn.opid))
+            .collect();
+
+        for (txid, opid) in overdue {
+            trace!("{}::Timeout waiting for decision on txid: {}", self.id_str, txid);
+            self.request_decision(&txid, opid);
+            if let Some(txn) = self.transactions.get_mut(&txid) {
+                txn.last_heard = clock::now();
+            }
+        }
+    }
+
+    ///
+    /// terminate_transactions()
+    /// 3PC termination, applied when the coordinator is unreachable, to every
+    /// transaction waiting longer than min_wait. One we did not vote to
+    /// commit aborts: the coordinator cannot have committed it. One-phase
+    /// transactions are ours to decide, so they abort too. The others are
+    /// settled in a termination round with our peers (see ask_peers()): a
+    /// peer that is pre-committed or knows the outcome settles it at once
+    /// (see poll_peers()). Once every peer answered, or the round timed out,
+    /// it commits if we are pre-committed and aborts otherwise, since then
+    /// no participant we can reach is. Without peers there is no round to
+    /// run: a pre-committed transaction commits, and the others keep waiting
+    /// for the coordinator.
+    ///
+    fn terminate_transactions(&mut self, min_wait: Duration) {
+        let waiting: Vec<(String, ParticipantState, u32, bool)> = self.transactions.iter()
+            .filter(|(_, txn)| clock::elapsed(txn.waiting_since) >= min_wait)
+            .map(|(txid, txn)| (txid.clone(), txn.state, txn.opid, txn.one_phase))
+            .collect();
+        for (txid, state, opid, one_phase) in waiting {
+            if one_phase {
+                self.decide_one_phase(&txid, false);
+                continue;
+            }
+            let decision = match state {
+                ParticipantState::Quiescent | ParticipantState::ReceivedP1 | ParticipantState::VotedAbort => {
+                    Some(MessageType::CoordinatorAbort)
+                },
+                ParticipantState::PreCommitted if self.peers.is_empty() => Some(MessageType::CoordinatorCommit),
+                _ if self.peers.is_empty() => None,
+                _ => self.termination_round(&txid, state),
+            };
+            if let Some(decision) = decision {
+                info!("{}::Coordinator unreachable, terminating txid: {} in state {:?} with {:?}", self.id_str, txid, state, decision);
+                self.handle_decision(decision, &txid, opid, 0);
+            }
+        }
+        self.ask_peers();
+    }
+
+    ///
+    /// termination_round()
+    /// 3PC only: starts a termination round for txid, or ends the one
+    /// running once every peer answered or it timed out. Returns the
+    /// decision once the round is over.
+    ///
+    fn termination_round(&mut self, txid: &str, state: ParticipantState) -> Option<MessageType> {
+        let num_peers = self.peers.len();
+        let txn = self.transactions.get_mut(txid)?;
+        match txn.terminating {
+            None => {
+                // Answers from before the round may be stale, so ask again
+                trace!("{}::Starting termination round for txid: {}", self.id_str, txid);
+                txn.terminating = Some(clock::now());
+                txn.peer_answers.clear();
+                txn.asked_peers = None;
+                None
+            },
+            Some(since) if txn.peer_answers.len() >= num_peers || clock::elapsed(since) > PEER_ROUND => {
+                if state == ParticipantState::PreCommitted {
+                    Some(MessageType::CoordinatorCommit)
+                } else {
+                    Some(MessageType::CoordinatorAbort)
+                }
+            },
+            Some(_) => None,
+        }
+    }
+
+    ///
+    /// step()
+    /// Handles the next message from the coordinator, or checks on waiting
+    /// transactions if there is none. Returns true if a message was handled.
+    ///
+    pub fn step(&mut self) -> bool {
+        if clock::elapsed(self.last_heartbeat) >= HEARTBEAT_INTERVAL {
+            self.heartbeat();
+        }
+        let peers_busy = self.poll_peers();
+        let busy = match self.transport.try_recv() {
+            Ok(msg) => {
+                self.coordinator_heard = clock::now();
+                if msg.mtype == MessageType::CoordinatorExit {
+                    trace!("{}::Received exit signal in protocol", self.id_str);
+                    self.exit_received = true;
+                } else if msg.mtype == MessageType::CoordinatorPropose {
+                    self.handle_proposal(msg);
+                } else if msg.mtype == MessageType::CoordinatorOnePhaseCommit {
+                    self.handle_one_phase(msg);
+                } else if msg.mtype == MessageType::CoordinatorPreCommit {
+                    self.handle_precommit(&msg);
+                } else if msg.mtype == MessageType::CoordinatorTerminationRequest {
+                    self.answer_termination(&msg, None);
+                } else if msg.mtype == MessageType::CoordinatorSnapshotRead {
+                    self.snapshot_reads.push(msg);
+                    self.serve_snapshot_reads();
+                } else if let Some(decision) = Self::decision_of(&msg) {
+                    if self.transactions.contains_key(&msg.txid) {
+                        self.handle_decision(decision, &msg.txid, msg.opid, msg.timestamp);
+                    } else if self.decided.contains(&msg.txid) {
+                        match self.outcomes.get(&msg.txid) {
+                            Some((logged, _)) if *logged != decision => {
+                                // Never ack a decision that contradicts the one we applied
+                                error!("{}::Received {:?} for txid: {} but logged {:?}", self.id_str, decision, msg.txid, logged);
+                            },
+                            _ => {
+                                // Our ack was probably lost, so send it again
+                                trace!("{}::Duplicate {:?} for txid: {}", self.id_str, decision, msg.txid);
+                                self.acknowledge(decision, &msg.txid, msg.opid);
+                            },
+                        }
+                    } else {
+                        // Overtook its proposal; hold it until the proposal arrives.
+                        // Nothing was done for it yet, so it can be acked right away.
+                        trace!("{}::Holding {:?} for unknown txid: {}", self.id_str, decision, msg.txid);
+                        self.acknowledge(decision, &msg.txid, msg.opid);
+                        self.mailbox.hold(msg);
+                    }
+                }
+                true
+            },
+            Err(TryRecvError::Empty) => {
+                self.check_transactions();
+                for msg in self.mailbox.expire(Duration::from_millis(5000)) {
+                    trace!("{}::Dropping expired {:?} for txid: {}", self.id_str, msg.mtype, msg.txid);
+                }
+                false
+            },
+            Err(TryRecvError::Disconnected) => {
+                // The coordinator is gone: 3PC can still finish what it started
+                if self.protocol == CommitProtocol::ThreePhase {
+                    self.terminate_transactions(Duration::from_millis(0));
+                }
+                if self.coordinator_gone.is_none() {
+                    self.coordinator_gone = Some(clock::now());
+                }
+                self.ask_peers();
+                false
+            }
+        };
+        busy || peers_busy
+    }
+
+    ///
+    /// poll_peers()
+    /// Takes in new links to peers, answers their termination requests and
+    /// resolves our transactions with the outcomes they report. Returns true
+    /// if a peer message was handled.
+    ///
+    fn poll_peers(&mut self) -> bool {
+        let joined: Vec<(String, T)> = match &self.peer_joins {
+            Some(joins) => joins.try_iter().collect(),
+            None => Vec::new(),
+        };
+        for (name, transport) in joined {
+            self.peer_join(&name, transport);
+        }
+
+        let mut received: Vec<(String, ProtocolMessage)> = Vec::new();
+        let mut lost: Vec<String> = Vec::new();
+        for (name, peer) in self.peers.iter() {
+            loop {
+                match peer.try_recv() {
+                    Ok(msg) => received.push((name.clone(), msg)),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        lost.push(name.clone());
+                        break;
+                    }
+                }
+            }
+        }
+        for name in lost {
+            trace!("{}::Lost link to peer {}", self.id_str, name);
+            self.peers.remove(&name);
+        }
+
+        let busy = !received.is_empty();
+        for (name, msg) in received {
+            if msg.mtype == MessageType::ParticipantTerminationRequest {
+                self.answer_termination(&msg, Some(&name));
+                continue;
+            }
+            if let Some(txn) = self.transactions.get_mut(&msg.txid) {
+                txn.peer_answers.insert(name.clone());
+            }
+            if let Some(decision) = Self::decision_of(&msg) {
+                if self.transactions.contains_key(&msg.txid) {
+                    info!("{}::Learned {:?} for txid: {} from {}", self.id_str, decision, msg.txid, name);
+                    if decision == MessageType::CoordinatorCommit && self.protocol == CommitProtocol::ThreePhase {
+                        self.adopt_precommit(&msg.txid);
+                    }
+                    self.handle_decision(decision, &msg.txid, msg.opid, msg.timestamp);
+                }
+            }
+        }
+        busy
+    }
+
+    ///
+    /// answer_termination()
+    /// Answers a termination request from a peer (or, under 3PC, from a
+    /// recovering coordinator if peer is None) with what we know: the global
+    /// decision if we learned it, or abort if we voted abort. If we have not
+    /// voted yet we vote abort now, which settles it. A pre-committed
+    /// transaction is reported as such. Otherwise we are as uncertain as the
+    /// asker and say so.
+    ///
+    fn answer_termination(&mut self, request: &ProtocolMessage, peer: Option<&str>) {
+        let txid = &request.txid;
+        let (status, timestamp) = match self.outcome_status(txid) {
+            Some(known) => known,
+            None => match self.transactions.get(txid).map(|txn| (txn.state, txn.one_phase)) {
+                Some((ParticipantState::VotedAbort, _)) => (RequestStatus::Aborted, 0),
+                Some((ParticipantState::ReceivedP1, false)) => {
+                    trace!("{}::{} is uncertain, voting abort for txid: {}", self.id_str, request.senderid, txid);
+                    self.vote(txid, false);
+                    (RequestStatus::Aborted, 0)
+                },
+                Some((ParticipantState::PreCommitted, _)) => (RequestStatus::PreCommitted, 0),
+                _ => (RequestStatus::Unknown, 0),
+            },
+        };
+        self.reply_termination(request, status, timestamp, peer);
+    }
+
+    ///
+    /// outcome_status()
+    /// The global decision we know for txid, as a termination reply status
+    /// with its commit timestamp
+    ///
+    fn outcome_status(&self, txid: &str) -> Option<(RequestStatus, u64)> {
+        self.outcomes.get(txid).map(|(decision, timestamp)| match decision {
+            MessageType::CoordinatorCommit => (RequestStatus::Committed, *timestamp),
+            _ => (RequestStatus::Aborted, 0),
+        })
+    }
+
+    ///
+    /// reply_termination()
+    /// Sends a termination reply to the peer that asked, or to the
+    /// coordinator if peer is None
+    ///
+    fn reply_termination(&mut self, request: &ProtocolMessage, status: RequestStatus, timestamp: u64, peer: Option<&str>) {
+        trace!("{}::Telling {} {:?} for txid: {}", self.id_str, request.senderid, status, request.txid);
+        let mut reply = ProtocolMessage::generate(
+            MessageType::ParticipantTerminationReply,
+            request.txid.clone(),
+            self.id_str.clone(),
+            request.opid,
+        );
+        reply.status = status;
+        reply.timestamp = timestamp;
+        match peer {
+            Some(name) => {
+                if let Some(transport) = self.peers.get(name) {
+                    transport.send(reply).unwrap_or(());
+                }
+            },
+            None => self.send(reply),
+        }
+    }
+
+    ///
+    /// adopt_precommit()
+    /// 3PC only: moves a transaction we voted to commit to the pre-committed
+    /// state (and logs the PreCommit), as a backup coordinator would before
+    /// committing it, once a peer told us it commits
+    ///
+    fn adopt_precommit(&mut self, txid: &str) {
+        let opid = match self.transactions.get_mut(txid) {
+            Some(txn) if txn.state == ParticipantState::VotedCommit => {
+                txn.state = ParticipantState::PreCommitted;
+                txn.opid
+            },
+            _ => return,
+        };
+        self.log_record(MessageType::CoordinatorPreCommit, txid.to_string(), opid);
+    }
+
+    ///
+    /// ask_peers()
+    /// Cooperative termination: while the coordinator is gone or silent,
+    /// asks every peer about each transaction we voted to commit without
+    /// learning the outcome, at most once per retry interval. Any peer that
+    /// knows the outcome (see answer_termination()) lets us resolve it.
+    ///
+    fn ask_peers(&mut self) {
+        let retry = Duration::from_millis(500);
+        if self.peers.is_empty() || (self.coordinator_gone.is_none() && clock::elapsed(self.coordinator_heard) <= retry) {
+            return;
+        }
+        let uncertain: Vec<(String, u32)> = self.transactions.iter_mut()
+            .filter(|(_, txn)| !txn.one_phase && matches!(txn.state,
+                ParticipantState::VotedCommit | ParticipantState::PreCommitted))
+            .filter(|(_, txn)| txn.asked_peers.is_none_or(|at| clock::elapsed(at) > retry))
+            .map(|(txid, txn)| {
+                txn.asked_peers = Some(clock::now());
+                (txid.clone(), txn.opid)
+            })
+            .collect();
+        for (txid, opid) in uncertain {
+            trace!("{}::Asking peers for the outcome of txid: {}", self.id_str, txid);
+            let request = ProtocolMessage::generate(
+                MessageType::ParticipantTerminationRequest,
+                txid,
+                self.id_str.clone(),
+                opid,
+            );
+            for peer in self.peers.values() {
+                peer.send(request.clone()).unwrap_or(());
+            }
+        }
+    }
+
+    ///
+    /// heartbeat()
+    /// Tells the coordinator's failure detector we are alive. Heartbeats
+    /// bypass the lossy send(): with a low send success probability enough
+    /// of them in a row would be lost to get a live participant declared
+    /// down.
+    ///
+    fn heartbeat(&mut self) {
+        self.last_heartbeat = clock::now();
+        let heartbeat = ProtocolMessage::generate(
+            MessageType::ParticipantHeartbeat,
+            "heartbeat".to_string(),
+            self.id_str.clone(),
+            0,
+        );
+        self.transport.send(heartbeat).unwrap_or(());
+    }
+
+    ///
+    /// finished()
+    /// True once the coordinator told us to exit, or went away and we had
+    /// our grace period to settle what we can with our peers
+    ///
+    pub fn finished(&self) -> bool {
+        self.exit_received || self.coordinator_gone
+            .is_some_and(|since| self.peers.is_empty() || clock::elapsed(since) > PEER_GRACE)
+    }
+
+    ///
+    /// give_up()
+    /// Counts every transaction still awaiting a decision as unknown
+    ///
+    pub fn give_up(&mut self) {
+        for (txid, _) in self.transactions.drain() {
+            trace!("{}::Gave up waiting for decision on txid: {}", self.id_str, txid);
+            self.unknown_ops += 1;
+        }
+    }
+
+    ///
+    /// protocol()
+    /// Implements the participant side of the 2PC protocol. Proposals are
+    /// voted on as they arrive, so many transactions may await a decision
+    /// at once.
+    /// HINT: If the simulation ends early, don't keep handling requests!
+    /// HINT: Wait for some kind of exit signal before returning from the protocol!
+    ///
+    pub fn protocol(&mut self) {
+        trace!("{}::Beginning protocol", self.id_str.clone());
+
+        if self.recovering && !self.recover() {
+            self.report_status();
+            return;
+        }
+
+        while !self.finished() && self.running.load(std::sync::atomic::Ordering::SeqCst) {
+            if !self.step() && !self.finished() {
+                clock::sleep(Duration::from_millis(1));
+            }
+        }
+
+        // Anything still awaiting a decision has an unknown outcome
+        self.give_up();
+
+        if !self.exit_received {
+            self.wait_for_exit_signal();
+        }
+        info!("{}::Store holds {} committed keys", self.id_str, self.store.len());
+        self.report_status();
+    }
+}
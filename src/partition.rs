@@ -0,0 +1,197 @@
+//!
+//! partition.rs
+//! Key-to-participant partition map the coordinator uses to shard the key
+//! space. A transaction is only proposed to the participants that own the
+//! keys it touches, and each of them only receives its own operations.
+//!
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+
+use message::Operation;
+
+///
+/// Scheme
+/// How keys without an explicit assignment are placed
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Scheme {
+    All,                    // Every participant owns every key (full replication)
+    Hash,                   // A hash of the key picks the owner
+    Range,                  // key_0..key_{num_keys-1} are split into contiguous ranges
+}
+
+///
+/// PartitionMap
+/// Parsed from a spec of comma-separated entries: at most one scheme ("all",
+/// "hash" or "range") and any number of explicit "key=participant"
+/// assignments. With only assignments, other keys are hashed. For example
+/// "range,key_0=2" places key_0 on participant 2 and splits the rest in
+/// ranges.
+///
+#[derive(Clone, Debug)]
+pub struct PartitionMap {
+    spec: String,
+    scheme: Scheme,
+    assigned: HashMap<String, u32>,
+    num_participants: u32,
+    num_keys: u32,
+}
+
+impl PartitionMap {
+
+    ///
+    /// parse(spec, num_participants, num_keys)
+    /// Builds the map described by spec. Panics on a malformed spec, like the
+    /// other option parsers.
+    ///
+    pub fn parse(spec: &str, num_participants: u32, num_keys: u32) -> PartitionMap {
+        let mut scheme = None;
+        let mut assigned = HashMap::new();
+        for entry in spec.split(',').map(|e| e.trim()).filter(|e| !e.is_empty()) {
+            match entry {
+                "all" => scheme = Some(Scheme::All),
+                "hash" => scheme = Some(Scheme::Hash),
+                "range" => scheme = Some(Scheme::Range),
+                _ => {
+                    let (key, owner) = match entry.split_once('=') {
+                        Some(pair) => pair,
+                        None => panic!("unknown partition map entry: {}", entry),
+                    };
+                    let owner = owner.parse::<u32>().expect("partition map owner must be a participant number");
+                    if owner >= num_participants.max(1) {
+                        panic!("partition map assigns {} to missing participant {}", key, owner);
+                    }
+                    assigned.insert(key.to_string(), owner);
+                },
+            }
+        }
+        if scheme == Some(Scheme::All) && !assigned.is_empty() {
+            panic!("partition map cannot assign keys when every participant owns every key");
+        }
+        PartitionMap {
+            spec: spec.to_string(),
+            scheme: scheme.unwrap_or(Scheme::Hash),
+            assigned,
+            num_participants,
+            num_keys,
+        }
+    }
+
+    ///
+    /// as_str()
+    /// The spec this map was parsed from
+    ///
+    pub fn as_str(&self) -> &str {
+        &self.spec
+    }
+
+    ///
+    /// owners(key)
+    /// Numbers of the participants that own key
+    ///
+    pub fn owners(&self, key: &str) -> Vec<u32> {
+        if let Some(owner) = self.assigned.get(key) {
+            return vec![*owner];
+        }
+        let n = self.num_participants.max(1);
+        match self.scheme {
+            Scheme::All => (0..self.num_participants).collect(),
+            Scheme::Range => match key.strip_prefix("key_").and_then(|i| i.parse::<u64>().ok()) {
+                Some(i) if i < self.num_keys as u64 => vec![(i * n as u64 / self.num_keys as u64) as u32],
+                _ => vec![PartitionMap::hash(key) % n],
+            },
+            Scheme::Hash => vec![PartitionMap::hash(key) % n],
+        }
+    }
+
+    ///
+    /// split(ops)
+    /// Splits the operations of a transaction by owning participant, keeping
+    /// their order. Participants with nothing to do are left out.
+    ///
+    pub fn split(&self, ops: &[Operation]) -> BTreeMap<u32, Vec<Operation>> {
+        let mut parts: BTreeMap<u32, Vec<Operation>> = BTreeMap::new();
+        for op in ops.iter() {
+            for owner in self.owners(op.key()) {
+                parts.entry(owner).or_default().push(op.clone());
+            }
+        }
+        parts
+    }
+
+    ///
+    /// hash(key)
+    /// FNV-1a, so every process (and every run) places a key the same way
+    ///
+    fn hash(key: &str) -> u32 {
+        key.bytes().fold(0x811c9dc5u32, |h, b| (h ^ b as u32).wrapping_mul(0x01000193))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_gives_every_key_to_everyone() {
+        let map = PartitionMap::parse("all", 3, 10);
+        assert_eq!(map.owners("key_4"), vec![0, 1, 2]);
+        assert_eq!(map.owners("other"), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn range_splits_keys_in_order() {
+        let map = PartitionMap::parse("range", 3, 9);
+        let owners: Vec<u32> = (0..9).map(|i| map.owners(&format!("key_{}", i))[0]).collect();
+        assert_eq!(owners, vec![0, 0, 0, 1, 1, 1, 2, 2, 2]);
+        // Keys outside the range fall back to hashing
+        assert_eq!(map.owners("key_9"), vec![PartitionMap::hash("key_9") % 3]);
+    }
+
+    #[test]
+    fn hash_places_each_key_once_and_stably() {
+        let map = PartitionMap::parse("hash", 3, 10);
+        for i in 0..10 {
+            let key = format!("key_{}", i);
+            let owners = map.owners(&key);
+            assert_eq!(owners.len(), 1);
+            assert!(owners[0] < 3);
+            assert_eq!(owners, PartitionMap::parse("", 3, 10).owners(&key));
+        }
+    }
+
+    #[test]
+    fn assignments_override_the_scheme() {
+        let map = PartitionMap::parse("range, key_0=2", 3, 9);
+        assert_eq!(map.owners("key_0"), vec![2]);
+        assert_eq!(map.owners("key_1"), vec![0]);
+        assert_eq!(map.as_str(), "range, key_0=2");
+    }
+
+    #[test]
+    fn split_groups_ops_by_owner_in_order() {
+        let map = PartitionMap::parse("range,key_5=0", 2, 4);
+        let ops = vec![
+            Operation::Put("key_3".to_string(), "a".to_string()),
+            Operation::Get("key_0".to_string()),
+            Operation::Delete("key_2".to_string()),
+            Operation::Get("key_5".to_string()),
+        ];
+        let parts = map.split(&ops);
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[&0], vec![ops[1].clone(), ops[3].clone()]);
+        assert_eq!(parts[&1], vec![ops[0].clone(), ops[2].clone()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "missing participant")]
+    fn rejects_assignment_to_missing_participant() {
+        PartitionMap::parse("key_0=3", 3, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown partition map entry")]
+    fn rejects_unknown_entry() {
+        PartitionMap::parse("modulo", 3, 10);
+    }
+}
@@ -0,0 +1,432 @@
+//!
+//! scenario.rs
+//! Scripted failure scenarios, loaded from a JSON file given with
+//! --scenario. A scenario lists events that the "run" harness (and, for
+//! partitions, "simulate") executes at fixed times after the protocol
+//! starts, so a failure that exposed a recovery bug can be replayed:
+//!
+//!     { "events": [
+//!         { "action": "partition", "isolate": ["participant_1"], "at_ms": 2000, "for_ms": 500 },
+//!         { "action": "crash", "target": "participant_2", "at_ms": 1000, "restart_after_ms": 300 },
+//!         { "action": "crash", "target": "coordinator",
+//!           "after_log": { "mtype": "CoordinatorCommit", "txid": "client_0_op_5" } }
+//!     ] }
+//!
+//! A partition cuts the named processes off from the coordinator: every
+//! message between them is lost until it heals. "coordinator" isolates
+//! everyone, and a trailing '*' matches any suffix ("participant_*").
+//!
+//! A crash kills the coordinator or a participant, either at_ms into the
+//! run or right after it appends the after_log record to its log. A
+//! participant is restarted with --recover restart_after_ms later if
+//! given; the coordinator is never restarted, rerun it with --recover.
+//!
+extern crate serde_json;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use fixedhash::HashMap;
+use message::MessageType;
+
+/// Exit status of a process crashed by a scenario, so the harness can tell
+/// it from one that exited normally
+pub const CRASH_STATUS: i32 = 3;
+
+///
+/// LogTrigger
+/// The log record after which a process crashes
+///
+#[derive(serde::Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct LogTrigger {
+    pub mtype: MessageType,
+    pub txid: String,
+}
+
+///
+/// Event
+/// One entry of a scenario file
+///
+#[derive(serde::Deserialize, Clone, Debug)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum Event {
+    Partition {
+        isolate: Vec<String>,
+        at_ms: u64,
+        for_ms: u64,
+    },
+    Crash {
+        target: String,
+        at_ms: Option<u64>,
+        after_log: Option<LogTrigger>,
+        restart_after_ms: Option<u64>,
+    },
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct ScenarioFile {
+    events: Vec<Event>,
+}
+
+///
+/// Action
+/// What the harness does at one point of a scenario's timeline
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    Isolate(String),        // Cut processes matching the pattern off from the coordinator
+    Heal(String),           // Undo one Isolate of the same pattern
+    Crash(String),          // Kill the process
+    Restart(String),        // Start the process again, recovering from its log
+}
+
+///
+/// Scenario
+/// The events of a scenario file, or none
+///
+#[derive(Clone, Debug)]
+pub struct Scenario {
+    path: String,
+    events: Vec<Event>,
+}
+
+impl Scenario {
+
+    ///
+    /// load(path)
+    /// Reads the scenario file at path; "none" is the empty scenario.
+    /// Panics on a missing or malformed file, like the other option parsers.
+    ///
+    pub fn load(path: &str) -> Scenario {
+        if path == "none" {
+            return Scenario { path: path.to_string(), events: Vec::new() };
+        }
+        let file = File::open(path).unwrap_or_else(|e| panic!("cannot open scenario {}: {}", path, e));
+        let parsed: ScenarioFile = serde_json::from_reader(BufReader::new(file))
+            .unwrap_or_else(|e| panic!("malformed scenario {}: {}", path, e));
+        for event in parsed.events.iter() {
+            match event {
+                Event::Partition { isolate, .. } => {
+                    if isolate.is_empty() {
+                        panic!("scenario partition isolates nobody: {:?}", event);
+                    }
+                },
+                Event::Crash { target, at_ms, after_log, restart_after_ms } => {
+                    if at_ms.is_some() == after_log.is_some() {
+                        panic!("scenario crash needs exactly one of at_ms and after_log: {:?}", event);
+                    }
+                    if target != "coordinator" && !target.starts_with("participant_") {
+                        panic!("scenario can only crash the coordinator or a participant: {}", target);
+                    }
+                    if target == "coordinator" && restart_after_ms.is_some() {
+                        panic!("scenario cannot restart the coordinator, rerun it with --recover");
+                    }
+                },
+            }
+        }
+        Scenario { path: path.to_string(), events: parsed.events }
+    }
+
+    ///
+    /// as_str()
+    /// The path this scenario was loaded from
+    ///
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+
+    ///
+    /// is_empty()
+    /// True if the scenario has no events
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    ///
+    /// has_crashes()
+    /// True if the scenario crashes any process
+    ///
+    pub fn has_crashes(&self) -> bool {
+        self.events.iter().any(|e| matches!(e, Event::Crash { .. }))
+    }
+
+    ///
+    /// timeline()
+    /// The timed actions of the scenario, ordered by when they are due
+    /// (measured from the start of the protocol). Crashes on a log record
+    /// are not on it: the process crashes itself (see crash_point).
+    ///
+    pub fn timeline(&self) -> Vec<(Duration, Action)> {
+        let mut timeline = Vec::new();
+        for event in self.events.iter() {
+            match event {
+                Event::Partition { isolate, at_ms, for_ms } => {
+                    for pattern in isolate.iter() {
+                        timeline.push((Duration::from_millis(*at_ms), Action::Isolate(pattern.clone())));
+                        timeline.push((Duration::from_millis(at_ms + for_ms), Action::Heal(pattern.clone())));
+                    }
+                },
+                Event::Crash { target, at_ms: Some(at_ms), restart_after_ms, .. } => {
+                    timeline.push((Duration::from_millis(*at_ms), Action::Crash(target.clone())));
+                    if let Some(after) = restart_after_ms {
+                        timeline.push((Duration::from_millis(at_ms + after), Action::Restart(target.clone())));
+                    }
+                },
+                Event::Crash { .. } => {},
+            }
+        }
+        timeline.sort_by_key(|(at, _)| *at);
+        timeline
+    }
+
+    ///
+    /// crash_point(name)
+    /// The log record after which process name crashes, if any
+    ///
+    pub fn crash_point(&self, name: &str) -> Option<LogTrigger> {
+        self.events.iter().find_map(|e| match e {
+            Event::Crash { target, after_log: Some(trigger), .. } if target == name => Some(trigger.clone()),
+            _ => None,
+        })
+    }
+
+    ///
+    /// restart_after(name)
+    /// How long after crashing on its log record process name is restarted,
+    /// if it is
+    ///
+    pub fn restart_after(&self, name: &str) -> Option<Duration> {
+        self.events.iter().find_map(|e| match e {
+            Event::Crash { target, after_log: Some(_), restart_after_ms: Some(after), .. } if target == name => {
+                Some(Duration::from_millis(*after))
+            },
+            _ => None,
+        })
+    }
+}
+
+///
+/// Partitions
+/// The isolation switches of the coordinator's links (see
+/// FaultyTransport::isolation), by the name of the process at the other end.
+/// Partitions may overlap, so a link stays cut until every Isolate that
+/// covers it is healed.
+///
+#[derive(Default)]
+pub struct Partitions {
+    links: HashMap<String, Arc<AtomicBool>>,
+    cuts: HashMap<String, u32>,
+}
+
+impl Partitions {
+
+    ///
+    /// new()
+    /// Returns an empty set of links
+    ///
+    pub fn new() -> Partitions {
+        Partitions::default()
+    }
+
+    ///
+    /// add(name, switch)
+    /// Registers the link to process name, replacing any earlier link to it
+    /// (e.g. of a process that was restarted). The new link is cut if the
+    /// process is currently isolated.
+    ///
+    pub fn add(&mut self, name: &str, switch: Arc<AtomicBool>) {
+        switch.store(self.cuts.get(name).is_some_and(|c| *c > 0), Ordering::SeqCst);
+        self.links.insert(name.to_string(), switch);
+    }
+
+    ///
+    /// apply(action)
+    /// Cuts or heals the links matching an Isolate or Heal action. Other
+    /// actions are ignored.
+    ///
+    pub fn apply(&mut self, action: &Action) {
+        let (pattern, cut) = match action {
+            Action::Isolate(pattern) => (pattern, true),
+            Action::Heal(pattern) => (pattern, false),
+            _ => return,
+        };
+        let matches = |name: &str| match pattern.strip_suffix('*') {
+            _ if pattern == "coordinator" => true,
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        };
+        for (name, switch) in self.links.iter().filter(|(name, _)| matches(name)) {
+            let count = self.cuts.entry(name.clone()).or_insert(0);
+            if cut {
+                *count += 1;
+            } else {
+                *count = count.saturating_sub(1);
+            }
+            info!("scenario::Link to {} {}", name, if *count > 0 { "cut" } else { "healed" });
+            switch.store(*count > 0, Ordering::SeqCst);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// A scenario file that is removed again, even if loading it panics
+    struct TempFile(PathBuf);
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            fs::remove_file(&self.0).unwrap_or(());
+        }
+    }
+
+    /// Writes a scenario file for a test and loads it
+    fn scenario(name: &str, json: &str) -> Scenario {
+        let file = TempFile(env::temp_dir().join(format!("tpc-scenario-{}-{}.json", name, process::id())));
+        fs::write(&file.0, json).unwrap();
+        Scenario::load(file.0.to_str().unwrap())
+    }
+
+    fn cut(links: &HashMap<String, Arc<AtomicBool>>, name: &str) -> bool {
+        links[name].load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn none_is_empty() {
+        let scenario = Scenario::load("none");
+        assert!(scenario.is_empty());
+        assert!(!scenario.has_crashes());
+        assert!(scenario.timeline().is_empty());
+        assert_eq!(scenario.crash_point("coordinator"), None);
+    }
+
+    #[test]
+    fn timeline_is_ordered_by_due_time() {
+        let scenario = scenario("timeline", r#"{ "events": [
+            { "action": "crash", "target": "participant_2", "at_ms": 1000, "restart_after_ms": 300 },
+            { "action": "partition", "isolate": ["participant_1", "client_*"], "at_ms": 200, "for_ms": 1500 }
+        ] }"#);
+        let ms = Duration::from_millis;
+        assert!(scenario.has_crashes());
+        assert_eq!(scenario.timeline(), vec![
+            (ms(200), Action::Isolate("participant_1".to_string())),
+            (ms(200), Action::Isolate("client_*".to_string())),
+            (ms(1000), Action::Crash("participant_2".to_string())),
+            (ms(1300), Action::Restart("participant_2".to_string())),
+            (ms(1700), Action::Heal("participant_1".to_string())),
+            (ms(1700), Action::Heal("client_*".to_string())),
+        ]);
+        assert_eq!(scenario.restart_after("participant_2"), None);
+    }
+
+    #[test]
+    fn log_triggered_crashes_are_not_on_the_timeline() {
+        let scenario = scenario("after-log", r#"{ "events": [
+            { "action": "crash", "target": "participant_0", "restart_after_ms": 250,
+              "after_log": { "mtype": "ParticipantVoteCommit", "txid": "client_0_op_2" } },
+            { "action": "crash", "target": "coordinator",
+              "after_log": { "mtype": "CoordinatorCommit", "txid": "client_1_op_5" } }
+        ] }"#);
+        assert!(scenario.timeline().is_empty());
+        assert_eq!(scenario.crash_point("participant_0"), Some(LogTrigger {
+            mtype: MessageType::ParticipantVoteCommit,
+            txid: "client_0_op_2".to_string(),
+        }));
+        assert_eq!(scenario.restart_after("participant_0"), Some(Duration::from_millis(250)));
+        assert_eq!(scenario.crash_point("coordinator").unwrap().mtype, MessageType::CoordinatorCommit);
+        assert_eq!(scenario.restart_after("coordinator"), None);
+        assert_eq!(scenario.crash_point("participant_1"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "exactly one of at_ms and after_log")]
+    fn rejects_crash_with_two_triggers() {
+        scenario("two-triggers", r#"{ "events": [
+            { "action": "crash", "target": "participant_0", "at_ms": 10,
+              "after_log": { "mtype": "CoordinatorCommit", "txid": "client_0_op_1" } }
+        ] }"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "exactly one of at_ms and after_log")]
+    fn rejects_crash_without_trigger() {
+        scenario("no-trigger", r#"{ "events": [ { "action": "crash", "target": "participant_0" } ] }"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "only crash the coordinator or a participant")]
+    fn rejects_crashing_a_client() {
+        scenario("client", r#"{ "events": [ { "action": "crash", "target": "client_0", "at_ms": 10 } ] }"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot restart the coordinator")]
+    fn rejects_restarting_the_coordinator() {
+        scenario("restart", r#"{ "events": [
+            { "action": "crash", "target": "coordinator", "at_ms": 10, "restart_after_ms": 10 }
+        ] }"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "isolates nobody")]
+    fn rejects_empty_partition() {
+        scenario("empty", r#"{ "events": [ { "action": "partition", "isolate": [], "at_ms": 0, "for_ms": 10 } ] }"#);
+    }
+
+    #[test]
+    #[should_panic(expected = "malformed scenario")]
+    fn rejects_unknown_action() {
+        scenario("unknown", r#"{ "events": [ { "action": "explode", "at_ms": 0 } ] }"#);
+    }
+
+    #[test]
+    fn overlapping_partitions_heal_when_all_do() {
+        let mut partitions = Partitions::new();
+        let mut links = HashMap::default();
+        for name in ["participant_0", "participant_1", "client_0"].iter() {
+            let switch = Arc::new(AtomicBool::new(false));
+            partitions.add(name, switch.clone());
+            links.insert(name.to_string(), switch);
+        }
+
+        partitions.apply(&Action::Isolate("participant_*".to_string()));
+        partitions.apply(&Action::Isolate("participant_1".to_string()));
+        assert!(cut(&links, "participant_0") && cut(&links, "participant_1"));
+        assert!(!cut(&links, "client_0"));
+
+        partitions.apply(&Action::Heal("participant_*".to_string()));
+        assert!(!cut(&links, "participant_0"));
+        assert!(cut(&links, "participant_1"));
+        partitions.apply(&Action::Heal("participant_1".to_string()));
+        assert!(!cut(&links, "participant_1"));
+
+        // "coordinator" isolates everyone
+        partitions.apply(&Action::Isolate("coordinator".to_string()));
+        assert!(links.keys().all(|name| cut(&links, name)));
+        partitions.apply(&Action::Heal("coordinator".to_string()));
+        assert!(links.keys().all(|name| !cut(&links, name)));
+    }
+
+    #[test]
+    fn restarted_process_rejoins_cut_off_while_isolated() {
+        let mut partitions = Partitions::new();
+        partitions.add("participant_0", Arc::new(AtomicBool::new(false)));
+        partitions.apply(&Action::Isolate("participant_0".to_string()));
+
+        let restarted = Arc::new(AtomicBool::new(false));
+        partitions.add("participant_0", restarted.clone());
+        assert!(restarted.load(Ordering::SeqCst));
+        partitions.apply(&Action::Heal("participant_0".to_string()));
+        assert!(!restarted.load(Ordering::SeqCst));
+    }
+}
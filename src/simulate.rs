@@ -0,0 +1,218 @@
+//!
+//! simulate.rs
+//! Deterministic simulation: the coordinator, participants and clients run
+//! as state machines in this one process, on the virtual clock and seeded
+//! RNG of clock.rs. A scheduler picks the order in which they take steps
+//! and how long each message is in flight, so two runs with the same seed
+//! and options produce the same logs and a failing run can be replayed.
+//!
+extern crate rand;
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::time::Instant;
+
+use simulate::rand::prelude::*;
+
+use clock;
+use client::Client;
+use coordinator::Coordinator;
+use faults::FaultyTransport;
+use message::ProtocolMessage;
+use participant::Participant;
+use scenario::Partitions;
+use tpcoptions::TPCOptions;
+use transport::SendError;
+use transport::Transport;
+use transport::TryRecvError;
+
+/// Messages are in flight for up to this long
+const MAX_LATENCY: Duration = Duration::from_micros(2000);
+
+/// Virtual time passes in steps of at most this much while everyone is idle
+const TICK: Duration = Duration::from_millis(1);
+
+/// How long participants get to hear the coordinator shut down; the exit
+/// message may well be lost
+const SHUTDOWN_GRACE: Duration = Duration::from_millis(1000);
+
+/// Messages in flight in one direction of a link, with when they arrive
+type Queue = Arc<Mutex<VecDeque<(Instant, ProtocolMessage)>>>;
+
+///
+/// SimTransport
+/// One end of a simulated link. Messages arrive in the order they were
+/// sent, each after a random latency.
+///
+pub struct SimTransport {
+    outbox: Queue,
+    inbox: Queue,
+}
+
+impl SimTransport {
+
+    ///
+    /// pair(queues)
+    /// Returns both ends of a new link, and adds its queues to queues so the
+    /// scheduler can see what is in flight
+    ///
+    fn pair(queues: &mut Vec<Queue>) -> (SimTransport, SimTransport) {
+        let a_to_b: Queue = Arc::new(Mutex::new(VecDeque::new()));
+        let b_to_a: Queue = Arc::new(Mutex::new(VecDeque::new()));
+        queues.push(a_to_b.clone());
+        queues.push(b_to_a.clone());
+        (SimTransport { outbox: a_to_b.clone(), inbox: b_to_a.clone() },
+         SimTransport { outbox: b_to_a, inbox: a_to_b })
+    }
+}
+
+impl Transport for SimTransport {
+    fn send(&self, msg: ProtocolMessage) -> Result<(), SendError> {
+        let latency = clock::with_rng(|rng| rng.gen_range(0, MAX_LATENCY.as_micros() as u64));
+        let mut outbox = self.outbox.lock().unwrap();
+        let mut arrival = clock::now() + Duration::from_micros(latency);
+        if let Some((last, _)) = outbox.back() {
+            arrival = arrival.max(*last);
+        }
+        outbox.push_back((arrival, msg));
+        Ok(())
+    }
+
+    fn try_recv(&self) -> Result<ProtocolMessage, TryRecvError> {
+        let mut inbox = self.inbox.lock().unwrap();
+        match inbox.front() {
+            Some((arrival, _)) if *arrival <= clock::now() => Ok(inbox.pop_front().unwrap().1),
+            _ => Err(TryRecvError::Empty),
+        }
+    }
+
+    // Nothing arrives while the caller waits: time only passes between steps
+    fn recv_timeout(&self, _timeout: Duration) -> Result<ProtocolMessage, TryRecvError> {
+        self.try_recv()
+    }
+}
+
+///
+/// idle(queues)
+/// Lets virtual time pass up to the next message arriving, but by no more
+/// than a tick. Messages that arrived already are not waited for: nobody
+/// may be left to receive them.
+///
+fn idle(queues: &[Queue]) {
+    let now = clock::now();
+    let next = queues.iter()
+        .filter_map(|q| q.lock().unwrap().iter().map(|(arrival, _)| *arrival).find(|arrival| *arrival > now))
+        .min();
+    clock::advance(next.map_or(TICK, |arrival| (arrival - now).min(TICK)));
+}
+
+///
+/// run(opts, running)
+///     opts: An options structure containing the CLI arguments
+///     running: An atomically reference counted (ARC) AtomicBool(ean) that is
+///         set to be false whenever Ctrl+C is pressed
+///
+/// 1. Switches this thread to virtual time and an RNG seeded with opts.seed
+/// 2. Creates the coordinator, participants and clients, connected by
+///    simulated links, and links the participants to each other
+/// 3. Steps them in a random order each round until every client finished
+///    its requests, cutting and healing links as the scenario (if any)
+///    says on the way
+/// 4. Shuts the coordinator down, lets the participants hear about it and
+///    reports everyone's status
+///
+pub fn run(opts: &TPCOptions, running: Arc<AtomicBool>) {
+    println!("Simulating with seed {}", opts.seed);
+    clock::simulate(opts.seed);
+
+    let mut queues: Vec<Queue> = Vec::new();
+    let mut partitions = Partitions::new();
+    let coord_log_path = format!("{}//{}", opts.log_path, "coordinator.log");
+    let mut coordinator = Coordinator::new(coord_log_path, &running, opts);
+
+    let mut participants = Vec::new();
+    for i in 0..opts.num_participants {
+        let name = format!("participant_{}", i);
+        let log_path = format!("{}//{}.log", opts.log_path, name);
+        let (coordinator_end, participant_end) = SimTransport::pair(&mut queues);
+        let coordinator_end = FaultyTransport::new(coordinator_end, "coordinator", &name, &opts.faults);
+        partitions.add(&name, coordinator_end.isolation());
+        coordinator.participant_join(&name, coordinator_end);
+        let participant_end = FaultyTransport::new(participant_end, &name, "coordinator", &opts.faults);
+        participants.push(Participant::new(name, log_path, running.clone(), opts, participant_end));
+    }
+
+    // Link every pair of participants, for cooperative termination
+    for i in 0..participants.len() {
+        for j in i + 1..participants.len() {
+            let (name_i, name_j) = (format!("participant_{}", i), format!("participant_{}", j));
+            let (end_i, end_j) = SimTransport::pair(&mut queues);
+            participants[i].peer_join(&name_j, FaultyTransport::new(end_i, &name_i, &name_j, &opts.faults));
+            participants[j].peer_join(&name_i, FaultyTransport::new(end_j, &name_j, &name_i, &opts.faults));
+        }
+    }
+
+    let mut clients = Vec::new();
+    for i in 0..opts.num_clients {
+        let name = format!("client_{}", i);
+        let (coordinator_end, client_end) = SimTransport::pair(&mut queues);
+        let coordinator_end = FaultyTransport::new(coordinator_end, "coordinator", &name, &opts.faults);
+        partitions.add(&name, coordinator_end.isolation());
+        coordinator.client_join(&name, coordinator_end);
+        let client_end = FaultyTransport::new(client_end, &name, "coordinator", &opts.faults);
+        clients.push(Client::new(name, opts.num_keys, running.clone(), client_end));
+    }
+
+    // Actor 0 is the coordinator, then the participants, then the clients
+    let num_participants = participants.len();
+    let mut order: Vec<usize> = (0..1 + participants.len() + clients.len()).collect();
+    let mut clients_done = false;
+    let timeline = opts.scenario.timeline();
+    let mut next_action = 0;
+    let start = clock::now();
+    while !clients_done && running.load(Ordering::SeqCst) {
+        while next_action < timeline.len() && timeline[next_action].0 <= clock::elapsed(start) {
+            partitions.apply(&timeline[next_action].1);
+            next_action += 1;
+        }
+        clock::with_rng(|rng| order.shuffle(rng));
+        let mut busy = false;
+        clients_done = true;
+        for actor in order.iter() {
+            match *actor {
+                0 => busy |= coordinator.step(),
+                i if i <= num_participants => {
+                    let participant = &mut participants[i - 1];
+                    if !participant.finished() {
+                        busy |= participant.step();
+                    }
+                },
+                i => clients_done &= clients[i - 1 - num_participants].step(opts.num_requests),
+            }
+        }
+        if !busy {
+            idle(&queues);
+        }
+    }
+
+    coordinator.shutdown();
+    let shutdown_at = clock::now();
+    while participants.iter().any(|p| !p.finished()) && clock::elapsed(shutdown_at) < SHUTDOWN_GRACE {
+        for participant in participants.iter_mut().filter(|p| !p.finished()) {
+            participant.step();
+        }
+        idle(&queues);
+    }
+
+    coordinator.report_status();
+    for participant in participants.iter_mut() {
+        participant.give_up();
+        participant.report_status();
+    }
+    for client in clients.iter_mut() {
+        client.report_status();
+    }
+}
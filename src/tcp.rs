@@ -0,0 +1,157 @@
+//!
+//! tcp.rs
+//! TCP transport so the coordinator, participants and clients can run as
+//! independently launched processes. Messages travel as length-prefixed
+//! bincode frames; TcpTransport carries them for the roles.
+//!
+extern crate bincode;
+extern crate serde;
+
+use std::io;
+use std::io::prelude::*;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use tcp::serde::Serialize;
+use tcp::serde::de::DeserializeOwned;
+
+use message::ProtocolMessage;
+use transport::SendError;
+use transport::Transport;
+use transport::TryRecvError;
+
+/// Largest frame we accept; anything bigger means the stream is garbage
+const MAX_FRAME: u32 = 16 << 20;
+
+///
+/// Hello
+/// First frame on every connection: who is connecting (e.g. "participant_1")
+///
+#[derive(serde::Serialize, serde::Deserialize, Clone, Debug)]
+struct Hello {
+    name: String,
+}
+
+///
+/// write_frame(stream, value)
+/// Sends value as a big-endian u32 length followed by its bincode encoding
+///
+fn write_frame<W: Write, T: Serialize>(stream: &mut W, value: &T) -> io::Result<()> {
+    let bytes = bincode::serialize(value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    stream.write_all(&(bytes.len() as u32).to_be_bytes())?;
+    stream.write_all(&bytes)?;
+    stream.flush()
+}
+
+///
+/// read_frame(stream)
+/// Blocks until a whole frame arrives and decodes it
+///
+fn read_frame<T: DeserializeOwned>(stream: &mut TcpStream) -> io::Result<T> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {} bytes", len)));
+    }
+    let mut bytes = vec![0u8; len as usize];
+    stream.read_exact(&mut bytes)?;
+    bincode::deserialize(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+///
+/// TcpTransport
+/// Link over one TCP connection. Frames are written straight to the socket;
+/// a reader thread decodes incoming frames into a channel, which reports a
+/// disconnect once the connection breaks.
+///
+pub struct TcpTransport {
+    stream: TcpStream,
+    inbox: mpsc::Receiver<ProtocolMessage>,
+}
+
+impl TcpTransport {
+    fn new(stream: TcpStream) -> io::Result<TcpTransport> {
+        let mut reader = stream.try_clone()?;
+        let (inbox_tx, inbox) = mpsc::channel();
+        thread::spawn(move || {
+            while let Ok(msg) = read_frame::<ProtocolMessage>(&mut reader) {
+                if inbox_tx.send(msg).is_err() {
+                    break;
+                }
+            }
+            trace!("tcp::Connection closed by remote end");
+        });
+        Ok(TcpTransport { stream, inbox })
+    }
+}
+
+impl Transport for TcpTransport {
+    fn send(&self, msg: ProtocolMessage) -> Result<(), SendError> {
+        write_frame(&mut &self.stream, &msg).map_err(|_| SendError)
+    }
+
+    fn try_recv(&self) -> Result<ProtocolMessage, TryRecvError> {
+        match self.inbox.try_recv() {
+            Ok(msg) => Ok(msg),
+            Err(mpsc::TryRecvError::Empty) => Err(TryRecvError::Empty),
+            Err(mpsc::TryRecvError::Disconnected) => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<ProtocolMessage, TryRecvError> {
+        match self.inbox.recv_timeout(timeout) {
+            Ok(msg) => Ok(msg),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(TryRecvError::Empty),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(TryRecvError::Disconnected),
+        }
+    }
+}
+
+impl Drop for TcpTransport {
+    // Wakes up the reader thread
+    fn drop(&mut self) {
+        self.stream.shutdown(std::net::Shutdown::Both).unwrap_or(());
+    }
+}
+
+///
+/// connect(address, name)
+/// Connects to a coordinator listening on address and introduces ourselves
+/// as name. Keeps retrying for a while, since independently launched
+/// processes may come up before the coordinator does.
+///
+pub fn connect(address: &str, name: &str) -> io::Result<TcpTransport> {
+    let give_up = Instant::now() + Duration::from_secs(10);
+    let mut stream = loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => break stream,
+            Err(e) if Instant::now() < give_up => {
+                trace!("{}::Coordinator at {} not reachable yet: {}", name, address, e);
+                thread::sleep(Duration::from_millis(100));
+            },
+            Err(e) => return Err(e),
+        }
+    };
+    stream.set_nodelay(true)?;
+    write_frame(&mut stream, &Hello { name: name.to_string() })?;
+    info!("{}::Connected to coordinator at {}", name, address);
+    TcpTransport::new(stream)
+}
+
+///
+/// accept(listener)
+/// Waits for the next process to connect. Returns its name and the transport
+/// to talk to it with.
+///
+pub fn accept(listener: &TcpListener) -> io::Result<(String, TcpTransport)> {
+    let (mut stream, peer) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    let hello: Hello = read_frame(&mut stream)?;
+    info!("coordinator::{} connected from {}", hello.name, peer);
+    Ok((hello.name, TcpTransport::new(stream)?))
+}
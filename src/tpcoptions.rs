@@ -1,169 +1,459 @@
-//!
-//! TPCOptions
-//! A simple tool for managing command line options and trace/log/debug
-//! instrumentation for the _T_wo _P_hase _C_ommit project. Exports a
-//! constructor for a struct that represents command line options for the
-//! project, uses the clap crate to collect command line options, and use the
-//! log and stderrlog crates to initialize the application to use trace!(),
-//! debug!(), info!() etc macros from the log crate.
-//!
-extern crate log;
-extern crate stderrlog;
-extern crate clap;
-use clap::{Arg, App};
-
-extern crate ctrlc;
-#[derive(Clone, Debug)]
-pub struct TPCOptions {
-    pub send_success_probability: f64,        // Probability that a message send succeeds
-    pub operation_success_probability: f64,   // Probability that an operation succeeds
-    pub num_clients: u32,                     // Number of concurrent clients issuing requests
-    pub num_requests: u32,                    // Number of requests issued per client
-    pub num_participants: u32,                // Number of participants in 2PC protocol (not including coordinator)
-    pub verbosity: usize,                     // Integer verbosity level. experiment with 0 (default) to 5 (fire-hose of output)
-    pub mode: String,                         // One of "run", "client", "particpant", "check"
-    pub log_path: String,                     // Directory for client, participant, and coordinator logs
-    pub ipc_path: String,                     // Path to IPC socket for setting up communication with the coordinator
-    pub num: u32,                             // Participant / Client number for naming the log files
-}
-
-impl TPCOptions {
-
-    ///
-    /// new()
-    /// Returns a new options structure containing CLI options / defaults
-    ///
-    pub fn new() -> TPCOptions {
-
-        // Default values for CLI options
-        let default_send_success_probability = "1.0";
-        let default_operation_success_probability = "1.0";
-        let default_num_participants = "3";
-        let default_num_clients = "3";
-        let default_num_requests = "15";
-        let default_verbosity = "0";
-        let default_mode = "run";
-        let default_log_path = "./logs/";
-        let default_ipc_path = "none";
-        let default_num = "0";
-
-        // Set-Up clap
-        let matches = App::new("concurrency-2pc")
-            .version("0.1.0")
-            .author("Rust Student <student@cs.utexas.edu>")
-            .about("2PC exercise written in Rust")
-            .arg(Arg::with_name("send_success_probability")
-                    .short("S")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Probability participants successfully send messages"))
-            .arg(Arg::with_name("operation_success_probability")
-                    .short("s")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Probability participants successfully execute requests"))
-            .arg(Arg::with_name("num_clients")
-                    .short("c")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Number of clients making requests"))
-            .arg(Arg::with_name("num_participants")
-                    .short("p")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Number of participants in protocol"))
-            .arg(Arg::with_name("num_requests")
-                    .short("r")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Number of requests made per client"))
-            .arg(Arg::with_name("verbosity")
-                    .short("v")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Output verbosity: 0->No Output, 5->Output Everything"))
-            .arg(Arg::with_name("log_path")
-                    .short("l")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Specifies path to directory where logs are stored"))
-            .arg(Arg::with_name("mode")
-                    .short("m")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Mode: \"run\" starts 2PC, \"client\" starts a client process, \"participant\" starts a participant process, \"check\" checks logs produced by previous run"))
-            .arg(Arg::with_name("ipc_path")
-                    .long("ipc_path")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Path for IPC socket for communication"))
-            .arg(Arg::with_name("num")
-                    .long("num")
-                    .required(false)
-                    .takes_value(true)
-                    .help("Participant / Client number for naming the log files. Ranges from 0 to num_clients - 1 or num_participants - 1"))
-            .get_matches();
-
-        // Parse CLI options and take default values if none given
-        let mode = matches.value_of("mode").unwrap_or(default_mode);
-        let operation_success_probability = matches.value_of("operation_success_probability").unwrap_or(default_operation_success_probability).parse::<f64>().unwrap();
-        let send_success_probability = matches.value_of("send_success_probability").unwrap_or(default_send_success_probability).parse::<f64>().unwrap();
-        let num_clients = matches.value_of("num_clients").unwrap_or(default_num_clients).parse::<u32>().unwrap();
-        let num_participants = matches.value_of("num_participants").unwrap_or(default_num_participants).parse::<u32>().unwrap();
-        let num_requests = matches.value_of("num_requests").unwrap_or(default_num_requests).parse::<u32>().unwrap();
-        let verbosity = matches.value_of("verbosity").unwrap_or(default_verbosity).parse::<usize>().unwrap();
-        let log_path = matches.value_of("log_path").unwrap_or(default_log_path);
-        let ipc_path = matches.value_of("ipc_path").unwrap_or(default_ipc_path);
-        let num = matches.value_of("num").unwrap_or(default_num).parse::<u32>().unwrap();
-
-        // IPC path is necessary for client / participant to communicate with the coordinator
-        match mode.as_ref() {
-            "run" => {},
-            "client" => {
-                if ipc_path == default_ipc_path {
-                    panic!("No ipc_path specified for client mode");
-                }
-            },
-            "participant" => {
-                if ipc_path == default_ipc_path {
-                    panic!("No ipc_path specified for participant mode");
-                }
-            },
-            "check" => {},
-            _ => panic!("unknown execution mode requested!"),
-        }
-
-        TPCOptions {
-            send_success_probability: send_success_probability,
-            operation_success_probability: operation_success_probability,
-            num_clients: num_clients,
-            num_participants: num_participants,
-            num_requests: num_requests,
-            verbosity: verbosity,
-            mode: mode.to_string(),
-            log_path: log_path.to_string(),
-            ipc_path: ipc_path.to_string(),
-            num: num,
-        }
-    }
-
-    ///
-    /// Convert this struct to a vector of CLI options
-    ///
-    /// HINT: This can be useful for passing arguments to children processes
-    ///       using the std::process::Command's args(..) function
-    pub fn as_vec(&self) -> Vec<String> {
-        vec![
-            format!("-S{}", self.send_success_probability),
-            format!("-s{}", self.operation_success_probability),
-            format!("-c{}", self.num_clients),
-            format!("-r{}", self.num_requests),
-            format!("-p{}", self.num_participants),
-            format!("-v{}", self.verbosity),
-            format!("-m{}", self.mode),
-            format!("-l{}", self.log_path),
-            format!("--ipc_path={}", self.ipc_path),
-            format!("--num={}", self.num),
-        ]
-    }
-}
+//!
+//! TPCOptions
+//! A simple tool for managing command line options and trace/log/debug
+//! instrumentation for the _T_wo _P_hase _C_ommit project. Exports a
+//! constructor for a struct that represents command line options for the
+//! project, uses the clap crate to collect command line options, and use the
+//! log and stderrlog crates to initialize the application to use trace!(),
+//! debug!(), info!() etc macros from the log crate.
+//!
+extern crate log;
+extern crate stderrlog;
+extern crate clap;
+use clap::{Arg, App};
+
+use clock;
+use crashpoint::CrashPlan;
+use faults::FaultPlan;
+use message::MessageType;
+use partition::PartitionMap;
+use scenario::Scenario;
+
+extern crate ctrlc;
+
+///
+/// CommitProtocol
+/// Atomic commitment protocol run between the coordinator and participants
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitProtocol {
+    TwoPhase,               // Blocking two-phase commit
+    ThreePhase,             // Non-blocking three-phase commit (adds a PreCommit phase)
+}
+
+impl CommitProtocol {
+    pub fn parse(s: &str) -> CommitProtocol {
+        match s {
+            "2pc" => CommitProtocol::TwoPhase,
+            "3pc" => CommitProtocol::ThreePhase,
+            _ => panic!("unknown commit protocol requested!"),
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CommitProtocol::TwoPhase => "2pc",
+            CommitProtocol::ThreePhase => "3pc",
+        }
+    }
+}
+
+///
+/// StorageMode
+/// Concurrency control participants use for their key-value stores
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StorageMode {
+    Locking,                // Single-version store under strict two-phase locking
+    Mvcc,                   // Multi-version store under snapshot isolation, no locks
+}
+
+impl StorageMode {
+    pub fn parse(s: &str) -> StorageMode {
+        match s {
+            "locking" => StorageMode::Locking,
+            "mvcc" => StorageMode::Mvcc,
+            _ => panic!("unknown storage mode requested!"),
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StorageMode::Locking => "locking",
+            StorageMode::Mvcc => "mvcc",
+        }
+    }
+}
+
+///
+/// LoggingMode
+/// Logging variant of the commit protocol. Decides which log records must be
+/// forced to stable storage, which decisions participants acknowledge, and
+/// what outcome is presumed for a transaction the coordinator has no record of.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoggingMode {
+    Standard,               // Force every decision, acknowledge every decision
+    PresumedAbort,          // Aborts are neither forced nor acknowledged
+    PresumedCommit,         // Force the proposal; commits are neither forced nor acknowledged
+}
+
+impl LoggingMode {
+    pub fn parse(s: &str) -> LoggingMode {
+        match s {
+            "standard" => LoggingMode::Standard,
+            "presumed_abort" => LoggingMode::PresumedAbort,
+            "presumed_commit" => LoggingMode::PresumedCommit,
+            _ => panic!("unknown logging mode requested!"),
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoggingMode::Standard => "standard",
+            LoggingMode::PresumedAbort => "presumed_abort",
+            LoggingMode::PresumedCommit => "presumed_commit",
+        }
+    }
+
+    ///
+    /// coordinator_forces(mtype)
+    /// Whether the coordinator must force a log record of this type
+    ///
+    pub fn coordinator_forces(&self, mtype: MessageType) -> bool {
+        match (self, mtype) {
+            // Presumed commit needs to know which transactions were in flight
+            (LoggingMode::PresumedCommit, MessageType::CoordinatorPropose) => true,
+            (_, MessageType::CoordinatorPropose) => false,
+            (_, MessageType::CoordinatorEnd) => false,
+            // Membership changes are informational, nothing is recovered from them
+            (_, MessageType::CoordinatorMemberUp | MessageType::CoordinatorMemberSuspected | MessageType::CoordinatorMemberDown) => false,
+            (LoggingMode::PresumedAbort, MessageType::CoordinatorAbort) => false,
+            _ => true,
+        }
+    }
+
+    ///
+    /// participant_forces(mtype)
+    /// Whether a participant must force a log record of this type. A
+    /// read-only vote promises nothing, so it is never forced.
+    ///
+    pub fn participant_forces(&self, mtype: MessageType) -> bool {
+        !matches!((self, mtype),
+                  (_, MessageType::ParticipantVoteReadOnly)
+                  | (LoggingMode::PresumedAbort, MessageType::CoordinatorAbort)
+                  | (LoggingMode::PresumedCommit, MessageType::CoordinatorCommit))
+    }
+
+    ///
+    /// acknowledges(decision)
+    /// Whether participants acknowledge a decision of this type (and so
+    /// whether the coordinator has to remember it until they do)
+    ///
+    pub fn acknowledges(&self, decision: MessageType) -> bool {
+        match self {
+            LoggingMode::Standard => true,
+            LoggingMode::PresumedAbort => decision == MessageType::CoordinatorCommit,
+            LoggingMode::PresumedCommit => decision == MessageType::CoordinatorAbort,
+        }
+    }
+
+    ///
+    /// presumed_outcome()
+    /// Decision reported for a transaction with no decision in the log
+    ///
+    pub fn presumed_outcome(&self) -> MessageType {
+        match self {
+            LoggingMode::PresumedCommit => MessageType::CoordinatorCommit,
+            _ => MessageType::CoordinatorAbort,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TPCOptions {
+    pub send_success_probability: f64,        // Probability that a message send succeeds
+    pub operation_success_probability: f64,   // Probability that an operation succeeds
+    pub num_clients: u32,                     // Number of concurrent clients issuing requests
+    pub num_requests: u32,                    // Number of requests issued per client
+    pub num_participants: u32,                // Number of participants in 2PC protocol (not including coordinator)
+    pub num_keys: u32,                        // Size of the key space clients read and write
+    pub lock_timeout: u64,                    // Milliseconds a participant waits for locks before voting abort
+    pub verbosity: usize,                     // Integer verbosity level. experiment with 0 (default) to 5 (fire-hose of output)
+    pub mode: String,                         // One of "run", "coordinator", "client", "particpant", "check", "simulate"
+    pub log_path: String,                     // Directory for client, participant, and coordinator logs
+    pub ipc_path: String,                     // Path to IPC socket for setting up communication with the coordinator
+    pub address: String,                      // TCP address the coordinator listens on / participants and clients connect to
+    pub num: u32,                             // Participant / Client number for naming the log files
+    pub recover: bool,                        // Recover from existing logs instead of starting with fresh ones
+    pub protocol: CommitProtocol,             // Commit protocol: "2pc" or "3pc"
+    pub logging: LoggingMode,                 // Logging variant: "standard", "presumed_abort" or "presumed_commit"
+    pub storage: StorageMode,                 // Participant storage: "locking" (strict 2PL) or "mvcc" (snapshot isolation)
+    pub partition_map: PartitionMap,          // Which participants own which keys (see partition.rs)
+    pub seed: u64,                            // Seed of a simulation (random unless given)
+    pub faults: FaultPlan,                    // Network faults injected per link (see faults.rs)
+    pub scenario: Scenario,                   // Scripted partitions and crashes (see scenario.rs)
+    pub crash_points: CrashPlan,              // Protocol points where processes crash (see crashpoint.rs)
+}
+
+#[allow(clippy::new_without_default)]
+impl TPCOptions {
+
+    ///
+    /// new()
+    /// Returns a new options structure containing CLI options / defaults
+    ///
+    pub fn new() -> TPCOptions {
+
+        // Default values for CLI options
+        let default_send_success_probability = "1.0";
+        let default_operation_success_probability = "1.0";
+        let default_num_participants = "3";
+        let default_num_clients = "3";
+        let default_num_requests = "15";
+        let default_num_keys = "16";
+        let default_lock_timeout = "0";
+        let default_verbosity = "0";
+        let default_mode = "run";
+        let default_log_path = "./logs/";
+        let default_ipc_path = "none";
+        let default_address = "none";
+        let default_num = "0";
+        let default_protocol = "2pc";
+        let default_logging = "standard";
+        let default_storage = "locking";
+        let default_partition_map = "all";
+        let default_faults = "none";
+        let default_scenario = "none";
+        let default_crash_points = "none";
+
+        // Set-Up clap
+        let matches = App::new("concurrency-2pc")
+            .version("0.1.0")
+            .author("Rust Student <student@cs.utexas.edu>")
+            .about("2PC exercise written in Rust")
+            .arg(Arg::with_name("send_success_probability")
+                    .short("S")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Probability participants successfully send messages"))
+            .arg(Arg::with_name("operation_success_probability")
+                    .short("s")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Probability participants successfully execute requests"))
+            .arg(Arg::with_name("num_clients")
+                    .short("c")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Number of clients making requests"))
+            .arg(Arg::with_name("num_participants")
+                    .short("p")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Number of participants in protocol"))
+            .arg(Arg::with_name("num_requests")
+                    .short("r")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Number of requests made per client"))
+            .arg(Arg::with_name("num_keys")
+                    .long("num_keys")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Number of distinct keys client requests read and write"))
+            .arg(Arg::with_name("lock_timeout")
+                    .long("lock_timeout")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Milliseconds a participant waits for locks before voting abort; 0 votes abort on the first conflict"))
+            .arg(Arg::with_name("verbosity")
+                    .short("v")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Output verbosity: 0->No Output, 5->Output Everything"))
+            .arg(Arg::with_name("log_path")
+                    .short("l")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Specifies path to directory where logs are stored"))
+            .arg(Arg::with_name("mode")
+                    .short("m")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Mode: \"run\" starts 2PC, \"coordinator\" starts a standalone coordinator listening on --address, \"client\" starts a client process, \"participant\" starts a participant process, \"check\" checks logs produced by previous run, \"simulate\" runs everything in this process on a virtual clock, reproducibly for a given --seed"))
+            .arg(Arg::with_name("ipc_path")
+                    .long("ipc_path")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Path for IPC socket for communication"))
+            .arg(Arg::with_name("address")
+                    .long("address")
+                    .required(false)
+                    .takes_value(true)
+                    .help("TCP address (e.g. 127.0.0.1:7000) a \"coordinator\" listens on, or that a client / participant connects to instead of using ipc_path"))
+            .arg(Arg::with_name("num")
+                    .long("num")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Participant / Client number for naming the log files. Ranges from 0 to num_clients - 1 or num_participants - 1"))
+            .arg(Arg::with_name("protocol")
+                    .long("protocol")
+                    .required(false)
+                    .takes_value(true)
+                    .possible_values(&["2pc", "3pc"])
+                    .help("Commit protocol: \"2pc\" runs two-phase commit, \"3pc\" runs non-blocking three-phase commit"))
+            .arg(Arg::with_name("logging")
+                    .long("logging")
+                    .required(false)
+                    .takes_value(true)
+                    .possible_values(&["standard", "presumed_abort", "presumed_commit"])
+                    .help("Logging variant: \"standard\", \"presumed_abort\" or \"presumed_commit\""))
+            .arg(Arg::with_name("storage")
+                    .long("storage")
+                    .required(false)
+                    .takes_value(true)
+                    .possible_values(&["locking", "mvcc"])
+                    .help("Participant storage: \"locking\" uses strict 2PL, \"mvcc\" uses snapshot isolation with coordinator-assigned commit timestamps"))
+            .arg(Arg::with_name("partition_map")
+                    .long("partition_map")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Key-to-participant map: \"all\" (every participant owns every key), \"hash\", \"range\", optionally with explicit \"key=participant\" entries, comma-separated"))
+            .arg(Arg::with_name("recover")
+                    .long("recover")
+                    .required(false)
+                    .takes_value(false)
+                    .help("Replay existing logs in log_path on startup instead of truncating them"))
+            .arg(Arg::with_name("faults")
+                    .long("faults")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Network faults, comma-separated \"[FROM>TO:]SETTING=VALUE\" entries: \"drop\", \"duplicate\" or \"reorder\" with a probability, \"delay\" with \"LO-HI\" or \"expMEAN\" milliseconds, e.g. \"delay=0-5,participant_*>coordinator:drop=0.1\""))
+            .arg(Arg::with_name("seed")
+                    .long("seed")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Seed for \"simulate\" mode; a run with the same seed and options is replayed exactly. Random if not given"))
+            .arg(Arg::with_name("scenario")
+                    .long("scenario")
+                    .required(false)
+                    .takes_value(true)
+                    .help("JSON file of partitions and crashes to script, executed by \"run\" (and \"simulate\", partitions only); see scenario.rs for the format"))
+            .arg(Arg::with_name("crash_points")
+                    .long("crash_points")
+                    .required(false)
+                    .takes_value(true)
+                    .help("Crash processes in the protocol, comma-separated \"[PROCESS:]POINT[=PROBABILITY]\" entries, POINT one of \"after_propose\", \"after_log_vote\", \"before_send_vote\" and \"after_log_decision\"; without a probability only the first time. \"run\" restarts crashed participants and refuses to crash the coordinator, e.g. \"participant_*:after_log_vote=0.01\""))
+            .get_matches();
+
+        // Parse CLI options and take default values if none given
+        let mode = matches.value_of("mode").unwrap_or(default_mode);
+        let operation_success_probability = matches.value_of("operation_success_probability").unwrap_or(default_operation_success_probability).parse::<f64>().unwrap();
+        let send_success_probability = matches.value_of("send_success_probability").unwrap_or(default_send_success_probability).parse::<f64>().unwrap();
+        let num_clients = matches.value_of("num_clients").unwrap_or(default_num_clients).parse::<u32>().unwrap();
+        let num_participants = matches.value_of("num_participants").unwrap_or(default_num_participants).parse::<u32>().unwrap();
+        let num_requests = matches.value_of("num_requests").unwrap_or(default_num_requests).parse::<u32>().unwrap();
+        let num_keys = matches.value_of("num_keys").unwrap_or(default_num_keys).parse::<u32>().unwrap();
+        let lock_timeout = matches.value_of("lock_timeout").unwrap_or(default_lock_timeout).parse::<u64>().unwrap();
+        let verbosity = matches.value_of("verbosity").unwrap_or(default_verbosity).parse::<usize>().unwrap();
+        let log_path = matches.value_of("log_path").unwrap_or(default_log_path);
+        let ipc_path = matches.value_of("ipc_path").unwrap_or(default_ipc_path);
+        let address = matches.value_of("address").unwrap_or(default_address);
+        let num = matches.value_of("num").unwrap_or(default_num).parse::<u32>().unwrap();
+        let recover = matches.is_present("recover");
+        let protocol = CommitProtocol::parse(matches.value_of("protocol").unwrap_or(default_protocol));
+        let logging = LoggingMode::parse(matches.value_of("logging").unwrap_or(default_logging));
+        let storage = StorageMode::parse(matches.value_of("storage").unwrap_or(default_storage));
+        let seed = match matches.value_of("seed") {
+            Some(seed) => seed.parse::<u64>().unwrap(),
+            None => clock::random(),
+        };
+        let faults = FaultPlan::parse(matches.value_of("faults").unwrap_or(default_faults));
+        let scenario = Scenario::load(matches.value_of("scenario").unwrap_or(default_scenario));
+        let crash_points = CrashPlan::parse(matches.value_of("crash_points").unwrap_or(default_crash_points));
+        let partition_map = PartitionMap::parse(matches.value_of("partition_map").unwrap_or(default_partition_map),
+                                                num_participants, num_keys);
+
+        // IPC path (or a TCP address) is necessary for client / participant to communicate with the coordinator
+        match mode {
+            "run" => {
+                if crash_points.crashes_coordinator() {
+                    panic!("run mode cannot restart the coordinator, limit crash points to participants (\"participant_*:POINT\")");
+                }
+            },
+            "coordinator" => {
+                if address == default_address {
+                    panic!("No address specified for coordinator mode");
+                }
+            },
+            "client" => {
+                if ipc_path == default_ipc_path && address == default_address {
+                    panic!("No ipc_path or address specified for client mode");
+                }
+            },
+            "participant" => {
+                if ipc_path == default_ipc_path && address == default_address {
+                    panic!("No ipc_path or address specified for participant mode");
+                }
+            },
+            "check" => {},
+            "simulate" => {
+                if recover {
+                    panic!("simulate mode always starts from fresh logs");
+                }
+                if scenario.has_crashes() || !crash_points.is_empty() {
+                    panic!("simulate mode cannot crash processes, use run mode");
+                }
+            },
+            _ => panic!("unknown execution mode requested!"),
+        }
+
+        TPCOptions {
+            send_success_probability,
+            operation_success_probability,
+            num_clients,
+            num_participants,
+            num_requests,
+            num_keys,
+            lock_timeout,
+            verbosity,
+            mode: mode.to_string(),
+            log_path: log_path.to_string(),
+            ipc_path: ipc_path.to_string(),
+            address: address.to_string(),
+            num,
+            recover,
+            protocol,
+            logging,
+            storage,
+            partition_map,
+            seed,
+            faults,
+            scenario,
+            crash_points,
+        }
+    }
+
+    ///
+    /// Convert this struct to a vector of CLI options
+    ///
+    /// HINT: This can be useful for passing arguments to children processes
+    ///       using the std::process::Command's args(..) function
+    pub fn as_vec(&self) -> Vec<String> {
+        let mut args = vec![
+            format!("-S{}", self.send_success_probability),
+            format!("-s{}", self.operation_success_probability),
+            format!("-c{}", self.num_clients),
+            format!("-r{}", self.num_requests),
+            format!("-p{}", self.num_participants),
+            format!("--num_keys={}", self.num_keys),
+            format!("--lock_timeout={}", self.lock_timeout),
+            format!("-v{}", self.verbosity),
+            format!("-m{}", self.mode),
+            format!("-l{}", self.log_path),
+            format!("--ipc_path={}", self.ipc_path),
+            format!("--address={}", self.address),
+            format!("--num={}", self.num),
+            format!("--protocol={}", self.protocol.as_str()),
+            format!("--logging={}", self.logging.as_str()),
+            format!("--storage={}", self.storage.as_str()),
+            format!("--partition_map={}", self.partition_map.as_str()),
+            format!("--seed={}", self.seed),
+            format!("--faults={}", self.faults.as_str()),
+            format!("--scenario={}", self.scenario.as_str()),
+            format!("--crash_points={}", self.crash_points.as_str()),
+        ];
+        if self.recover {
+            args.push("--recover".to_string());
+        }
+        args
+    }
+}
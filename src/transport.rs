@@ -0,0 +1,516 @@
+//!
+//! transport.rs
+//! Transport abstraction the coordinator, participants and clients talk
+//! through. A transport is one end of a duplex link to a single peer.
+//! Implementations exist for ipc-channel (run mode), TCP (see tcp.rs) and
+//! std::sync::mpsc channels (everything in one process).
+//!
+extern crate ipc_channel;
+
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use transport::ipc_channel::ipc;
+use transport::ipc_channel::ipc::IpcReceiver as Receiver;
+use transport::ipc_channel::ipc::IpcSender as Sender;
+
+use message::ProtocolMessage;
+
+///
+/// TryRecvError
+/// Why no message was received
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    Empty,                  // Nothing arrived (in time)
+    Disconnected,           // The peer is gone; nothing will ever arrive
+}
+
+///
+/// SendError
+/// The peer is gone, so the message could not be sent
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SendError;
+
+///
+/// Transport
+/// One end of a link to a peer
+///
+pub trait Transport: Send {
+
+    ///
+    /// send(msg)
+    /// Sends msg to the peer without waiting for it to be received
+    ///
+    fn send(&self, msg: ProtocolMessage) -> Result<(), SendError>;
+
+    ///
+    /// try_recv()
+    /// Returns the next message from the peer if one is waiting
+    ///
+    fn try_recv(&self) -> Result<ProtocolMessage, TryRecvError>;
+
+    ///
+    /// recv_timeout(timeout)
+    /// Waits up to timeout for the next message from the peer
+    ///
+    fn recv_timeout(&self, timeout: Duration) -> Result<ProtocolMessage, TryRecvError>;
+}
+
+///
+/// IpcTransport
+/// Link over a pair of ipc-channel channels
+///
+pub struct IpcTransport {
+    tx: Sender<ProtocolMessage>,
+    rx: Receiver<ProtocolMessage>,
+}
+
+impl IpcTransport {
+    pub fn new(tx: Sender<ProtocolMessage>, rx: Receiver<ProtocolMessage>) -> IpcTransport {
+        IpcTransport { tx, rx }
+    }
+}
+
+impl Transport for IpcTransport {
+    fn send(&self, msg: ProtocolMessage) -> Result<(), SendError> {
+        self.tx.send(msg).map_err(|_| SendError)
+    }
+
+    fn try_recv(&self) -> Result<ProtocolMessage, TryRecvError> {
+        match self.rx.try_recv() {
+            Ok(msg) => Ok(msg),
+            Err(ipc::TryRecvError::Empty) => Err(TryRecvError::Empty),
+            Err(ipc::TryRecvError::IpcError(_)) => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    // ipc-channel 0.14 has no timed receive, so poll
+    fn recv_timeout(&self, timeout: Duration) -> Result<ProtocolMessage, TryRecvError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.try_recv() {
+                Err(TryRecvError::Empty) if Instant::now() < deadline => thread::sleep(Duration::from_millis(1)),
+                result => return result,
+            }
+        }
+    }
+}
+
+///
+/// ChannelTransport
+/// Link over a pair of std::sync::mpsc channels, for peers in one process
+///
+pub struct ChannelTransport {
+    tx: mpsc::Sender<ProtocolMessage>,
+    rx: mpsc::Receiver<ProtocolMessage>,
+}
+
+impl ChannelTransport {
+
+    ///
+    /// pair()
+    /// Returns both ends of a new link
+    ///
+    pub fn pair() -> (ChannelTransport, ChannelTransport) {
+        let (a_tx, b_rx) = mpsc::channel();
+        let (b_tx, a_rx) = mpsc::channel();
+        (ChannelTransport { tx: a_tx, rx: a_rx }, ChannelTransport { tx: b_tx, rx: b_rx })
+    }
+}
+
+impl Transport for ChannelTransport {
+    fn send(&self, msg: ProtocolMessage) -> Result<(), SendError> {
+        self.tx.send(msg).map_err(|_| SendError)
+    }
+
+    fn try_recv(&self) -> Result<ProtocolMessage, TryRecvError> {
+        match self.rx.try_recv() {
+            Ok(msg) => Ok(msg),
+            Err(mpsc::TryRecvError::Empty) => Err(TryRecvError::Empty),
+            Err(mpsc::TryRecvError::Disconnected) => Err(TryRecvError::Disconnected),
+        }
+    }
+
+    fn recv_timeout(&self, timeout: Duration) -> Result<ProtocolMessage, TryRecvError> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(msg) => Ok(msg),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(TryRecvError::Empty),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(TryRecvError::Disconnected),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicBool;
+
+    use checker;
+    use client::Client;
+    use coordinator::Coordinator;
+    use crashpoint::CrashPlan;
+    use detector::FailureDetector;
+    use faults::FaultPlan;
+    use fixedhash::HashMap;
+    use message::MessageType;
+    use message::Operation;
+    use message::RequestStatus;
+    use oplog::OpLog;
+    use participant::Participant;
+    use partition::PartitionMap;
+    use scenario::Scenario;
+    use tpcoptions::CommitProtocol;
+    use tpcoptions::LoggingMode;
+    use tpcoptions::StorageMode;
+    use tpcoptions::TPCOptions;
+
+    fn options(log_path: &str) -> TPCOptions {
+        TPCOptions {
+            send_success_probability: 1.0,
+            operation_success_probability: 0.9,
+            num_clients: 2,
+            num_requests: 10,
+            num_participants: 3,
+            num_keys: 16,
+            lock_timeout: 0,
+            verbosity: 0,
+            mode: "run".to_string(),
+            log_path: log_path.to_string(),
+            ipc_path: "none".to_string(),
+            address: "none".to_string(),
+            num: 0,
+            recover: false,
+            protocol: CommitProtocol::TwoPhase,
+            logging: LoggingMode::Standard,
+            storage: StorageMode::Locking,
+            partition_map: PartitionMap::parse("hash", 3, 16),
+            seed: 0,
+            faults: FaultPlan::parse("none"),
+            scenario: Scenario::load("none"),
+            crash_points: CrashPlan::parse("none"),
+        }
+    }
+
+    #[test]
+    fn channel_pair_is_duplex() {
+        let (a, b) = ChannelTransport::pair();
+        assert_eq!(a.try_recv().err(), Some(TryRecvError::Empty));
+        let msg = ProtocolMessage::generate(MessageType::ClientRequest, "t".to_string(), "a".to_string(), 1);
+        a.send(msg.clone()).unwrap();
+        assert_eq!(b.recv_timeout(Duration::from_millis(100)).unwrap().txid, "t");
+        b.send(msg).unwrap();
+        assert_eq!(a.try_recv().unwrap().senderid, "a");
+        drop(b);
+        assert_eq!(a.try_recv().err(), Some(TryRecvError::Disconnected));
+    }
+
+    /// Makes a fresh log directory for a test, named after it
+    fn log_dir(name: &str) -> String {
+        let log_path = env::temp_dir().join(format!("tpc-{}-{}", name, process::id()));
+        fs::remove_dir_all(&log_path).unwrap_or(());
+        fs::create_dir_all(&log_path).unwrap();
+        log_path.to_str().unwrap().to_string()
+    }
+
+    /// Joins a participant per opts.num_participants to the coordinator over
+    /// channels, recovering them from their logs if asked to
+    fn join_participants(coordinator: &mut Coordinator<ChannelTransport>, opts: &TPCOptions, log_path: &str,
+                         running: &Arc<AtomicBool>, recover: bool) -> Vec<Participant<ChannelTransport>> {
+        let mut participants = Vec::new();
+        for i in 0..opts.num_participants {
+            let name = format!("participant_{}", i);
+            let (coordinator_end, participant_end) = ChannelTransport::pair();
+            coordinator.participant_join(&name, coordinator_end);
+            let path = format!("{}//{}.log", log_path, name);
+            participants.push(if recover {
+                Participant::from_log(name, path, running.clone(), opts, participant_end)
+            } else {
+                Participant::new(name, path, running.clone(), opts, participant_end)
+            });
+        }
+        participants
+    }
+
+    /// Shuts the coordinator down and steps the participants until they heard
+    fn shut_down(coordinator: &mut Coordinator<ChannelTransport>, participants: &mut [Participant<ChannelTransport>]) {
+        coordinator.shutdown();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while participants.iter().any(|p| !p.finished()) && Instant::now() < deadline {
+            for participant in participants.iter_mut().filter(|p| !p.finished()) {
+                participant.step();
+            }
+        }
+        assert!(participants.iter().all(|p| p.finished()), "participants did not hear the coordinator exit");
+    }
+
+    /// Runs the coordinator, participants and clients over channels until
+    /// every client finished its requests
+    fn run_roles(opts: &TPCOptions, log_path: &str, running: &Arc<AtomicBool>) {
+        let mut coordinator: Coordinator<ChannelTransport> =
+            Coordinator::new(format!("{}//coordinator.log", log_path), running, opts);
+        let mut participants = join_participants(&mut coordinator, opts, log_path, running, false);
+        let mut clients = Vec::new();
+        for i in 0..opts.num_clients {
+            let name = format!("client_{}", i);
+            let (coordinator_end, client_end) = ChannelTransport::pair();
+            coordinator.client_join(&name, coordinator_end);
+            clients.push(Client::new(name, opts.num_keys, running.clone(), client_end));
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut clients_done = false;
+        while !clients_done {
+            assert!(Instant::now() < deadline, "clients did not finish over channels");
+            coordinator.step();
+            for participant in participants.iter_mut() {
+                participant.step();
+            }
+            clients_done = true;
+            for client in clients.iter_mut() {
+                clients_done &= client.step(opts.num_requests);
+            }
+        }
+        shut_down(&mut coordinator, &mut participants);
+    }
+
+    /// The decision the coordinator logged for txid, if any
+    fn logged_decision(log_path: &str, txid: &str) -> Option<MessageType> {
+        OpLog::load(format!("{}//coordinator.log", log_path)).entries().iter()
+            .filter(|m| m.txid == txid)
+            .map(|m| m.mtype)
+            .find(|mtype| matches!(mtype, MessageType::CoordinatorCommit | MessageType::CoordinatorAbort))
+    }
+
+    #[test]
+    fn roles_run_over_channels() {
+        let log_path = log_dir("channel");
+        let opts = options(&log_path);
+        let running = Arc::new(AtomicBool::new(true));
+        run_roles(&opts, &log_path, &running);
+
+        // Every request was decided, and the participants agree
+        let decided = OpLog::load(format!("{}//coordinator.log", log_path)).entries().iter()
+            .filter(|m| matches!(m.mtype, MessageType::CoordinatorCommit | MessageType::CoordinatorAbort))
+            .count();
+        assert_eq!(decided as u32, opts.num_clients * opts.num_requests);
+        checker::check_last_run(opts.num_clients, opts.num_requests, opts.num_participants, &log_path,
+                                opts.protocol, &opts.partition_map);
+        fs::remove_dir_all(&log_path).unwrap_or(());
+    }
+
+    #[test]
+    fn recovered_coordinator_answers_clients() {
+        let log_path = log_dir("recovered");
+        let opts = options(&log_path);
+        let running = Arc::new(AtomicBool::new(true));
+        run_roles(&opts, &log_path, &running);
+
+        // A client of the next run numbers its requests from 1 again
+        let old_txid = "client_0_op_1".to_string();
+        let old_decision = logged_decision(&log_path, &old_txid).expect("first request was not decided");
+        let mut coordinator: Coordinator<ChannelTransport> =
+            Coordinator::from_log(format!("{}//coordinator.log", log_path), &running, &opts);
+        let mut participants = join_participants(&mut coordinator, &opts, &log_path, &running, true);
+        let (coordinator_end, client_end) = ChannelTransport::pair();
+        coordinator.client_join("client_0", coordinator_end);
+
+        let new_txid = format!("client_0_op_{}", opts.num_requests + 1);
+        for (opid, txid) in vec![old_txid.clone(), new_txid.clone()].into_iter().enumerate() {
+            let mut request = ProtocolMessage::generate(MessageType::ClientRequest, txid, "client_0".to_string(), opid as u32 + 1);
+            request.ops = vec![Operation::Put("key_1".to_string(), "recovered".to_string())];
+            client_end.send(request).unwrap();
+        }
+
+        let mut results = HashMap::default();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while results.len() < 2 {
+            assert!(Instant::now() < deadline, "recovered coordinator did not answer the client");
+            coordinator.step();
+            for participant in participants.iter_mut() {
+                participant.step();
+            }
+            while let Ok(msg) = client_end.try_recv() {
+                results.insert(msg.txid.clone(), msg.mtype);
+            }
+        }
+        shut_down(&mut coordinator, &mut participants);
+
+        // The old request gets its recorded outcome, the new one is decided anew
+        let expected = match old_decision {
+            MessageType::CoordinatorCommit => MessageType::ClientResultCommit,
+            _ => MessageType::ClientResultAbort,
+        };
+        assert_eq!(results[&old_txid], expected);
+        let new_result = match logged_decision(&log_path, &new_txid).expect("new request was not decided") {
+            MessageType::CoordinatorCommit => MessageType::ClientResultCommit,
+            _ => MessageType::ClientResultAbort,
+        };
+        assert_eq!(results[&new_txid], new_result);
+        checker::check_last_run(opts.num_clients, opts.num_requests, opts.num_participants, &log_path,
+                                opts.protocol, &opts.partition_map);
+        fs::remove_dir_all(&log_path).unwrap_or(());
+    }
+
+    /// Writes a participant log holding a vote to commit txid, without the
+    /// decision: what a participant that crashed right after voting leaves
+    fn in_doubt_log(log_path: &str, name: &str, txid: &str) -> String {
+        let path = format!("{}//{}.log", log_path, name);
+        let mut log = OpLog::new(path.clone());
+        log.append_with_ops(MessageType::ParticipantVoteCommit, txid.to_string(), name.to_string(), 1,
+                            vec![Operation::Put("key_0".to_string(), txid.to_string())], true);
+        path
+    }
+
+    #[test]
+    fn recovering_participant_keeps_sending_heartbeats() {
+        let log_path = log_dir("heartbeat");
+        let opts = options(&log_path);
+        let running = Arc::new(AtomicBool::new(true));
+        let path = in_doubt_log(&log_path, "participant_0", "client_0_op_1");
+        let (coordinator_end, participant_end) = ChannelTransport::pair();
+        let mut participant = Participant::from_log("participant_0".to_string(), path, running.clone(), &opts, participant_end);
+        let recovering = thread::spawn(move || participant.protocol());
+
+        // Leave the transaction in doubt for well past the time a silent
+        // participant is declared down
+        let mut detector = FailureDetector::new();
+        detector.heard("participant_0");
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(1000) {
+            if let Ok(msg) = coordinator_end.recv_timeout(Duration::from_millis(5)) {
+                detector.heard(&msg.senderid);
+            }
+            detector.check();
+            assert!(!detector.is_down("participant_0"), "recovering participant was declared down");
+        }
+
+        let mut commit = ProtocolMessage::generate(MessageType::CoordinatorCommit, "client_0_op_1".to_string(),
+                                                   "coordinator".to_string(), 1);
+        commit.timestamp = 1;
+        coordinator_end.send(commit).unwrap();
+        coordinator_end.send(ProtocolMessage::generate(MessageType::CoordinatorExit, String::new(),
+                                                       "coordinator".to_string(), 0)).unwrap();
+        recovering.join().unwrap();
+        fs::remove_dir_all(&log_path).unwrap_or(());
+    }
+
+    #[test]
+    fn recovered_participant_learns_in_doubt_outcomes() {
+        let log_path = log_dir("in-doubt");
+        let opts = options(&log_path);
+        let running = Arc::new(AtomicBool::new(true));
+
+        // The coordinator committed and ended client_0_op_1 (so it will not
+        // re-send the decision) and never heard of client_0_op_2
+        {
+            let mut log = OpLog::new(format!("{}//coordinator.log", log_path));
+            log.append_with_ops(MessageType::CoordinatorPropose, "client_0_op_1".to_string(), "coordinator".to_string(), 1,
+                                vec![Operation::Put("key_0".to_string(), "client_0_op_1".to_string())], true);
+            let mut commit = ProtocolMessage::generate(MessageType::CoordinatorCommit, "client_0_op_1".to_string(),
+                                                       "coordinator".to_string(), 1);
+            commit.timestamp = 7;
+            log.append_message(commit, true);
+            log.append(MessageType::CoordinatorEnd, "client_0_op_1".to_string(), "coordinator".to_string(), 1, true);
+        }
+        let path = in_doubt_log(&log_path, "participant_0", "client_0_op_1");
+        OpLog::from_file(path.clone()).append_with_ops(MessageType::ParticipantVoteCommit, "client_0_op_2".to_string(),
+            "participant_0".to_string(), 2, vec![Operation::Put("key_1".to_string(), "client_0_op_2".to_string())], true);
+
+        let mut coordinator: Coordinator<ChannelTransport> =
+            Coordinator::from_log(format!("{}//coordinator.log", log_path), &running, &opts);
+        let (coordinator_end, participant_end) = ChannelTransport::pair();
+        coordinator.participant_join("participant_0", coordinator_end);
+        let mut participant = Participant::from_log("participant_0".to_string(), path.clone(), running.clone(), &opts, participant_end);
+        let recovering = thread::spawn(move || participant.protocol());
+
+        // The participant asks about both and logs what it learns
+        let decisions = || -> Vec<(String, MessageType, u64)> {
+            OpLog::load(path.clone()).entries().into_iter()
+                .filter(|m| matches!(m.mtype, MessageType::CoordinatorCommit | MessageType::CoordinatorAbort))
+                .map(|m| (m.txid, m.mtype, m.timestamp))
+                .collect()
+        };
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while decisions().len() < 2 {
+            assert!(Instant::now() < deadline, "participant did not resolve its in-doubt transactions");
+            coordinator.step();
+            thread::sleep(Duration::from_millis(1));
+        }
+        coordinator.shutdown();
+        recovering.join().unwrap();
+
+        let mut decisions = decisions();
+        decisions.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(decisions, vec![
+            ("client_0_op_1".to_string(), MessageType::CoordinatorCommit, 7),
+            ("client_0_op_2".to_string(), MessageType::CoordinatorAbort, 0),
+        ]);
+        fs::remove_dir_all(&log_path).unwrap_or(());
+    }
+
+    #[test]
+    fn one_phase_outcome_is_unknown_while_its_participant_is_down() {
+        let log_path = log_dir("one-phase-down");
+        let mut opts = options(&log_path);
+        opts.num_participants = 1;
+        opts.partition_map = PartitionMap::parse("hash", 1, opts.num_keys);
+        let running = Arc::new(AtomicBool::new(true));
+        let mut coordinator: Coordinator<ChannelTransport> =
+            Coordinator::new(format!("{}//coordinator.log", log_path), &running, &opts);
+        let (coordinator_end, participant_end) = ChannelTransport::pair();
+        coordinator.participant_join("participant_0", coordinator_end);
+        let (coordinator_end, client_end) = ChannelTransport::pair();
+        coordinator.client_join("client_0", coordinator_end);
+
+        let mut request = ProtocolMessage::generate(MessageType::ClientRequest, "client_0_op_1".to_string(),
+                                                    "client_0".to_string(), 1);
+        request.ops = vec![Operation::Put("key_0".to_string(), "client_0_op_1".to_string())];
+        client_end.send(request).unwrap();
+
+        // The participant takes the request and goes silent
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let result = loop {
+            assert!(Instant::now() < deadline, "client got no result while the participant was down");
+            coordinator.step();
+            if let Ok(msg) = client_end.try_recv() {
+                break msg.mtype;
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(result, MessageType::ClientResultUnknown);
+        let sent: Vec<MessageType> = std::iter::from_fn(|| participant_end.try_recv().ok()).map(|m| m.mtype).collect();
+        assert!(!sent.is_empty() && sent.iter().all(|m| *m == MessageType::CoordinatorOnePhaseCommit));
+
+        // Nothing is re-sent while it is down
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(300) {
+            coordinator.step();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(participant_end.try_recv().err(), Some(TryRecvError::Empty));
+
+        // Back up, it reports having committed, and the outcome is logged
+        let mut reply = ProtocolMessage::generate(MessageType::ParticipantOnePhaseReply, "client_0_op_1".to_string(),
+                                                  "participant_0".to_string(), 1);
+        reply.status = RequestStatus::Committed;
+        participant_end.send(reply).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while logged_decision(&log_path, "client_0_op_1").is_none() {
+            assert!(Instant::now() < deadline, "one-phase outcome was not logged");
+            coordinator.step();
+        }
+        assert_eq!(logged_decision(&log_path, "client_0_op_1"), Some(MessageType::CoordinatorCommit));
+        // The client already gave up on it, so it hears nothing more
+        assert_eq!(client_end.try_recv().err(), Some(TryRecvError::Empty));
+        fs::remove_dir_all(&log_path).unwrap_or(());
+    }
+}
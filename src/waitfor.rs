@@ -0,0 +1,168 @@
+//!
+//! waitfor.rs
+//! Global wait-for graph the coordinator assembles from the lock waits
+//! participants report. A cycle in it is a (possibly distributed) deadlock
+//! that no participant can see on its own.
+//!
+use fixedhash::HashMap;
+use fixedhash::HashSet;
+
+///
+/// WaitForGraph
+/// For each participant, the transactions waiting there and the ones they
+/// wait for
+///
+#[derive(Debug, Default)]
+pub struct WaitForGraph {
+    waits: HashMap<String, HashMap<String, Vec<String>>>,
+}
+
+impl WaitForGraph {
+
+    ///
+    /// new()
+    /// Returns an empty graph
+    ///
+    pub fn new() -> WaitForGraph {
+        WaitForGraph {
+            waits: HashMap::default(),
+        }
+    }
+
+    ///
+    /// record(participant, waiter, holders)
+    /// Replaces what waiter waits for at participant; no holders clears it
+    ///
+    pub fn record(&mut self, participant: &str, waiter: &str, holders: Vec<String>) {
+        let site = self.waits.entry(participant.to_string()).or_default();
+        if holders.is_empty() {
+            site.remove(waiter);
+        } else {
+            site.insert(waiter.to_string(), holders);
+        }
+    }
+
+    ///
+    /// remove(txid)
+    /// Forgets a finished transaction, both as a waiter and as a holder
+    ///
+    pub fn remove(&mut self, txid: &str) {
+        for site in self.waits.values_mut() {
+            site.remove(txid);
+            for holders in site.values_mut() {
+                holders.retain(|h| h != txid);
+            }
+            site.retain(|_, holders| !holders.is_empty());
+        }
+    }
+
+    ///
+    /// find_cycle()
+    /// Returns the transactions on some cycle of the union of all
+    /// participants' waits, if there is one
+    ///
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        let mut edges: HashMap<&String, HashSet<&String>> = HashMap::default();
+        for site in self.waits.values() {
+            for (waiter, holders) in site.iter() {
+                edges.entry(waiter).or_default().extend(holders.iter());
+            }
+        }
+
+        let mut done: HashSet<&String> = HashSet::default();
+        let mut starts: Vec<&String> = edges.keys().cloned().collect();
+        starts.sort();
+        for start in starts {
+            if done.contains(start) {
+                continue;
+            }
+            // Iterative DFS; path holds the current chain of waiters
+            let mut path: Vec<&String> = vec![start];
+            let mut pending: Vec<Vec<&String>> = vec![WaitForGraph::successors(&edges, start)];
+            while let Some(next) = pending.last_mut() {
+                match next.pop() {
+                    Some(txid) => {
+                        if let Some(pos) = path.iter().position(|t| *t == txid) {
+                            return Some(path[pos..].iter().map(|t| (*t).clone()).collect());
+                        }
+                        if !done.contains(txid) {
+                            path.push(txid);
+                            pending.push(WaitForGraph::successors(&edges, txid));
+                        }
+                    },
+                    None => {
+                        pending.pop();
+                        if let Some(txid) = path.pop() {
+                            done.insert(txid);
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn successors<'a>(edges: &HashMap<&'a String, HashSet<&'a String>>, txid: &String) -> Vec<&'a String> {
+        match edges.get(txid) {
+            Some(holders) => holders.iter().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holders(txids: &[&str]) -> Vec<String> {
+        txids.iter().map(|t| t.to_string()).collect()
+    }
+
+    fn sorted(cycle: Option<Vec<String>>) -> Option<Vec<String>> {
+        cycle.map(|mut c| { c.sort(); c })
+    }
+
+    #[test]
+    fn chain_is_not_a_cycle() {
+        let mut graph = WaitForGraph::new();
+        graph.record("p0", "t1", holders(&["t2"]));
+        graph.record("p0", "t2", holders(&["t3"]));
+        assert_eq!(graph.find_cycle(), None);
+    }
+
+    #[test]
+    fn finds_local_cycle() {
+        let mut graph = WaitForGraph::new();
+        graph.record("p0", "t1", holders(&["t2"]));
+        graph.record("p0", "t2", holders(&["t1"]));
+        graph.record("p0", "t3", holders(&["t1"]));
+        assert_eq!(sorted(graph.find_cycle()), Some(holders(&["t1", "t2"])));
+    }
+
+    #[test]
+    fn finds_cycle_across_participants() {
+        let mut graph = WaitForGraph::new();
+        graph.record("p0", "t1", holders(&["t2"]));
+        graph.record("p1", "t2", holders(&["t3"]));
+        graph.record("p2", "t3", holders(&["t1"]));
+        assert_eq!(sorted(graph.find_cycle()), Some(holders(&["t1", "t2", "t3"])));
+    }
+
+    #[test]
+    fn cleared_wait_breaks_cycle() {
+        let mut graph = WaitForGraph::new();
+        graph.record("p0", "t1", holders(&["t2"]));
+        graph.record("p1", "t2", holders(&["t1"]));
+        graph.record("p1", "t2", Vec::new());
+        assert_eq!(graph.find_cycle(), None);
+    }
+
+    #[test]
+    fn removed_transaction_breaks_cycle() {
+        let mut graph = WaitForGraph::new();
+        graph.record("p0", "t1", holders(&["t2"]));
+        graph.record("p1", "t2", holders(&["t1", "t3"]));
+        graph.remove("t1");
+        assert_eq!(graph.find_cycle(), None);
+    }
+}